@@ -15,12 +15,19 @@ export default function DeadDropPage() {
   const [loading, setLoading] = useState(true);
 
   const [decodePaintingId, setDecodePaintingId] = useState("");
+  const [decodePassphrase, setDecodePassphrase] = useState("");
   const [decodedMessage, setDecodedMessage] = useState<string | null>(null);
+  const [decodedSigner, setDecodedSigner] = useState<{
+    signer: string | null;
+    verification: string;
+  } | null>(null);
   const [decoding, setDecoding] = useState(false);
   const [decodeError, setDecodeError] = useState("");
 
   const [encodePaintingId, setEncodePaintingId] = useState("");
   const [encodeMessage, setEncodeMessage] = useState("");
+  const [encodePassphrase, setEncodePassphrase] = useState("");
+  const [encodeRecipient, setEncodeRecipient] = useState("");
   const [encoding, setEncoding] = useState(false);
   const [encodeSuccess, setEncodeSuccess] = useState(false);
   const [encodeError, setEncodeError] = useState("");
@@ -45,9 +52,14 @@ export default function DeadDropPage() {
     setDecodeError("");
     try {
       const res = await api.post<DecodeResponse>(
-        `/api/steg/decode/${decodePaintingId}`
+        `/api/steg/decode/${decodePaintingId}`,
+        decodePassphrase ? { passphrase: decodePassphrase } : undefined
       );
       setDecodedMessage(res.message);
+      setDecodedSigner({
+        signer: res.signer,
+        verification: res.verification,
+      });
       setMessages((prev) => [res.steg_message, ...prev]);
       setPaintings((prev) =>
         prev.map((p) =>
@@ -72,6 +84,8 @@ export default function DeadDropPage() {
       const res = await api.post<EncodeResponse>("/api/steg/encode", {
         painting_id: encodePaintingId,
         message: encodeMessage,
+        passphrase: encodePassphrase || undefined,
+        recipient: encodeRecipient || undefined,
       });
       setEncodeSuccess(true);
       setEncodeMessage("");
@@ -176,6 +190,14 @@ export default function DeadDropPage() {
             </div>
           )}
 
+          <input
+            type="password"
+            value={decodePassphrase}
+            onChange={(e) => setDecodePassphrase(e.target.value)}
+            placeholder="Passphrase (if encrypted)"
+            className="w-full bg-white/5 border border-white/10 rounded-lg px-4 py-2.5 text-sm text-gray-300 font-mono outline-none focus:border-blue-400/50 placeholder:text-gray-600 transition-colors mb-4"
+          />
+
           <button
             onClick={handleDecode}
             disabled={!decodePaintingId || decoding}
@@ -198,6 +220,17 @@ export default function DeadDropPage() {
               <p className="text-green-300 font-mono text-sm leading-relaxed whitespace-pre-wrap break-words">
                 {decodedMessage}
               </p>
+              {decodedSigner && (
+                <p
+                  className={`mt-3 text-[10px] font-bold uppercase tracking-wider ${
+                    decodedSigner.signer ? "text-green-400" : "text-yellow-400"
+                  }`}
+                >
+                  {decodedSigner.signer
+                    ? `Signed by ${decodedSigner.signer}`
+                    : decodedSigner.verification}
+                </p>
+              )}
             </div>
           )}
         </div>
@@ -252,6 +285,24 @@ export default function DeadDropPage() {
             className="w-full bg-white/5 border border-white/10 rounded-lg px-4 py-3 text-sm text-gray-300 font-mono outline-none focus:border-purple-400/50 placeholder:text-gray-600 transition-colors mb-4"
           />
 
+          <input
+            type="password"
+            value={encodePassphrase}
+            onChange={(e) => setEncodePassphrase(e.target.value)}
+            placeholder="Passphrase (optional, encrypts payload)"
+            disabled={!!encodeRecipient}
+            className="w-full bg-white/5 border border-white/10 rounded-lg px-4 py-2.5 text-sm text-gray-300 font-mono outline-none focus:border-purple-400/50 placeholder:text-gray-600 transition-colors mb-4 disabled:opacity-30"
+          />
+
+          <input
+            type="text"
+            value={encodeRecipient}
+            onChange={(e) => setEncodeRecipient(e.target.value)}
+            placeholder="Recipient username (optional, seals to their public key)"
+            disabled={!!encodePassphrase}
+            className="w-full bg-white/5 border border-white/10 rounded-lg px-4 py-2.5 text-sm text-gray-300 font-mono outline-none focus:border-purple-400/50 placeholder:text-gray-600 transition-colors mb-4 disabled:opacity-30"
+          />
+
           <button
             onClick={handleEncode}
             disabled={!encodePaintingId || !encodeMessage || encoding}
@@ -321,7 +372,13 @@ export default function DeadDropPage() {
                       {m.painting_id.slice(0, 8)}
                     </td>
                     <td className="px-6 py-3 max-w-xs truncate text-surface-700">
-                      {m.message_text}
+                      {m.expired ? (
+                        <span className="text-xs italic text-gray-400">
+                          Expired {m.expires_at}
+                        </span>
+                      ) : (
+                        m.message_text
+                      )}
                     </td>
                     <td className="px-6 py-3 text-xs text-gray-400 tabular-nums">
                       {new Date(m.created_at).toLocaleString("en-US", {
@@ -85,6 +85,8 @@ export interface StegMessage {
   decoded_by: string | null;
   encoded_by: string | null;
   created_at: string;
+  expires_at: string | null;
+  expired: boolean;
 }
 
 export interface AuthResponse {
@@ -108,6 +110,12 @@ export interface AdminStats {
 
 export interface DecodeResponse {
   message: string;
+  format_version: number;
+  errors_corrected: number;
+  filename: string | null;
+  mime_type: string | null;
+  signer: string | null;
+  verification: string;
   painting_id: string;
   steg_message: StegMessage;
 }
@@ -117,3 +125,11 @@ export interface EncodeResponse {
   painting_id: string;
   steg_message: StegMessage;
 }
+
+export interface UserPublicKey {
+  user_id: string;
+  username: string;
+  public_key: string;
+  created_at: string;
+  updated_at: string;
+}
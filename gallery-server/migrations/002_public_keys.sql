@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS user_public_keys (
+    user_id TEXT PRIMARY KEY NOT NULL REFERENCES users(id),
+    public_key TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
@@ -0,0 +1,2 @@
+ALTER TABLE steg_messages ADD COLUMN expires_at TEXT;
+ALTER TABLE steg_messages ADD COLUMN expired INTEGER NOT NULL DEFAULT 0;
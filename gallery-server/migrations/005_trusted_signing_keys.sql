@@ -0,0 +1 @@
+ALTER TABLE user_signing_keys ADD COLUMN trusted INTEGER NOT NULL DEFAULT 0;
@@ -12,8 +12,42 @@ pub async fn init_pool(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
     Ok(pool)
 }
 
+/// Apply each migration once, recording it in `schema_migrations`. The
+/// first three are idempotent and predate the table, so databases created
+/// before it simply run them again.
 async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    let migration_sql = include_str!("../migrations/001_init.sql");
-    sqlx::raw_sql(migration_sql).execute(pool).await?;
+    let migrations = [
+        include_str!("../migrations/001_init.sql"),
+        include_str!("../migrations/002_public_keys.sql"),
+        include_str!("../migrations/003_signing_keys.sql"),
+        include_str!("../migrations/004_message_expiry.sql"),
+        include_str!("../migrations/005_trusted_signing_keys.sql"),
+    ];
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT (datetime('now'))
+        )",
+    )
+    .execute(pool)
+    .await?;
+    for (index, migration_sql) in migrations.into_iter().enumerate() {
+        let version = index as i64 + 1;
+        let applied = sqlx::query("SELECT 1 FROM schema_migrations WHERE version = ?")
+            .bind(version)
+            .fetch_optional(pool)
+            .await?
+            .is_some();
+        if applied {
+            continue;
+        }
+        let mut tx = pool.begin().await?;
+        sqlx::raw_sql(migration_sql).execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO schema_migrations (version) VALUES (?)")
+            .bind(version)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
     Ok(())
 }
@@ -0,0 +1,332 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use steg_core::{RecipientKey, SignerKey, StegError, TrustedSigners};
+
+use crate::auth::middleware::{AdminUser, AuthUser};
+use crate::models::UserPublicKey;
+use crate::AppState;
+
+/// The two kinds of public key a user can register.
+#[derive(Clone, Copy)]
+enum KeyKind {
+    /// X25519 key messages are sealed to.
+    Recipient,
+    /// Ed25519 key the user's signed messages are checked against.
+    Signing,
+}
+
+impl KeyKind {
+    fn table(self) -> &'static str {
+        match self {
+            KeyKind::Recipient => "user_public_keys",
+            KeyKind::Signing => "user_signing_keys",
+        }
+    }
+
+    /// Validate a key in text form and return it normalised.
+    fn parse(self, text: &str) -> Result<String, StegError> {
+        match self {
+            KeyKind::Recipient => text.parse::<RecipientKey>().map(|k| k.to_string()),
+            KeyKind::Signing => text.parse::<SignerKey>().map(|k| k.to_string()),
+        }
+    }
+
+    /// Columns a replaced key resets, besides the key itself: a new signing
+    /// key needs approving again.
+    fn reset_on_replace(self) -> &'static str {
+        match self {
+            KeyKind::Recipient => "",
+            KeyKind::Signing => ", trusted = 0",
+        }
+    }
+
+    fn select(self) -> String {
+        let trusted = match self {
+            KeyKind::Recipient => "NULL",
+            KeyKind::Signing => "k.trusted",
+        };
+        format!(
+            "SELECT k.user_id, u.username, k.public_key, {} AS trusted, k.created_at, k.updated_at \
+             FROM {} k JOIN users u ON u.id = k.user_id",
+            trusted,
+            self.table()
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetPublicKeyRequest {
+    /// Public key in text form, as printed by `steg-keys generate`.
+    pub public_key: String,
+}
+
+/// Store or replace the caller's public key for sealed messages. Only the
+/// public half is ever sent; messages sealed to it can be read by the key
+/// holder alone.
+pub async fn set_public_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(body): Json<SetPublicKeyRequest>,
+) -> Result<Json<UserPublicKey>, (StatusCode, String)> {
+    set_key(&state.db, KeyKind::Recipient, &auth.user_id, &body.public_key).await
+}
+
+/// Store or replace the public key the caller signs messages with. The key
+/// is not trusted until an admin approves it with `set_signing_key_trust`.
+pub async fn set_signing_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(body): Json<SetPublicKeyRequest>,
+) -> Result<Json<UserPublicKey>, (StatusCode, String)> {
+    set_key(&state.db, KeyKind::Signing, &auth.user_id, &body.public_key).await
+}
+
+#[derive(Deserialize)]
+pub struct SetTrustRequest {
+    pub trusted: bool,
+}
+
+/// Approve or revoke a user's signing key as a trusted sender.
+pub async fn set_signing_key_trust(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(user_id): Path<String>,
+    Json(body): Json<SetTrustRequest>,
+) -> Result<Json<UserPublicKey>, (StatusCode, String)> {
+    let updated = sqlx::query(
+        "UPDATE user_signing_keys SET trusted = ?, updated_at = datetime('now') WHERE user_id = ?",
+    )
+    .bind(body.trusted)
+    .bind(&user_id)
+    .execute(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if updated.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, "User has no signing key".into()));
+    }
+
+    let stored = sqlx::query_as::<_, UserPublicKey>(&format!(
+        "{} WHERE k.user_id = ?",
+        KeyKind::Signing.select()
+    ))
+    .bind(&user_id)
+    .fetch_one(&state.db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(stored))
+}
+
+pub async fn delete_public_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<StatusCode, (StatusCode, String)> {
+    delete_key(&state.db, KeyKind::Recipient, &auth.user_id).await
+}
+
+pub async fn delete_signing_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<StatusCode, (StatusCode, String)> {
+    delete_key(&state.db, KeyKind::Signing, &auth.user_id).await
+}
+
+pub async fn get_public_key(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<UserPublicKey>, (StatusCode, String)> {
+    Ok(Json(find_key(&state.db, KeyKind::Recipient, &username).await?))
+}
+
+pub async fn get_signing_key(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<UserPublicKey>, (StatusCode, String)> {
+    Ok(Json(find_key(&state.db, KeyKind::Signing, &username).await?))
+}
+
+/// Look up the stored public key messages to `username` are sealed to.
+pub async fn find_public_key(
+    db: &SqlitePool,
+    username: &str,
+) -> Result<UserPublicKey, (StatusCode, String)> {
+    find_key(db, KeyKind::Recipient, username).await
+}
+
+/// Every signing key an admin has approved, trusted under its owner's
+/// username.
+pub async fn trusted_signers(db: &SqlitePool) -> Result<TrustedSigners, (StatusCode, String)> {
+    let keys = sqlx::query_as::<_, UserPublicKey>(&format!(
+        "{} WHERE k.trusted = 1",
+        KeyKind::Signing.select()
+    ))
+        .fetch_all(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let mut trusted = TrustedSigners::new();
+    for key in keys {
+        let signer = key
+            .public_key
+            .parse()
+            .map_err(|e: StegError| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        trusted.add(key.username, signer);
+    }
+    Ok(trusted)
+}
+
+async fn set_key(
+    db: &SqlitePool,
+    kind: KeyKind,
+    user_id: &str,
+    text: &str,
+) -> Result<Json<UserPublicKey>, (StatusCode, String)> {
+    let key = kind
+        .parse(text)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    sqlx::query(&format!(
+        "INSERT INTO {} (user_id, public_key) VALUES (?, ?)
+         ON CONFLICT(user_id) DO UPDATE SET public_key = excluded.public_key{}, updated_at = datetime('now')",
+        kind.table(),
+        kind.reset_on_replace()
+    ))
+    .bind(user_id)
+    .bind(key)
+    .execute(db)
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let stored = sqlx::query_as::<_, UserPublicKey>(&format!("{} WHERE k.user_id = ?", kind.select()))
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Json(stored))
+}
+
+async fn delete_key(
+    db: &SqlitePool,
+    kind: KeyKind,
+    user_id: &str,
+) -> Result<StatusCode, (StatusCode, String)> {
+    sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?", kind.table()))
+        .bind(user_id)
+        .execute(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn find_key(
+    db: &SqlitePool,
+    kind: KeyKind,
+    username: &str,
+) -> Result<UserPublicKey, (StatusCode, String)> {
+    sqlx::query_as::<_, UserPublicKey>(&format!("{} WHERE u.username = ?", kind.select()))
+        .bind(username)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("No key stored for {}", username),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use steg_core::{EmbedConfig, Embedder, LsbEmbedder, SignerSecret, Verification};
+
+    async fn state(name: &str) -> AppState {
+        let url = format!("sqlite:file:{}?mode=memory&cache=shared", name);
+        let db = crate::db::init_pool(&url).await.unwrap();
+        for (id, role) in [("u-mallory", "customer"), ("u-admin", "admin")] {
+            sqlx::query(
+                "INSERT INTO users (id, username, email, password_hash, role) VALUES (?, ?, ?, '', ?)",
+            )
+                .bind(id)
+                .bind(&id[2..])
+                .bind(format!("{}@example.com", id))
+                .bind(role)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        AppState {
+            db,
+            jwt_secret: "test".into(),
+            upload_dir: "uploads".into(),
+        }
+    }
+
+    /// Verification of a message signed with `signer`, as `decode_painting`
+    /// reports it.
+    async fn verify(state: &AppState, signer: &SignerSecret) -> Verification {
+        let config = EmbedConfig::default().with_signer(signer.clone());
+        let img = image::DynamicImage::new_rgb8(32, 32);
+        let encoded = LsbEmbedder::new(config).unwrap().embed(&img, b"planted").unwrap();
+        let decoded = LsbEmbedder::default().extract_message(&encoded, None).unwrap();
+        trusted_signers(&state.db).await.unwrap().verify(&decoded.signature)
+    }
+
+    #[tokio::test]
+    async fn test_self_registered_signing_keys_are_untrusted() {
+        let state = state("self_registered_keys").await;
+        let mallory = AuthUser {
+            user_id: "u-mallory".into(),
+            role: "customer".into(),
+        };
+        let admin = || AdminUser {
+            user_id: "u-admin".into(),
+        };
+        let key = SignerSecret::generate();
+        let request = || {
+            Json(SetPublicKeyRequest {
+                public_key: key.public_key().to_string(),
+            })
+        };
+
+        let stored = set_signing_key(State(state.clone()), mallory.clone(), request())
+            .await
+            .unwrap();
+        assert_eq!(stored.trusted, Some(false));
+        assert!(matches!(verify(&state, &key).await, Verification::Untrusted(_)));
+
+        let trust = |trusted| Json(SetTrustRequest { trusted });
+        let approved =
+            set_signing_key_trust(State(state.clone()), admin(), Path("u-mallory".into()), trust(true))
+                .await
+                .unwrap();
+        assert_eq!(approved.trusted, Some(true));
+        assert!(matches!(
+            verify(&state, &key).await,
+            Verification::Trusted { name, .. } if name == "mallory"
+        ));
+
+        // Swapping in another key drops the approval
+        let other = SignerSecret::generate();
+        let replaced = set_signing_key(
+            State(state.clone()),
+            mallory,
+            Json(SetPublicKeyRequest {
+                public_key: other.public_key().to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(replaced.trusted, Some(false));
+        assert!(matches!(verify(&state, &other).await, Verification::Untrusted(_)));
+
+        let missing =
+            set_signing_key_trust(State(state), admin(), Path("u-admin".into()), trust(true)).await;
+        assert_eq!(missing.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+}
@@ -2,6 +2,7 @@ pub mod admin_handler;
 pub mod auth_handler;
 pub mod cart_handler;
 pub mod comment_handler;
+pub mod key_handler;
 pub mod order_handler;
 pub mod painting_handler;
 pub mod steg_handler;
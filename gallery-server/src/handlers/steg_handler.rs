@@ -3,16 +3,32 @@ use axum::{
     http::StatusCode,
     Json,
 };
-use image::ImageFormat;
 use serde::{Deserialize, Serialize};
+use steg_core::{Carrier, EmbedConfig, Embedder, Redundancy, StegError};
 
 use crate::auth::middleware::AdminUser;
+use crate::handlers::key_handler;
 use crate::models::{Painting, StegMessage};
 use crate::AppState;
 
+#[derive(Deserialize, Default)]
+pub struct DecodeRequest {
+    pub passphrase: Option<String>,
+    pub scheme: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct DecodeResponse {
     pub message: String,
+    pub format_version: u8,
+    pub errors_corrected: usize,
+    /// Original filename and type when the message was embedded as a file.
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    /// Username of the trusted sender whose signature checked out, if any.
+    pub signer: Option<String>,
+    /// "signed by NAME", or "UNVERIFIED" with the reason.
+    pub verification: String,
     pub painting_id: String,
     pub steg_message: StegMessage,
 }
@@ -21,7 +37,10 @@ pub async fn decode_painting(
     State(state): State<AppState>,
     admin: AdminUser,
     Path(painting_id): Path<String>,
+    body: Option<Json<DecodeRequest>>,
 ) -> Result<Json<DecodeResponse>, (StatusCode, String)> {
+    let DecodeRequest { passphrase, scheme } = body.map(|Json(b)| b).unwrap_or_default();
+
     let painting = sqlx::query_as::<_, Painting>("SELECT * FROM paintings WHERE id = ?")
         .bind(&painting_id)
         .fetch_optional(&state.db)
@@ -31,22 +50,65 @@ pub async fn decode_painting(
 
     // Load the image from disk
     let image_full_path = painting.image_path.replace("uploads/", &format!("{}/", state.upload_dir));
-    let img = image::open(&image_full_path)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open image: {}", e)))?;
+    let carrier = open_carrier(&image_full_path)?;
+    let embedder = select_embedder(&carrier, scheme.as_deref(), EmbedConfig::default())?;
 
     // Try to decode
-    let message = steg_core::decode_string(&img)
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("No hidden message found: {}", e)))?;
+    let decoded = match embedder.extract_message(carrier.image(), passphrase.as_deref().map(str::as_bytes)) {
+        Err(StegError::Expired { expires_at }) => {
+            // Record that a drop was here, but not what it said
+            let expires_at = steg_core::clock::format_unix_time(expires_at);
+            sqlx::query(
+                "INSERT INTO steg_messages (id, painting_id, direction, message_text, decoded_by, expires_at, expired) VALUES (?, ?, 'incoming', '', ?, ?, 1)",
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(&painting_id)
+            .bind(&admin.user_id)
+            .bind(&expires_at)
+            .execute(&state.db)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            return Err((
+                StatusCode::GONE,
+                format!("Dead drop expired at {}", expires_at),
+            ));
+        }
+        result => result.map_err(decode_error)?,
+    };
+    let expires_at = decoded
+        .header
+        .validity
+        .and_then(|validity| validity.expires_at)
+        .map(steg_core::clock::format_unix_time);
+    let format_version = decoded.header.version.number();
+    let errors_corrected = decoded.errors_corrected;
+    // Only signing keys an admin has approved count as trusted senders
+    let verification = key_handler::trusted_signers(&state.db)
+        .await?
+        .verify(&decoded.signature);
+    let signer = match &verification {
+        steg_core::Verification::Trusted { name, .. } => Some(name.clone()),
+        _ => None,
+    };
+    let (content, filename, mime_type) = if decoded.header.container {
+        let container = steg_core::Container::from_bytes(&decoded.message).map_err(decode_error)?;
+        (container.data, container.filename, container.mime_type)
+    } else {
+        (decoded.message, None, None)
+    };
+    let message = String::from_utf8(content)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Hidden message is not UTF-8: {}", e)))?;
 
     // Store the decoded message
     let msg_id = uuid::Uuid::new_v4().to_string();
     sqlx::query(
-        "INSERT INTO steg_messages (id, painting_id, direction, message_text, decoded_by) VALUES (?, ?, 'incoming', ?, ?)",
+        "INSERT INTO steg_messages (id, painting_id, direction, message_text, decoded_by, expires_at) VALUES (?, ?, 'incoming', ?, ?, ?)",
     )
     .bind(&msg_id)
     .bind(&painting_id)
     .bind(&message)
     .bind(&admin.user_id)
+    .bind(&expires_at)
     .execute(&state.db)
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
@@ -68,15 +130,83 @@ pub async fn decode_painting(
 
     Ok(Json(DecodeResponse {
         message,
+        format_version,
+        errors_corrected,
+        filename,
+        mime_type,
+        signer,
+        verification: verification.to_string(),
         painting_id,
         steg_message,
     }))
 }
 
+/// Resolve the embedding scheme named in a request, defaulting to LSB.
+/// Palette images always use the palette scheme and JPEG images the F5
+/// scheme.
+fn select_embedder(
+    carrier: &Carrier,
+    scheme: Option<&str>,
+    config: EmbedConfig,
+) -> Result<Box<dyn Embedder>, (StatusCode, String)> {
+    let name = scheme.unwrap_or(steg_core::LsbEmbedder::NAME);
+    carrier
+        .embedder(name, config)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+/// Load a painting's image as a carrier. Images no scheme can embed in, such
+/// as animated GIFs, are the client's problem rather than the server's.
+fn open_carrier(path: &str) -> Result<Carrier, (StatusCode, String)> {
+    steg_core::format::open_carrier(path).map_err(|e| match e {
+        StegError::UnsupportedCarrier(_) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+        e => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to open image: {}", e),
+        ),
+    })
+}
+
+fn decode_error(e: StegError) -> (StatusCode, String) {
+    match e {
+        StegError::DecryptionFailed => (
+            StatusCode::FORBIDDEN,
+            "Wrong passphrase or tampered message".into(),
+        ),
+        StegError::ChecksumMismatch | StegError::Uncorrectable => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Message present but corrupted".into(),
+        ),
+        StegError::SecretKeyRequired => (
+            StatusCode::BAD_REQUEST,
+            "Message is sealed to a recipient key and can only be read with their secret key"
+                .into(),
+        ),
+        StegError::NotYetValid { .. } => (StatusCode::TOO_EARLY, e.to_string()),
+        StegError::PassphraseRequired
+        | StegError::NotEncrypted
+        | StegError::CorruptHeader
+        | StegError::UnsupportedHeader(_) => (StatusCode::BAD_REQUEST, e.to_string()),
+        StegError::Decompression(_)
+        | StegError::DecompressionLimit { .. }
+        | StegError::MalformedContainer(_) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        }
+        _ => (StatusCode::BAD_REQUEST, format!("No hidden message found: {}", e)),
+    }
+}
+
 #[derive(Deserialize)]
 pub struct EncodeRequest {
     pub painting_id: String,
     pub message: String,
+    pub passphrase: Option<String>,
+    pub scheme: Option<String>,
+    /// Error-correction redundancy: "low", "medium" or "high".
+    pub fec: Option<String>,
+    /// Username to seal the message to with their stored public key, instead
+    /// of a passphrase.
+    pub recipient: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -91,6 +221,24 @@ pub async fn encode_painting(
     admin: AdminUser,
     Json(body): Json<EncodeRequest>,
 ) -> Result<Json<EncodeResponse>, (StatusCode, String)> {
+    if body.passphrase.is_some() && body.recipient.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Give either a passphrase or a recipient, not both".into(),
+        ));
+    }
+    let recipient = match &body.recipient {
+        Some(username) => {
+            let stored = key_handler::find_public_key(&state.db, username).await?;
+            let key: steg_core::RecipientKey = stored
+                .public_key
+                .parse()
+                .map_err(|e: StegError| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            Some(key)
+        }
+        None => None,
+    };
+
     let painting = sqlx::query_as::<_, Painting>("SELECT * FROM paintings WHERE id = ?")
         .bind(&body.painting_id)
         .fetch_optional(&state.db)
@@ -100,39 +248,70 @@ pub async fn encode_painting(
 
     // Load the image
     let image_full_path = painting.image_path.replace("uploads/", &format!("{}/", state.upload_dir));
-    let img = image::open(&image_full_path)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open image: {}", e)))?;
+    let carrier = open_carrier(&image_full_path)?;
+    let img = carrier.image();
 
     // Encode the message
-    let encoded = steg_core::encode(&img, body.message.as_bytes())
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Encoding failed: {}", e)))?;
+    // Messages are text, which nearly always shrinks; incompressible ones are
+    // stored verbatim
+    let mut config = EmbedConfig::default().with_compression(true);
+    if let Some(name) = &body.fec {
+        let redundancy = Redundancy::by_name(name).ok_or((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown error-correction level: {}", name),
+        ))?;
+        config = config.with_fec(redundancy);
+    }
+    let embedder = select_embedder(&carrier, body.scheme.as_deref(), config)?;
+    let encoded = match (&body.passphrase, &recipient) {
+        (Some(passphrase), _) => {
+            embedder.embed_encrypted(img, body.message.as_bytes(), passphrase.as_bytes())
+        }
+        (None, Some(recipient)) => embedder.embed_sealed(img, body.message.as_bytes(), recipient),
+        (None, None) => embedder.embed(img, body.message.as_bytes()),
+    }
+    .and_then(|encoded| carrier.with_image(encoded))
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Encoding failed: {}", e)))?;
 
-    // Save the encoded image back (overwrite)
+    // Save the encoded image back (overwrite), palette and JPEG images in their own format.
+    // A file whose name lies about its format moves to a matching extension
+    let output_path = encoded.output_path(&image_full_path);
     encoded
-        .save_with_format(&image_full_path, ImageFormat::Png)
+        .save(&output_path)
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save image: {}", e)))?;
+    let image_path = encoded.output_path(&painting.image_path).to_string_lossy().into_owned();
 
-    // Store the message record
+    // Store the message record. An encrypted or sealed message is meant for
+    // its key holder alone, so its text stays out of the database
     let msg_id = uuid::Uuid::new_v4().to_string();
+    let message_text = if body.passphrase.is_some() || recipient.is_some() {
+        ""
+    } else {
+        body.message.as_str()
+    };
     sqlx::query(
         "INSERT INTO steg_messages (id, painting_id, direction, message_text, encoded_by) VALUES (?, ?, 'outgoing', ?, ?)",
     )
     .bind(&msg_id)
     .bind(&body.painting_id)
-    .bind(&body.message)
+    .bind(message_text)
     .bind(&admin.user_id)
     .execute(&state.db)
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    // Mark painting as having steg message
+    // Mark painting as having steg message, under its new name if it moved
     sqlx::query(
-        "UPDATE paintings SET has_steg_message = 1, updated_at = datetime('now') WHERE id = ?",
+        "UPDATE paintings SET has_steg_message = 1, image_path = ?, updated_at = datetime('now') WHERE id = ?",
     )
+    .bind(&image_path)
     .bind(&body.painting_id)
     .execute(&state.db)
     .await
     .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if image_path != painting.image_path {
+        let _ = tokio::fs::remove_file(&image_full_path).await;
+    }
 
     let steg_message = sqlx::query_as::<_, StegMessage>("SELECT * FROM steg_messages WHERE id = ?")
         .bind(&msg_id)
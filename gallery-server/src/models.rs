@@ -104,4 +104,24 @@ pub struct StegMessage {
     pub decoded_by: Option<String>,
     pub encoded_by: Option<String>,
     pub created_at: String,
+    /// When the embedded drop stops being readable, if it has an expiry.
+    pub expires_at: Option<String>,
+    /// Whether the drop had already expired when it was decoded; the message
+    /// text is empty then.
+    pub expired: bool,
+}
+
+/// A user's public key: X25519 for messages sealed to them, or Ed25519 for
+/// checking messages they signed. The server never sees the secret keys.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserPublicKey {
+    pub user_id: String,
+    pub username: String,
+    pub public_key: String,
+    /// For signing keys, whether an admin has approved the key; messages
+    /// signed with an unapproved key are reported as unverified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<bool>,
+    pub created_at: String,
+    pub updated_at: String,
 }
@@ -10,7 +10,15 @@ pub fn create_router() -> Router<AppState> {
     let auth_routes = Router::new()
         .route("/register", post(auth_handler::register))
         .route("/login", post(auth_handler::login))
-        .route("/me", get(auth_handler::me));
+        .route("/me", get(auth_handler::me))
+        .route("/me/public-key", put(key_handler::set_public_key))
+        .route("/me/public-key", delete(key_handler::delete_public_key))
+        .route("/me/signing-key", put(key_handler::set_signing_key))
+        .route("/me/signing-key", delete(key_handler::delete_signing_key));
+
+    let user_routes = Router::new()
+        .route("/{username}/public-key", get(key_handler::get_public_key))
+        .route("/{username}/signing-key", get(key_handler::get_signing_key));
 
     let painting_routes = Router::new()
         .route("/", get(painting_handler::list_paintings))
@@ -46,6 +54,10 @@ pub fn create_router() -> Router<AppState> {
         .route("/stats", get(admin_handler::get_stats))
         .route("/users", get(admin_handler::list_users))
         .route("/users/{id}/role", put(admin_handler::update_user_role))
+        .route(
+            "/users/{id}/signing-key",
+            put(key_handler::set_signing_key_trust),
+        )
         .route("/paintings", get(admin_handler::list_all_paintings))
         .route(
             "/paintings/{id}/status",
@@ -69,6 +81,7 @@ pub fn create_router() -> Router<AppState> {
 
     Router::new()
         .nest("/api/auth", auth_routes)
+        .nest("/api/users", user_routes)
         .nest("/api/paintings", painting_routes)
         .nest("/api/comments", comment_routes)
         .nest("/api/cart", cart_routes)
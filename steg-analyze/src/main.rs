@@ -0,0 +1,72 @@
+use clap::Parser;
+use std::path::PathBuf;
+use steg_core::analysis;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "steg-analyze",
+    about = "Estimate whether an image carries LSB-embedded data"
+)]
+struct Args {
+    /// Image to analyse
+    #[arg(short, long)]
+    input: PathBuf,
+
+    /// Number of raster-order regions to test separately
+    #[arg(short, long, default_value_t = 16)]
+    regions: usize,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    let img = image::open(&args.input)?;
+    let analysis = analysis::analyze(&img, args.regions)?;
+    let report = &analysis.chi_square;
+
+    println!("Chi-square attack (pairs of values)");
+    println!(
+        "{:>6}  {:>10}  {:>10}  {:>11}",
+        "region", "start", "samples", "probability"
+    );
+    for (i, region) in report.regions.iter().enumerate() {
+        println!(
+            "{:>6}  {:>10}  {:>10}  {:>11.4}",
+            i, region.start, region.len, region.probability
+        );
+    }
+    println!(
+        "Estimated embedding rate: {:.1}%",
+        report.embedding_rate * 100.0
+    );
+
+    println!();
+    println!("Message length estimates (fraction of samples used)");
+    println!("{:>7}  {:>8}  {:>8}", "channel", "RS", "SPA");
+    for (name, estimate) in analysis.channel_names().iter().zip(&analysis.channels) {
+        println!(
+            "{:>7}  {:>8}  {:>8}",
+            name,
+            percent(estimate.rs),
+            percent(estimate.spa)
+        );
+    }
+    for (method, bytes) in [
+        ("RS", analysis.rs_message_bytes()),
+        ("SPA", analysis.spa_message_bytes()),
+    ] {
+        match bytes {
+            Some(bytes) => println!("Estimated message size ({}): {} bytes", method, bytes),
+            None => println!("Estimated message size ({}): n/a", method),
+        }
+    }
+
+    Ok(())
+}
+
+fn percent(rate: Option<f64>) -> String {
+    match rate {
+        Some(rate) => format!("{:.1}%", rate * 100.0),
+        None => "n/a".to_string(),
+    }
+}
@@ -0,0 +1,335 @@
+use image::DynamicImage;
+
+use crate::config::{EmbedConfig, EmbedStrategy};
+use crate::embedder::Embedder;
+use crate::error::StegError;
+use crate::header::{FormatVersion, Header};
+use crate::keyed::SlotPermutation;
+use crate::lsb::to_bits;
+use crate::samples::Samples;
+
+/// Content-adaptive LSB embedding: only textured areas carry bits.
+///
+/// Every colour sample is scored by the texture around it, the summed
+/// absolute difference between its 7 most significant bits and those of the
+/// same channel in the four neighbouring pixels. Only values scoring at least
+/// `min_texture` are used, so smooth areas such as sky or plain walls, where
+/// LSB changes are easiest to detect, are left untouched. Embedding changes
+/// nothing but LSBs, so the decoder recomputes the same selection from the
+/// stego image.
+///
+/// Selected values are filled in raster order, or in a key-driven order when
+/// the configuration has a key. The threshold is recorded in the header, but
+/// the decoder must be built with the same one to find the header at all.
+/// LSB matching and deeper embedding would change the scored bits, so the
+/// scheme requires LSB replacement at 1 bit per channel.
+#[derive(Debug, Clone)]
+pub struct AdaptiveEmbedder {
+    config: EmbedConfig,
+    min_texture: u8,
+}
+
+impl AdaptiveEmbedder {
+    pub const NAME: &'static str = "adaptive";
+
+    /// Scheme id recorded in the header.
+    pub const ID: u8 = 2;
+
+    /// Default texture threshold: an average difference of two MSB steps
+    /// (about four grey levels) to each neighbour.
+    pub const DEFAULT_MIN_TEXTURE: u8 = 8;
+
+    pub fn new(config: EmbedConfig) -> Result<Self, StegError> {
+        config.validate()?;
+        if config.bits_per_channel != 1 {
+            return Err(StegError::InvalidBitDepth(config.bits_per_channel));
+        }
+        if config.strategy != EmbedStrategy::Replace {
+            return Err(StegError::IncompatibleOptions(
+                "adaptive embedding needs LSB replacement; matching changes the bits used to select pixels".into(),
+            ));
+        }
+        Ok(Self {
+            config,
+            min_texture: Self::DEFAULT_MIN_TEXTURE,
+        })
+    }
+
+    /// Use only values whose texture score is at least `min_texture`.
+    /// 0 selects every value, like the LSB scheme.
+    pub fn with_min_texture(mut self, min_texture: u8) -> Self {
+        self.min_texture = min_texture;
+        self
+    }
+
+    pub fn min_texture(&self) -> u8 {
+        self.min_texture
+    }
+}
+
+impl Default for AdaptiveEmbedder {
+    fn default() -> Self {
+        Self {
+            config: EmbedConfig::default(),
+            min_texture: Self::DEFAULT_MIN_TEXTURE,
+        }
+    }
+}
+
+/// Texture score of every slot in raster order (see `AdaptiveEmbedder`).
+/// Pixels on the border are scaled up to four neighbours' worth. Scores do
+/// not depend on the sample depth.
+pub fn texture_scores(samples: &Samples) -> Vec<u16> {
+    let (w, h) = (samples.width() as i64, samples.height() as i64);
+    let shift = samples.depth() - 7;
+    let msbs = |x: i64, y: i64, ch: usize| (samples.sample(x as u32, y as u32, ch) >> shift) as i64;
+    let mut scores = Vec::with_capacity(samples.slot_count());
+    for y in 0..h {
+        for x in 0..w {
+            for ch in 0..samples.color_channels() {
+                let center = msbs(x, y, ch);
+                let (sum, count) = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                    .iter()
+                    .map(|(dx, dy)| (x + dx, y + dy))
+                    .filter(|&(nx, ny)| (0..w).contains(&nx) && (0..h).contains(&ny))
+                    .fold((0, 0), |(sum, count), (nx, ny)| {
+                        (sum + (center - msbs(nx, ny, ch)).abs(), count + 1)
+                    });
+                scores.push(if count == 0 {
+                    0
+                } else {
+                    (sum * 4 / count) as u16
+                });
+            }
+        }
+    }
+    scores
+}
+
+impl Embedder for AdaptiveEmbedder {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn config(&self) -> &EmbedConfig {
+        &self.config
+    }
+
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize {
+        match Samples::from_image(img) {
+            Ok(samples) => {
+                let carrier = Carrier::new(samples, self.min_texture, &self.config);
+                carrier.len().saturating_sub(header_len * 8) / 8
+            }
+            Err(_) => 0,
+        }
+    }
+
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let mut header = header.clone();
+        if header.version == FormatVersion::Legacy {
+            return Err(StegError::UnsupportedHeader(
+                "legacy headers only support the LSB scheme".into(),
+            ));
+        }
+        header.scheme = Self::ID;
+        header.scheme_param = self.min_texture;
+        let header = header.to_bytes()?;
+
+        let mut carrier = Carrier::new(Samples::from_image(img)?, self.min_texture, &self.config);
+        let needed = (header.len() + payload.len()) * 8;
+        if needed > carrier.len() {
+            return Err(StegError::MessageTooLarge {
+                needed,
+                capacity: carrier.len().saturating_sub(header.len() * 8) / 8 * 8,
+            });
+        }
+        for (slot, bit) in to_bits(&header).chain(to_bits(payload)).enumerate() {
+            carrier.set_lsb(slot, bit);
+        }
+        Ok(carrier.samples.into_image())
+    }
+
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError> {
+        let carrier = Carrier::new(Samples::from_image(img)?, self.min_texture, &self.config);
+        let read_bytes = |offset: usize, count: usize| -> Vec<u8> {
+            (0..count)
+                .map(|byte| {
+                    (0..8).fold(0u8, |acc, i| {
+                        (acc << 1) | carrier.lsb((offset + byte) * 8 + i)
+                    })
+                })
+                .collect()
+        };
+        let header = Header::read(read_bytes, carrier.len() / 8)?;
+
+        if header.version == FormatVersion::Legacy || header.scheme != Self::ID {
+            return Err(StegError::UnsupportedHeader(format!(
+                "embedding scheme {}",
+                header.scheme
+            )));
+        }
+        if header.scheme_param != self.min_texture {
+            return Err(StegError::UnsupportedHeader(format!(
+                "texture threshold {} (decoder uses {})",
+                header.scheme_param, self.min_texture
+            )));
+        }
+
+        let header_len = header.encoded_len();
+        if header_len + header.payload_len > carrier.len() / 8 {
+            return Err(StegError::InvalidLength);
+        }
+        let payload = read_bytes(header_len, header.payload_len);
+        Ok((header, payload))
+    }
+}
+
+/// The textured slots of an image, in embedding order.
+struct Carrier {
+    samples: Samples,
+    /// Image slots scoring at least the threshold, in raster order.
+    selected: Vec<usize>,
+    permutation: Option<SlotPermutation>,
+}
+
+impl Carrier {
+    fn new(samples: Samples, min_texture: u8, config: &EmbedConfig) -> Self {
+        let selected: Vec<usize> = texture_scores(&samples)
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, score)| score >= min_texture as u16)
+            .map(|(slot, _)| slot)
+            .collect();
+        let permutation = config
+            .key
+            .as_ref()
+            .map(|key| SlotPermutation::new(selected.len(), key));
+        Self {
+            samples,
+            selected,
+            permutation,
+        }
+    }
+
+    /// Number of usable slots.
+    fn len(&self) -> usize {
+        self.selected.len()
+    }
+
+    fn position(&self, slot: usize) -> usize {
+        let index = match &self.permutation {
+            Some(perm) => perm.get(slot),
+            None => slot,
+        };
+        self.selected[index]
+    }
+
+    fn lsb(&self, slot: usize) -> u8 {
+        (self.samples.get(self.position(slot)) & 1) as u8
+    }
+
+    fn set_lsb(&mut self, slot: usize, bit: u8) {
+        let slot = self.position(slot);
+        let value = self.samples.get(slot);
+        self.samples.set(slot, (value & !1) | bit as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsb::LsbEmbedder;
+    use image::RgbaImage;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    /// Left half flat grey, right half noise.
+    fn half_textured(w: u32, h: u32) -> DynamicImage {
+        let mut rng = ChaCha20Rng::seed_from_u64(41);
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |x, _| {
+            if x < w / 2 {
+                image::Rgba([128, 128, 128, 255])
+            } else {
+                image::Rgba([rng.gen(), rng.gen(), rng.gen(), 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_flat_regions_are_untouched() {
+        let img = half_textured(80, 80);
+        let embedder = AdaptiveEmbedder::default();
+        let capacity = embedder.capacity(&img);
+        // Roughly the noisy half; the column at the seam scores too
+        assert!(capacity < LsbEmbedder::default().capacity(&img) * 6 / 10);
+        assert!(capacity > LsbEmbedder::default().capacity(&img) * 4 / 10);
+
+        let message = vec![0xA5; capacity];
+        let encoded = embedder.embed(&img, &message).unwrap();
+        assert_eq!(embedder.extract(&encoded).unwrap(), message);
+
+        let (before, after) = (img.to_rgba8(), encoded.to_rgba8());
+        for y in 0..80 {
+            for x in 0..39 {
+                assert_eq!(before.get_pixel(x, y), after.get_pixel(x, y));
+            }
+        }
+        assert!(matches!(
+            embedder.embed(&img, &vec![0; capacity + 1]),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_capacity_follows_threshold() {
+        let img = half_textured(60, 60);
+        let all = AdaptiveEmbedder::default().with_min_texture(0);
+        assert_eq!(all.capacity(&img), LsbEmbedder::default().capacity(&img));
+        let strict = AdaptiveEmbedder::default().with_min_texture(200);
+        assert!(strict.capacity(&img) < AdaptiveEmbedder::default().capacity(&img));
+
+        let flat = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            60,
+            60,
+            image::Rgba([128, 128, 128, 255]),
+        ));
+        assert_eq!(AdaptiveEmbedder::default().capacity(&flat), 0);
+    }
+
+    #[test]
+    fn test_keyed_roundtrip_and_threshold_mismatch() {
+        let img = half_textured(60, 60);
+        let keyed = AdaptiveEmbedder::new(EmbedConfig::default().with_key(b"texture key")).unwrap();
+        let encoded = keyed.embed(&img, b"only in the noise").unwrap();
+        assert_eq!(keyed.extract(&encoded).unwrap(), b"only in the noise");
+        assert!(AdaptiveEmbedder::default().extract(&encoded).is_err());
+
+        let encoded = AdaptiveEmbedder::default()
+            .embed(&img, b"only in the noise")
+            .unwrap();
+        assert!(AdaptiveEmbedder::default()
+            .with_min_texture(AdaptiveEmbedder::DEFAULT_MIN_TEXTURE + 40)
+            .extract(&encoded)
+            .is_err());
+    }
+
+    #[test]
+    fn test_rejects_options_that_change_msbs() {
+        assert!(matches!(
+            AdaptiveEmbedder::new(EmbedConfig::default().with_bits_per_channel(2)),
+            Err(StegError::InvalidBitDepth(2))
+        ));
+        assert!(matches!(
+            AdaptiveEmbedder::new(
+                EmbedConfig::default().with_strategy(EmbedStrategy::Match { seed: Some(1) })
+            ),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use image::DynamicImage;
+
+use super::slots;
+use super::special::chi_square_survival;
+use crate::error::StegError;
+use crate::samples::Samples;
+
+/// Pairs of values whose expected count is below this are left out of the
+/// statistic; the chi-square approximation is poor for sparse bins.
+const MIN_EXPECTED: f64 = 5.0;
+
+/// Regions whose probability exceeds this count as carrying data.
+const DETECTION_THRESHOLD: f64 = 0.5;
+
+/// Chi-square result for one contiguous run of samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionProbability {
+    /// Index of the first slot of the region, in raster order.
+    pub start: usize,
+    /// Number of samples in the region.
+    pub len: usize,
+    pub statistic: f64,
+    pub degrees_of_freedom: usize,
+    /// Probability that the region's LSBs carry embedded data.
+    pub probability: f64,
+}
+
+/// Outcome of the chi-square attack on an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChiSquareReport {
+    pub regions: Vec<RegionProbability>,
+    /// Estimated fraction of the samples that carry data: the share of
+    /// regions whose probability exceeds one half.
+    pub embedding_rate: f64,
+}
+
+/// Westfeld-Pfitzmann chi-square attack.
+///
+/// Overwriting LSBs with random message bits equalises the counts of each
+/// pair of values `2i` and `2i + 1`. For every region this tests the observed
+/// counts of the even values against the pair means; a close fit means the
+/// region was likely embedded into. The colour samples are split in raster
+/// order into `regions` runs of equal length, matching the order sequential
+/// LSB embedding fills them in; 16-bit samples are tested at full depth.
+///
+/// The attack detects sequential LSB replacement well. Data scattered over a
+/// small fraction of the image, or embedded with LSB matching, leaves the
+/// pairs of values unbalanced and goes largely unnoticed.
+pub fn chi_square(img: &DynamicImage, regions: usize) -> Result<ChiSquareReport, StegError> {
+    Ok(attack(&Samples::from_image(img)?, regions))
+}
+
+/// `chi_square` over samples already taken from the image.
+pub(super) fn attack(samples: &Samples, regions: usize) -> ChiSquareReport {
+    let values = 1usize << samples.depth();
+    let samples = slots(samples);
+    let regions = regions.clamp(1, samples.len().max(1));
+    let region_len = samples.len().div_ceil(regions).max(1);
+
+    let regions: Vec<RegionProbability> = samples
+        .chunks(region_len)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let (statistic, degrees_of_freedom) = pairs_of_values(chunk, values);
+            let probability = if degrees_of_freedom == 0 {
+                0.0
+            } else {
+                chi_square_survival(statistic, degrees_of_freedom)
+            };
+            RegionProbability {
+                start: i * region_len,
+                len: chunk.len(),
+                statistic,
+                degrees_of_freedom,
+                probability,
+            }
+        })
+        .collect();
+
+    let detected = regions
+        .iter()
+        .filter(|r| r.probability > DETECTION_THRESHOLD)
+        .map(|r| r.len)
+        .sum::<usize>();
+    let embedding_rate = if samples.is_empty() {
+        0.0
+    } else {
+        detected as f64 / samples.len() as f64
+    };
+
+    ChiSquareReport {
+        regions,
+        embedding_rate,
+    }
+}
+
+/// Chi-square statistic of the even-value counts against the pair means,
+/// and its degrees of freedom. `values` is the number of possible values.
+fn pairs_of_values(samples: &[u16], values: usize) -> (f64, usize) {
+    let mut histogram = vec![0usize; values];
+    for &value in samples {
+        histogram[value as usize] += 1;
+    }
+    let mut statistic = 0.0;
+    let mut categories = 0usize;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected < MIN_EXPECTED {
+            continue;
+        }
+        let observed = pair[0] as f64;
+        statistic += (observed - expected).powi(2) / expected;
+        categories += 1;
+    }
+    (statistic, categories.saturating_sub(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsb::encode;
+    use image::RgbaImage;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    /// A carrier whose LSBs are clearly not uniform: three in four values even.
+    fn biased_image(w: u32, h: u32) -> DynamicImage {
+        let mut rng = ChaCha20Rng::seed_from_u64(21);
+        let mut value = move || (rng.gen::<u8>() & !1) | u8::from(rng.gen_bool(0.25));
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |_, _| {
+            image::Rgba([value(), value(), value(), 255])
+        }))
+    }
+
+    fn fill(img: &DynamicImage, fraction: f64) -> DynamicImage {
+        let len = (crate::lsb::capacity(img) as f64 * fraction) as usize;
+        let mut message = vec![0u8; len];
+        ChaCha20Rng::seed_from_u64(22).fill_bytes(&mut message);
+        encode(img, &message).unwrap()
+    }
+
+    #[test]
+    fn test_clean_carrier_is_not_flagged() {
+        let report = chi_square(&biased_image(128, 128), 16).unwrap();
+        assert_eq!(report.regions.len(), 16);
+        assert!(report.regions.iter().all(|r| r.probability < 0.01));
+        assert_eq!(report.embedding_rate, 0.0);
+    }
+
+    #[test]
+    fn test_full_embedding_is_flagged_everywhere() {
+        let report = chi_square(&fill(&biased_image(128, 128), 1.0), 16).unwrap();
+        assert!(report.regions.iter().all(|r| r.probability > 0.5));
+        assert!(report.embedding_rate > 0.95);
+    }
+
+    #[test]
+    fn test_partial_embedding_rate_is_estimated() {
+        let img = biased_image(128, 128);
+        for fraction in [0.25, 0.5, 0.75] {
+            let report = chi_square(&fill(&img, fraction), 32).unwrap();
+            assert!(
+                (report.embedding_rate - fraction).abs() <= 0.07,
+                "fill {}: estimated {}",
+                fraction,
+                report.embedding_rate
+            );
+            // Sequential embedding: flagged regions come first
+            let first_clean = report
+                .regions
+                .iter()
+                .position(|r| r.probability <= 0.5)
+                .unwrap();
+            assert!(report.regions[first_clean..]
+                .iter()
+                .all(|r| r.probability <= 0.5));
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Steganalysis: estimate whether, and how much, an image carries LSB data.
+//!
+//! These detectors are for auditing carriers before they are published and
+//! for screening uploads; they say nothing about the content of a payload.
+
+mod chi_square;
+mod rs;
+mod spa;
+mod special;
+
+pub use chi_square::{chi_square, ChiSquareReport, RegionProbability};
+
+use image::DynamicImage;
+
+use crate::error::StegError;
+use crate::samples::Samples;
+
+/// Names of the analysed channels of a colour image, in report order.
+pub const CHANNELS: [&str; 3] = ["red", "green", "blue"];
+
+/// Name of the single analysed channel of a greyscale image.
+pub const GREY_CHANNELS: [&str; 1] = ["grey"];
+
+/// Message-length estimates for one colour channel, as the fraction of the
+/// channel's samples carrying message bits (0 = clean, 1 = fully used).
+/// `None` when the estimator has no solution for this channel, e.g. an image
+/// too small or too flat to analyse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelEstimate {
+    /// Regular/singular groups analysis.
+    pub rs: Option<f64>,
+    /// Sample pair analysis.
+    pub spa: Option<f64>,
+}
+
+/// Every detector run over one image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalysisReport {
+    pub width: u32,
+    pub height: u32,
+    pub chi_square: ChiSquareReport,
+    /// Red, green and blue estimates, in that order, or the single grey one.
+    pub channels: Vec<ChannelEstimate>,
+}
+
+impl AnalysisReport {
+    /// Names of `channels`, in the same order.
+    pub fn channel_names(&self) -> &'static [&'static str] {
+        if self.channels.len() == 1 {
+            &GREY_CHANNELS
+        } else {
+            &CHANNELS
+        }
+    }
+
+    /// Hidden message size in bytes implied by the RS estimates.
+    pub fn rs_message_bytes(&self) -> Option<usize> {
+        self.message_bytes(|c| c.rs)
+    }
+
+    /// Hidden message size in bytes implied by the SPA estimates.
+    pub fn spa_message_bytes(&self) -> Option<usize> {
+        self.message_bytes(|c| c.spa)
+    }
+
+    fn message_bytes(&self, rate: impl Fn(&ChannelEstimate) -> Option<f64>) -> Option<usize> {
+        let samples = self.width as f64 * self.height as f64;
+        let bits = self
+            .channels
+            .iter()
+            .map(|c| rate(c).map(|r| r * samples))
+            .sum::<Option<f64>>()?;
+        Some((bits / 8.0).round() as usize)
+    }
+}
+
+/// Run the chi-square attack over `regions` regions and the RS and SPA
+/// length estimators on each colour channel: R, G and B, or the single grey
+/// one. Samples are analysed at the image's own depth. Fails with
+/// `UnsupportedCarrier` for floating-point images.
+pub fn analyze(img: &DynamicImage, regions: usize) -> Result<AnalysisReport, StegError> {
+    let samples = Samples::from_image(img)?;
+    let channels = (0..samples.color_channels())
+        .map(|ch| {
+            let rows: Vec<Vec<u16>> = (0..samples.height())
+                .map(|y| {
+                    (0..samples.width())
+                        .map(|x| samples.sample(x, y, ch))
+                        .collect()
+                })
+                .collect();
+            ChannelEstimate {
+                rs: rs::estimate(&rows).map(|p| p.clamp(0.0, 1.0)),
+                spa: spa::estimate(&rows).map(|p| p.clamp(0.0, 1.0)),
+            }
+        })
+        .collect();
+    Ok(AnalysisReport {
+        width: samples.width(),
+        height: samples.height(),
+        chi_square: chi_square::attack(&samples, regions),
+        channels,
+    })
+}
+
+/// The slots of an image in the order the LSB scheme fills them: raster order,
+/// R then G then B or one grey sample per pixel, at the image's own depth.
+fn slots(samples: &Samples) -> Vec<u16> {
+    (0..samples.slot_count())
+        .map(|slot| samples.get(slot))
+        .collect()
+}
+
+/// Root of `a x^2 + b x + c = 0` with the smaller magnitude. A pair of
+/// complex roots, which sampling noise produces near full embedding, yields
+/// their common real part.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<f64> {
+    if a.abs() < 1e-12 {
+        return (b.abs() > 1e-12).then(|| -c / b);
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Some(-b / (2.0 * a));
+    }
+    let root = discriminant.sqrt();
+    let first = (-b + root) / (2.0 * a);
+    let second = (-b - root) / (2.0 * a);
+    Some(if first.abs() <= second.abs() {
+        first
+    } else {
+        second
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsb::{capacity, encode};
+    use image::RgbaImage;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    /// Smooth shapes plus sensor-like noise: neighbouring samples are
+    /// correlated, as in a photograph.
+    fn natural_image(w: u32, h: u32) -> DynamicImage {
+        let mut rng = ChaCha20Rng::seed_from_u64(31);
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |x, y| {
+            let (fx, fy) = (x as f64, y as f64);
+            let base = 120.0
+                + 60.0 * (fx / 23.0).sin() * (fy / 31.0).cos()
+                + 30.0 * ((fx + 2.0 * fy) / 41.0).sin();
+            let mut channel = |offset: f64| {
+                let noise: f64 = (0..4).map(|_| rng.gen_range(-2.0..2.0)).sum::<f64>() / 2.0;
+                (base + offset + noise).round().clamp(0.0, 255.0) as u8
+            };
+            image::Rgba([channel(0.0), channel(-15.0), channel(20.0), 255])
+        }))
+    }
+
+    fn embed_fraction(img: &DynamicImage, fraction: f64) -> DynamicImage {
+        let len = (capacity(img) as f64 * fraction) as usize;
+        let mut message = vec![0u8; len];
+        ChaCha20Rng::seed_from_u64(32).fill_bytes(&mut message);
+        encode(img, &message).unwrap()
+    }
+
+    #[test]
+    fn test_estimates_track_fill_rate() {
+        let img = natural_image(256, 256);
+        for fraction in [0.0, 0.1, 0.25, 0.5, 0.75] {
+            let stego = if fraction == 0.0 {
+                img.clone()
+            } else {
+                embed_fraction(&img, fraction)
+            };
+            let report = analyze(&stego, 16).unwrap();
+            for (name, estimate) in CHANNELS.iter().zip(&report.channels) {
+                let rs = estimate.rs.unwrap();
+                let spa = estimate.spa.unwrap();
+                assert!(
+                    (rs - fraction).abs() < 0.08,
+                    "{} RS at {}: {}",
+                    name,
+                    fraction,
+                    rs
+                );
+                assert!(
+                    (spa - fraction).abs() < 0.08,
+                    "{} SPA at {}: {}",
+                    name,
+                    fraction,
+                    spa
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_embedding_reads_high() {
+        // Both estimators lose accuracy close to 100%, but stay near it
+        let report = analyze(&embed_fraction(&natural_image(256, 256), 1.0), 16).unwrap();
+        for estimate in &report.channels {
+            assert!(estimate.rs.unwrap() > 0.85, "{:?}", estimate);
+            assert!(estimate.spa.unwrap() > 0.85, "{:?}", estimate);
+        }
+    }
+
+    #[test]
+    fn test_message_length_estimate() {
+        let img = natural_image(256, 256);
+        let stego = embed_fraction(&img, 0.5);
+        let actual = capacity(&img) / 2;
+        let report = analyze(&stego, 16).unwrap();
+        for estimate in [report.rs_message_bytes(), report.spa_message_bytes()] {
+            let estimate = estimate.unwrap() as f64;
+            assert!(
+                (estimate - actual as f64).abs() < actual as f64 * 0.15,
+                "estimated {} bytes, embedded {}",
+                estimate,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn test_greyscale_16_bit_samples_are_analysed_at_full_depth() {
+        // The same scene in a narrow band of 16-bit grey: converting it to
+        // 8 bits would leave a flat image with none of the embedded LSBs
+        let rgba = natural_image(256, 256).into_rgba8();
+        let img = DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(256, 256, |x, y| {
+            image::Luma([20_000 + rgba.get_pixel(x, y)[0] as u16])
+        }));
+        let stego = embed_fraction(&img, 0.5);
+        assert_eq!(stego.color(), image::ColorType::L16);
+        let report = analyze(&stego, 16).unwrap();
+        assert_eq!(report.channel_names(), ["grey"]);
+        assert_eq!(report.chi_square.regions[0].start, 0);
+        assert_eq!(
+            report
+                .chi_square
+                .regions
+                .iter()
+                .map(|r| r.len)
+                .sum::<usize>(),
+            256 * 256
+        );
+        let estimate = &report.channels[0];
+        for rate in [estimate.rs.unwrap(), estimate.spa.unwrap()] {
+            assert!((rate - 0.5).abs() < 0.08, "{:?}", estimate);
+        }
+        let actual = capacity(&img) / 2;
+        let bytes = report.rs_message_bytes().unwrap() as f64;
+        assert!(
+            (bytes - actual as f64).abs() < actual as f64 * 0.15,
+            "{}",
+            bytes
+        );
+    }
+
+    #[test]
+    fn test_solve_quadratic_picks_smaller_root() {
+        // (x - 0.2)(x - 3) = x^2 - 3.2x + 0.6
+        assert!((solve_quadratic(1.0, -3.2, 0.6).unwrap() - 0.2).abs() < 1e-12);
+        assert!((solve_quadratic(0.0, 2.0, -1.0).unwrap() - 0.5).abs() < 1e-12);
+        assert_eq!(solve_quadratic(1.0, -1.0, 1.0), Some(0.5));
+        assert_eq!(solve_quadratic(0.0, 0.0, 1.0), None);
+    }
+}
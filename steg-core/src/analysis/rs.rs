@@ -0,0 +1,100 @@
+use super::solve_quadratic;
+
+/// Pixels per group. Groups are runs of horizontally adjacent samples.
+const GROUP_LEN: usize = 4;
+
+/// Which members of a group the flipping functions are applied to.
+const MASK: [bool; GROUP_LEN] = [false, true, true, false];
+
+/// Proportions of regular and singular groups under one mask.
+struct Counts {
+    regular: f64,
+    singular: f64,
+}
+
+impl Counts {
+    fn difference(&self) -> f64 {
+        self.regular - self.singular
+    }
+}
+
+/// RS analysis (Fridrich, Goljan and Du) of one channel, given as rows of
+/// samples. Returns the estimated fraction of samples carrying message bits.
+///
+/// Groups of adjacent samples are classified as regular or singular by
+/// whether flipping their LSBs makes them noisier or smoother. In a natural
+/// image flipping with `F1` (0<->1, 2<->3, ...) and with the shifted `F-1`
+/// (-1<->0, 1<->2, ...) change the counts alike; LSB replacement breaks that
+/// symmetry in proportion to the message length.
+pub(super) fn estimate(rows: &[Vec<u16>]) -> Option<f64> {
+    let positive = counts(rows, false, flip_positive);
+    let negative = counts(rows, false, flip_negative);
+    let positive_flipped = counts(rows, true, flip_positive);
+    let negative_flipped = counts(rows, true, flip_negative);
+    if positive.regular + positive.singular == 0.0 {
+        return None;
+    }
+
+    let d0 = positive.difference();
+    let d1 = positive_flipped.difference();
+    let dn0 = negative.difference();
+    let dn1 = negative_flipped.difference();
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+    let z = solve_quadratic(a, b, c)?;
+    if (z - 0.5).abs() < f64::EPSILON {
+        return None;
+    }
+    Some(z / (z - 0.5))
+}
+
+fn flip_positive(value: i32) -> i32 {
+    value ^ 1
+}
+
+fn flip_negative(value: i32) -> i32 {
+    ((value + 1) ^ 1) - 1
+}
+
+/// Classify every group, optionally after flipping all LSBs of the channel.
+fn counts(rows: &[Vec<u16>], flip_all: bool, flip: fn(i32) -> i32) -> Counts {
+    let mut regular = 0usize;
+    let mut singular = 0usize;
+    let mut total = 0usize;
+    for row in rows {
+        for group in row.chunks_exact(GROUP_LEN) {
+            let mut values = [0i32; GROUP_LEN];
+            for (value, &sample) in values.iter_mut().zip(group) {
+                *value = if flip_all {
+                    (sample ^ 1) as i32
+                } else {
+                    sample as i32
+                };
+            }
+            let before = smoothness(&values);
+            for (value, &masked) in values.iter_mut().zip(&MASK) {
+                if masked {
+                    *value = flip(*value);
+                }
+            }
+            let after = smoothness(&values);
+            total += 1;
+            if after > before {
+                regular += 1;
+            } else if after < before {
+                singular += 1;
+            }
+        }
+    }
+    let total = total.max(1) as f64;
+    Counts {
+        regular: regular as f64 / total,
+        singular: singular as f64 / total,
+    }
+}
+
+/// Discrimination function: total variation along the group.
+fn smoothness(values: &[i32]) -> i32 {
+    values.windows(2).map(|w| (w[1] - w[0]).abs()).sum()
+}
@@ -0,0 +1,45 @@
+use super::solve_quadratic;
+
+/// Sample Pair Analysis (Dumitrescu, Wu and Memon) of one channel, given as
+/// rows of samples. Returns the estimated fraction of samples carrying
+/// message bits.
+///
+/// Horizontally adjacent pairs `(u, v)` are sorted into `X` (v even and
+/// u < v, or v odd and u > v), `Y` (the mirror case), `Z` (u = v) and `W`
+/// (u and v differ only in the LSB). Natural images have |X| close to |Y|;
+/// LSB replacement moves pairs between the sets in a way that makes the
+/// embedding rate `p` the smaller root of
+/// `(W + Z) / 2 * p^2 + (2X - P) * p + Y - X = 0`, with `P` the pair count.
+pub(super) fn estimate(rows: &[Vec<u16>]) -> Option<f64> {
+    let mut x = 0usize;
+    let mut y = 0usize;
+    let mut z = 0usize;
+    let mut w = 0usize;
+    let mut pairs = 0usize;
+    for row in rows {
+        for pair in row.windows(2) {
+            let (u, v) = (pair[0], pair[1]);
+            pairs += 1;
+            if u == v {
+                z += 1;
+                continue;
+            }
+            if u >> 1 == v >> 1 {
+                w += 1;
+            }
+            if (v % 2 == 0 && u < v) || (v % 2 == 1 && u > v) {
+                x += 1;
+            } else {
+                y += 1;
+            }
+        }
+    }
+    if pairs == 0 {
+        return None;
+    }
+
+    let a = (w + z) as f64 / 2.0;
+    let b = 2.0 * x as f64 - pairs as f64;
+    let c = y as f64 - x as f64;
+    solve_quadratic(a, b, c)
+}
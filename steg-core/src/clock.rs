@@ -0,0 +1,132 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time for checking when a message may be read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// The system clock.
+    #[default]
+    System,
+    /// A fixed time in seconds since the Unix epoch, for tests and for
+    /// checking a drop as of some other moment.
+    Fixed(u64),
+}
+
+impl Clock {
+    /// Seconds since the Unix epoch.
+    pub fn now(&self) -> u64 {
+        match self {
+            Clock::System => unix_now(),
+            Clock::Fixed(secs) => *secs,
+        }
+    }
+}
+
+/// Seconds since the Unix epoch according to the system clock.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+pub fn format_unix_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days (Howard Hinnant), counting eras of 400 years from 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// Parse a UTC time given as seconds since the Unix epoch, as a date
+/// (`2026-10-18`) or as an RFC 3339 timestamp in UTC
+/// (`2026-10-18T06:00:00Z`).
+pub fn parse_unix_time(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse() {
+        return Some(secs);
+    }
+    let (date, time) = match text.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z')?)),
+        None => (text, None),
+    };
+    let mut parts = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let seconds = match time {
+        Some(time) => {
+            let mut parts = time.splitn(3, ':').map(str::parse::<i64>);
+            let (h, m, s) = (
+                parts.next()?.ok()?,
+                parts.next()?.ok()?,
+                parts.next()?.ok()?,
+            );
+            if !(0..24).contains(&h) || !(0..60).contains(&m) || !(0..60).contains(&s) {
+                return None;
+            }
+            h * 3_600 + m * 60 + s
+        }
+        None => 0,
+    };
+    // Days-from-civil, the inverse of `format_unix_time`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86_400 + seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_unix_time() {
+        assert_eq!(format_unix_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_unix_time(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_unix_time(1_792_300_558), "2026-10-18T05:15:58Z");
+    }
+
+    #[test]
+    fn test_parse_unix_time() {
+        for secs in [0, 951_782_400, 1_792_300_558, 4_102_444_800] {
+            assert_eq!(parse_unix_time(&format_unix_time(secs)), Some(secs));
+        }
+        assert_eq!(parse_unix_time("2000-02-29"), Some(951_782_400));
+        assert_eq!(parse_unix_time("1792300558"), Some(1_792_300_558));
+        for bad in [
+            "2026-13-01",
+            "2026-10-18T25:00:00Z",
+            "2026-10-18T06:00:00+02:00",
+            "soon",
+            "1969-12-31",
+        ] {
+            assert_eq!(parse_unix_time(bad), None, "{}", bad);
+        }
+        assert_eq!(Clock::Fixed(42).now(), 42);
+    }
+}
@@ -0,0 +1,109 @@
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression as Level;
+
+use crate::error::StegError;
+
+/// Compression algorithm applied to a payload before it is embedded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Raw DEFLATE (RFC 1951) at the best compression level.
+    Deflate,
+}
+
+impl Compression {
+    /// Identifier recorded in the header.
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::Deflate => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Compression::Deflate),
+            _ => None,
+        }
+    }
+}
+
+/// Default cap on the size of a decompressed message: 16 MiB.
+pub const DEFAULT_DECOMPRESSION_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Compress `data`, returning `None` unless the result plus `overhead` bytes
+/// of header bookkeeping is strictly smaller than `data`.
+pub fn compress(data: &[u8], overhead: usize) -> Option<(Compression, Vec<u8>)> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Level::best());
+    encoder
+        .write_all(data)
+        .expect("writing to an in-memory buffer cannot fail");
+    let compressed = encoder
+        .finish()
+        .expect("writing to an in-memory buffer cannot fail");
+    (compressed.len() + overhead < data.len()).then_some((Compression::Deflate, compressed))
+}
+
+/// Decompress `data`, refusing to produce more than `limit` bytes so a small
+/// crafted payload cannot expand into an arbitrarily large allocation.
+pub fn decompress(
+    compression: Compression,
+    data: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, StegError> {
+    match compression {
+        Compression::Deflate => {
+            let mut output = Vec::new();
+            DeflateDecoder::new(data)
+                .take(limit as u64 + 1)
+                .read_to_end(&mut output)
+                .map_err(|e| StegError::Decompression(e.to_string()))?;
+            if output.len() > limit {
+                return Err(StegError::DecompressionLimit { limit });
+            }
+            Ok(output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_roundtrip_when_it_shrinks() {
+        let text = b"meet at the usual place, meet at the usual place, meet at the usual place";
+        let (compression, compressed) = compress(text, 1).unwrap();
+        assert!(compressed.len() < text.len());
+        assert_eq!(
+            decompress(compression, &compressed, 1024).unwrap(),
+            text.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_skipped_when_it_does_not_shrink() {
+        assert!(compress(b"x7Qz", 1).is_none());
+        let mut noise = vec![0u8; 512];
+        ChaCha20Rng::seed_from_u64(1).fill_bytes(&mut noise);
+        assert!(compress(&noise, 1).is_none());
+    }
+
+    #[test]
+    fn test_limit_stops_decompression_bombs() {
+        let bomb = vec![0u8; 1 << 20];
+        let (compression, compressed) = compress(&bomb, 1).unwrap();
+        assert!(compressed.len() < 2048);
+        assert!(matches!(
+            decompress(compression, &compressed, 64 * 1024),
+            Err(StegError::DecompressionLimit { limit: 65536 })
+        ));
+        assert_eq!(
+            decompress(compression, &compressed, 1 << 20).unwrap().len(),
+            1 << 20
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+use crate::clock::Clock;
+use crate::compress::DEFAULT_DECOMPRESSION_LIMIT;
+use crate::error::StegError;
+use crate::fec::Redundancy;
+use crate::header::Validity;
+use crate::signature::SignerSecret;
+
+/// How a channel value is changed when its low bits must carry a payload bit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmbedStrategy {
+    /// Overwrite the low bits (`value & !mask | bits`). Fast, but pairs of
+    /// values become equally frequent, which the chi-square attack detects.
+    #[default]
+    Replace,
+    /// LSB matching (+-1 embedding): move the value to the nearest one that
+    /// carries the wanted bits, choosing randomly between the candidates above
+    /// and below. The random choices come from a ChaCha20 RNG seeded with
+    /// `seed`, or from the OS when `None`. Decoding is unaffected.
+    Match { seed: Option<u64> },
+}
+
+impl EmbedStrategy {
+    /// RNG driving the random choices, if the strategy needs one.
+    pub(crate) fn rng(&self) -> Option<ChaCha20Rng> {
+        match self {
+            EmbedStrategy::Replace => None,
+            EmbedStrategy::Match { seed: Some(seed) } => Some(ChaCha20Rng::seed_from_u64(*seed)),
+            EmbedStrategy::Match { seed: None } => Some(ChaCha20Rng::from_entropy()),
+        }
+    }
+}
+
+/// Parameters controlling how payload bits are placed in the carrier.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EmbedConfig {
+    /// Number of low-order bits used in each R, G, B channel (1-4).
+    /// More bits give more capacity at the cost of visible distortion.
+    pub bits_per_channel: u8,
+    /// How channel values are modified to carry the bits.
+    pub strategy: EmbedStrategy,
+    /// Scatter the bits over a permutation of the slots derived from this
+    /// key (see `SlotPermutation`) instead of raster order.
+    pub key: Option<Vec<u8>>,
+    /// Wrap the payload in Reed-Solomon codewords with this much parity.
+    pub fec: Option<Redundancy>,
+    /// Compress the message before embedding when that makes it smaller.
+    pub compress: bool,
+    /// Largest message in bytes decoding will decompress to.
+    pub decompression_limit: usize,
+    /// Sign every message with this key before compression and encryption.
+    pub signer: Option<SignerSecret>,
+    /// Messages are `Container` encodings; the header says so, so decoders
+    /// know to parse them.
+    pub container: bool,
+    /// Time window recorded in every message; decoders refuse to read it
+    /// outside the window.
+    pub validity: Option<Validity>,
+    /// Clock decoding checks validity windows against.
+    pub clock: Clock,
+}
+
+impl EmbedConfig {
+    pub const MAX_BITS_PER_CHANNEL: u8 = 4;
+
+    pub fn with_bits_per_channel(mut self, bits_per_channel: u8) -> Self {
+        self.bits_per_channel = bits_per_channel;
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: EmbedStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn with_key(mut self, key: &[u8]) -> Self {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    pub fn with_fec(mut self, redundancy: Redundancy) -> Self {
+        self.fec = Some(redundancy);
+        self
+    }
+
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn with_decompression_limit(mut self, limit: usize) -> Self {
+        self.decompression_limit = limit;
+        self
+    }
+
+    pub fn with_signer(mut self, signer: SignerSecret) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn with_container(mut self, container: bool) -> Self {
+        self.container = container;
+        self
+    }
+
+    /// Messages cannot be read before `secs`, in seconds since the Unix epoch.
+    pub fn with_not_before(mut self, secs: u64) -> Self {
+        self.validity.get_or_insert_with(Validity::default).not_before = Some(secs);
+        self
+    }
+
+    /// Messages cannot be read from `secs` on, in seconds since the Unix epoch.
+    pub fn with_expires_at(mut self, secs: u64) -> Self {
+        self.validity.get_or_insert_with(Validity::default).expires_at = Some(secs);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), StegError> {
+        if !(1..=Self::MAX_BITS_PER_CHANNEL).contains(&self.bits_per_channel) {
+            return Err(StegError::InvalidBitDepth(self.bits_per_channel));
+        }
+        if let Some(Validity {
+            not_before: Some(not_before),
+            expires_at: Some(expires_at),
+        }) = self.validity
+        {
+            if not_before >= expires_at {
+                return Err(StegError::IncompatibleOptions(
+                    "the message would expire before it can be read".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for EmbedConfig {
+    fn default() -> Self {
+        Self {
+            bits_per_channel: 1,
+            strategy: EmbedStrategy::default(),
+            key: None,
+            fec: None,
+            compress: false,
+            decompression_limit: DEFAULT_DECOMPRESSION_LIMIT,
+            signer: None,
+            container: false,
+            validity: None,
+            clock: Clock::default(),
+        }
+    }
+}
+
+impl fmt::Debug for EmbedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmbedConfig")
+            .field("bits_per_channel", &self.bits_per_channel)
+            .field("strategy", &self.strategy)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("fec", &self.fec)
+            .field("compress", &self.compress)
+            .field("decompression_limit", &self.decompression_limit)
+            .field("signer", &self.signer.as_ref().map(SignerSecret::public_key))
+            .field("container", &self.container)
+            .field("validity", &self.validity)
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
@@ -0,0 +1,296 @@
+use std::path::{Component, Path};
+
+use crate::clock::unix_now;
+use crate::error::StegError;
+
+/// Format version written as the first byte of a container.
+pub const CONTAINER_VERSION: u8 = 1;
+
+/// MIME type for content of unknown type.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+const TAG_DATA: u8 = 0x01;
+const TAG_FILENAME: u8 = 0x02;
+const TAG_MIME_TYPE: u8 = 0x03;
+const TAG_CREATED_AT: u8 = 0x04;
+const TAG_FIELD: u8 = 0x05;
+
+/// Bytes of a record before its value: tag and u32 length.
+const RECORD_HEADER_LEN: usize = 5;
+
+/// A file or message with its name, type and metadata, embedded in place of
+/// raw bytes when the header carries `FLAG_CONTAINER`.
+///
+/// The encoding is a version byte followed by type-length-value records: a
+/// one-byte tag, a big-endian u32 length and the value. Records may come in
+/// any order and readers skip tags they do not know, so fields can be added
+/// without a new version.
+///
+/// | tag  | value                                              |
+/// |------|----------------------------------------------------|
+/// | 0x01 | content bytes                                      |
+/// | 0x02 | filename, UTF-8                                    |
+/// | 0x03 | MIME type, UTF-8                                   |
+/// | 0x04 | creation time, u64 seconds since the Unix epoch    |
+/// | 0x05 | key/value field: u16 key length, key, value, UTF-8 |
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Container {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: Option<u64>,
+    /// Free-form metadata, in the order given.
+    pub fields: Vec<(String, String)>,
+    pub data: Vec<u8>,
+}
+
+impl Container {
+    /// A container holding `data`, stamped with the current time.
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            created_at: Some(unix_now()),
+            data,
+            ..Self::default()
+        }
+    }
+
+    /// Record `filename` and the MIME type its extension suggests.
+    pub fn with_filename(mut self, filename: impl Into<String>) -> Self {
+        let filename = filename.into();
+        self.mime_type = Some(guess_mime_type(&filename).to_string());
+        self.filename = Some(filename);
+        self
+    }
+
+    pub fn with_mime_type(mut self, mime_type: impl Into<String>) -> Self {
+        self.mime_type = Some(mime_type.into());
+        self
+    }
+
+    pub fn with_field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Value of the first field called `key`.
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The filename, if it names a plain file without any directory, drive or
+    /// stream part, so that writing to it cannot escape the directory it is
+    /// restored into on any platform. Hidden files, which shells and tools
+    /// read on their own, Windows device names and names Windows would change
+    /// by stripping a trailing dot or space are refused too.
+    pub fn safe_filename(&self) -> Option<&str> {
+        self.filename.as_deref().filter(|name| {
+            let mut components = Path::new(name).components();
+            !name.contains(['/', '\\', ':', '\0'])
+                && !name.starts_with('.')
+                && !name.ends_with(['.', ' '])
+                && !is_device_name(name)
+                && matches!(components.next(), Some(Component::Normal(part)) if part == *name)
+                && components.next().is_none()
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![CONTAINER_VERSION];
+        if let Some(filename) = &self.filename {
+            push_record(&mut bytes, TAG_FILENAME, filename.as_bytes());
+        }
+        if let Some(mime_type) = &self.mime_type {
+            push_record(&mut bytes, TAG_MIME_TYPE, mime_type.as_bytes());
+        }
+        if let Some(created_at) = self.created_at {
+            push_record(&mut bytes, TAG_CREATED_AT, &created_at.to_be_bytes());
+        }
+        for (key, value) in &self.fields {
+            let mut field = Vec::with_capacity(2 + key.len() + value.len());
+            field.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            field.extend_from_slice(key.as_bytes());
+            field.extend_from_slice(value.as_bytes());
+            push_record(&mut bytes, TAG_FIELD, &field);
+        }
+        push_record(&mut bytes, TAG_DATA, &self.data);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StegError> {
+        let (&version, mut rest) = bytes
+            .split_first()
+            .ok_or_else(|| malformed("empty container"))?;
+        if version != CONTAINER_VERSION {
+            return Err(StegError::UnsupportedHeader(format!(
+                "container version {}",
+                version
+            )));
+        }
+        let mut container = Self::default();
+        let mut data = None;
+        while !rest.is_empty() {
+            if rest.len() < RECORD_HEADER_LEN {
+                return Err(malformed("truncated record"));
+            }
+            let tag = rest[0];
+            let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            let value = rest
+                .get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)
+                .ok_or_else(|| malformed("record runs past the end"))?;
+            rest = &rest[RECORD_HEADER_LEN + len..];
+            match tag {
+                TAG_DATA => data = Some(value.to_vec()),
+                TAG_FILENAME => container.filename = Some(utf8(value, "filename")?),
+                TAG_MIME_TYPE => container.mime_type = Some(utf8(value, "MIME type")?),
+                TAG_CREATED_AT => {
+                    let secs: [u8; 8] = value
+                        .try_into()
+                        .map_err(|_| malformed("creation time is not 8 bytes"))?;
+                    container.created_at = Some(u64::from_be_bytes(secs));
+                }
+                TAG_FIELD => {
+                    if value.len() < 2 {
+                        return Err(malformed("truncated field"));
+                    }
+                    let key_len = u16::from_be_bytes([value[0], value[1]]) as usize;
+                    let key = value
+                        .get(2..2 + key_len)
+                        .ok_or_else(|| malformed("field key runs past the record"))?;
+                    container.fields.push((
+                        utf8(key, "field key")?,
+                        utf8(&value[2 + key_len..], "field value")?,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        container.data = data.ok_or_else(|| malformed("no content record"))?;
+        Ok(container)
+    }
+}
+
+fn push_record(bytes: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    bytes.push(tag);
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+fn utf8(value: &[u8], what: &str) -> Result<String, StegError> {
+    String::from_utf8(value.to_vec()).map_err(|_| malformed(&format!("{} is not UTF-8", what)))
+}
+
+/// Whether Windows treats `name` as a device, whatever its extension:
+/// `CON`, `NUL`, `com1.txt`.
+fn is_device_name(name: &str) -> bool {
+    let stem = name.split_once('.').map_or(name, |(stem, _)| stem);
+    let stem = stem.trim_end().to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        _ => {
+            (stem.starts_with("COM") || stem.starts_with("LPT"))
+                && stem.len() == 4
+                && stem.as_bytes()[3].is_ascii_digit()
+        }
+    }
+}
+
+fn malformed(reason: &str) -> StegError {
+    StegError::MalformedContainer(reason.to_string())
+}
+
+/// MIME type suggested by a filename's extension, or `DEFAULT_MIME_TYPE`.
+pub fn guess_mime_type(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "text" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => DEFAULT_MIME_TYPE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_with_metadata() {
+        let container = Container::new(b"%PDF-1.7 ...".to_vec())
+            .with_filename("Report.PDF")
+            .with_field("source", "north gate")
+            .with_field("", "empty key is fine");
+        assert_eq!(container.mime_type.as_deref(), Some("application/pdf"));
+        let bytes = container.to_bytes();
+        assert_eq!(bytes[0], CONTAINER_VERSION);
+        let parsed = Container::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, container);
+        assert_eq!(parsed.field("source"), Some("north gate"));
+    }
+
+    #[test]
+    fn test_unknown_records_are_skipped_and_bad_ones_rejected() {
+        let mut bytes = Container::new(b"data".to_vec()).to_bytes();
+        push_record(&mut bytes, 0x7F, b"from a newer writer");
+        assert_eq!(Container::from_bytes(&bytes).unwrap().data, b"data");
+
+        bytes.truncate(bytes.len() - 3);
+        assert!(matches!(
+            Container::from_bytes(&bytes),
+            Err(StegError::MalformedContainer(_))
+        ));
+        assert!(matches!(
+            Container::from_bytes(&[CONTAINER_VERSION]),
+            Err(StegError::MalformedContainer(_))
+        ));
+        assert!(matches!(
+            Container::from_bytes(&[9, TAG_DATA, 0, 0, 0, 0]),
+            Err(StegError::UnsupportedHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_safe_filename() {
+        let named = |name: &str| Container::default().with_filename(name);
+        assert_eq!(named("notes.txt").safe_filename(), Some("notes.txt"));
+        assert_eq!(named("console.log").safe_filename(), Some("console.log"));
+        assert_eq!(named("com10").safe_filename(), Some("com10"));
+        for unsafe_name in [
+            "../etc/passwd",
+            "/tmp/x",
+            "a\\b",
+            "..",
+            ".",
+            "",
+            "C:evil.exe",
+            "notes.txt:hidden",
+            "notes.txt/",
+            ".bash_login",
+            ".profile",
+            "CON",
+            "nul.txt",
+            "com1",
+            "LPT9.log",
+            "notes.txt.",
+        ] {
+            assert_eq!(named(unsafe_name).safe_filename(), None, "{}", unsafe_name);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::error::StegError;
+
+/// Argon2id salt length in bytes.
+pub const SALT_LEN: usize = 16;
+
+/// ChaCha20-Poly1305 nonce length in bytes.
+pub const NONCE_LEN: usize = 12;
+
+/// Poly1305 authentication tag length in bytes.
+pub const TAG_LEN: usize = 16;
+
+/// Key-derivation salt and AEAD nonce of an encrypted payload.
+/// They travel in the embedded header next to the ciphertext.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionParams {
+    pub salt: [u8; SALT_LEN],
+    pub nonce: [u8; NONCE_LEN],
+}
+
+/// Encrypt `plaintext` with a key derived from `passphrase` (Argon2id, fresh
+/// random salt) using ChaCha20-Poly1305 with a fresh random nonce.
+/// `aad` is authenticated but not encrypted. Returns the parameters needed to
+/// decrypt and the ciphertext followed by the authentication tag.
+pub fn seal(plaintext: &[u8], passphrase: &[u8], aad: &[u8]) -> (EncryptionParams, Vec<u8>) {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("ChaCha20-Poly1305 encryption of an in-memory buffer cannot fail");
+
+    let params = EncryptionParams {
+        salt,
+        nonce: nonce.into(),
+    };
+    (params, ciphertext)
+}
+
+/// Decrypt and authenticate a sealed payload along with the `aad` it was
+/// sealed with. A wrong passphrase and a tampered payload or `aad` all yield
+/// `DecryptionFailed`.
+pub fn open(
+    params: &EncryptionParams,
+    ciphertext: &[u8],
+    passphrase: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, StegError> {
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &params.salt));
+    cipher
+        .decrypt(
+            Nonce::from_slice(&params.nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| StegError::DecryptionFailed)
+}
+
+fn derive_key(passphrase: &[u8], salt: &[u8; SALT_LEN]) -> Key {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase, salt, &mut key)
+        .expect("Argon2 default parameters accept a 16-byte salt and 32-byte output");
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let (params, ciphertext) = seal(b"rendezvous at 0300", b"passphrase", b"");
+        assert_eq!(ciphertext.len(), 18 + TAG_LEN);
+        assert_eq!(
+            open(&params, &ciphertext, b"passphrase", b"").unwrap(),
+            b"rendezvous at 0300"
+        );
+    }
+
+    #[test]
+    fn test_open_rejects_wrong_passphrase_and_tampering() {
+        let (params, mut ciphertext) = seal(b"rendezvous at 0300", b"passphrase", b"window");
+        assert!(matches!(
+            open(&params, &ciphertext, b"guess", b"window"),
+            Err(StegError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            open(&params, &ciphertext, b"passphrase", b"widow"),
+            Err(StegError::DecryptionFailed)
+        ));
+        ciphertext[0] ^= 1;
+        assert!(matches!(
+            open(&params, &ciphertext, b"passphrase", b"window"),
+            Err(StegError::DecryptionFailed)
+        ));
+    }
+}
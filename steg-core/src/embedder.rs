@@ -0,0 +1,785 @@
+use image::DynamicImage;
+
+use crate::adaptive::AdaptiveEmbedder;
+use crate::compress;
+use crate::config::EmbedConfig;
+use crate::crypto::{self, EncryptionParams, NONCE_LEN, SALT_LEN, TAG_LEN};
+use crate::error::StegError;
+use crate::fec;
+use crate::header::{FecParams, Header, ShardInfo, ShareInfo, Validity, COMPRESSION_LEN};
+use crate::lsb::LsbEmbedder;
+use crate::matrix::MatrixEmbedder;
+use crate::recipient::{self, RecipientKey, RecipientSecret, SealedParams, KEY_LEN};
+use crate::samples::Samples;
+use crate::signature::{self, SignatureStatus, SIGNATURE_BLOCK_LEN};
+
+/// A message read back from a carrier, with the header it was found under.
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub header: Header,
+    pub message: Vec<u8>,
+    /// Bytes repaired by error correction (always 0 without FEC).
+    pub errors_corrected: usize,
+    /// Sender signature found on the message; check it against trusted keys
+    /// with `TrustedSigners::verify`.
+    pub signature: SignatureStatus,
+}
+
+/// How much an encode disturbed the carrier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbedStats {
+    /// Colour samples available to carry data.
+    pub total_values: usize,
+    /// Colour samples that differ between the carrier and the output.
+    pub changed_values: usize,
+}
+
+impl EmbedStats {
+    /// Compare a carrier with the image an embedder produced from it, at the
+    /// carrier's own depth.
+    pub fn between(original: &DynamicImage, encoded: &DynamicImage) -> Self {
+        let (Ok(original), Ok(encoded)) =
+            (Samples::from_image(original), Samples::from_image(encoded))
+        else {
+            return Self {
+                total_values: 0,
+                changed_values: 0,
+            };
+        };
+        let total_values = original.slot_count();
+        let changed_values = (0..total_values.min(encoded.slot_count()))
+            .filter(|&slot| original.get(slot) != encoded.get(slot))
+            .count();
+        Self {
+            total_values,
+            changed_values,
+        }
+    }
+
+    /// Fraction of the carrier values that were modified.
+    pub fn change_rate(&self) -> f64 {
+        if self.total_values == 0 {
+            return 0.0;
+        }
+        self.changed_values as f64 / self.total_values as f64
+    }
+}
+
+/// A steganographic scheme that hides a byte message in a carrier image.
+///
+/// Implementations only move a header and an opaque payload in and out of
+/// the carrier; payload options such as encryption are layered on top by the
+/// provided methods and recorded in the header.
+pub trait Embedder: Send + Sync {
+    /// Identifier used to pick this scheme at runtime (see `by_name`).
+    fn name(&self) -> &'static str;
+
+    /// Configuration the embedder was built with.
+    fn config(&self) -> &EmbedConfig;
+
+    /// Largest payload in bytes that fits behind a header of `header_len` bytes.
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize;
+
+    /// Hide `header` and `payload` in a copy of `img`. The embedder records
+    /// its own scheme id and parameter in the header it writes.
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError>;
+
+    /// Read back a header and payload written by `embed_payload`.
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError>;
+
+    /// Largest message in bytes that `embed` accepts for this image.
+    fn capacity(&self, img: &DynamicImage) -> usize {
+        data_capacity(self, img, Header::for_payload(&[]))
+    }
+
+    /// Largest message in bytes that `embed_encrypted` accepts for this image.
+    fn capacity_encrypted(&self, img: &DynamicImage) -> usize {
+        let header = Header {
+            encryption: Some(EncryptionParams {
+                salt: [0; SALT_LEN],
+                nonce: [0; NONCE_LEN],
+            }),
+            ..Header::for_payload(&[])
+        };
+        data_capacity(self, img, header).saturating_sub(TAG_LEN)
+    }
+
+    /// Largest message in bytes that `embed_sealed` accepts for this image.
+    fn capacity_sealed(&self, img: &DynamicImage) -> usize {
+        let header = Header {
+            sealed: Some(SealedParams {
+                ephemeral: [0; KEY_LEN],
+            }),
+            ..Header::for_payload(&[])
+        };
+        data_capacity(self, img, header).saturating_sub(TAG_LEN)
+    }
+
+    /// Hide `message` in a copy of `img`.
+    fn embed(&self, img: &DynamicImage, message: &[u8]) -> Result<DynamicImage, StegError> {
+        let (header, payload) = prepare(self.config(), message, None, Part::Whole)?;
+        self.embed_payload(img, &header, &payload)
+    }
+
+    /// Encrypt `message` under `passphrase` and hide it in a copy of `img`.
+    fn embed_encrypted(
+        &self,
+        img: &DynamicImage,
+        message: &[u8],
+        passphrase: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let (header, payload) = prepare(
+            self.config(),
+            message,
+            Some(Lock::Passphrase(passphrase)),
+            Part::Whole,
+        )?;
+        self.embed_payload(img, &header, &payload)
+    }
+
+    /// Seal `message` to `recipient`'s public key and hide it in a copy of
+    /// `img`. Only the matching secret key can read it back, not even the
+    /// sender.
+    fn embed_sealed(
+        &self,
+        img: &DynamicImage,
+        message: &[u8],
+        recipient: &RecipientKey,
+    ) -> Result<DynamicImage, StegError> {
+        let (header, payload) = prepare(
+            self.config(),
+            message,
+            Some(Lock::Recipient(recipient)),
+            Part::Whole,
+        )?;
+        self.embed_payload(img, &header, &payload)
+    }
+
+    /// Recover a message and its header, undoing error correction, encryption
+    /// and compression as recorded in the header.
+    /// Fails with `Uncorrectable` or `ChecksumMismatch` when the payload was
+    /// damaged, with `PassphraseRequired` for an encrypted message without a
+    /// passphrase, with `SecretKeyRequired` for a sealed one, with
+    /// `NotEncrypted` for a plain message with a passphrase and with
+    /// `Expired` or `NotYetValid` when the configured clock is outside the
+    /// message's validity window.
+    fn extract_message(
+        &self,
+        img: &DynamicImage,
+        passphrase: Option<&[u8]>,
+    ) -> Result<Decoded, StegError> {
+        open_message(self, img, passphrase.map(Unlock::Passphrase))
+    }
+
+    /// Recover a message sealed to the public key of `secret` by
+    /// `embed_sealed`, with its header.
+    fn extract_sealed(
+        &self,
+        img: &DynamicImage,
+        secret: &RecipientSecret,
+    ) -> Result<Decoded, StegError> {
+        open_message(self, img, Some(Unlock::Secret(secret)))
+    }
+
+    /// Recover a plain message hidden by `embed` with the same configuration.
+    fn extract(&self, img: &DynamicImage) -> Result<Vec<u8>, StegError> {
+        Ok(self.extract_message(img, None)?.message)
+    }
+
+    /// Recover and decrypt a message hidden by `embed_encrypted`.
+    fn extract_encrypted(
+        &self,
+        img: &DynamicImage,
+        passphrase: &[u8],
+    ) -> Result<Vec<u8>, StegError> {
+        Ok(self.extract_message(img, Some(passphrase))?.message)
+    }
+}
+
+/// How a message is encrypted before embedding.
+#[derive(Clone, Copy)]
+pub(crate) enum Lock<'a> {
+    Passphrase(&'a [u8]),
+    Recipient(&'a RecipientKey),
+}
+
+/// What is on hand to decrypt an extracted message.
+#[derive(Clone, Copy)]
+enum Unlock<'a> {
+    Passphrase(&'a [u8]),
+    Secret(&'a RecipientSecret),
+}
+
+/// Undo error correction, encryption and compression as recorded in the
+/// header and check the validity window (see `Embedder::extract_message`).
+fn open_message<E: Embedder + ?Sized>(
+    embedder: &E,
+    img: &DynamicImage,
+    unlock: Option<Unlock<'_>>,
+) -> Result<Decoded, StegError> {
+    let (header, payload) = embedder.extract_payload(img)?;
+    let (payload, errors_corrected) = match header.fec {
+        Some(params) => fec::decode(&payload, params.data_len, params.parity)?,
+        None => (payload, 0),
+    };
+    header.verify_payload(&payload)?;
+    let aad = associated_data(header.validity, header.shard, header.share);
+    let message = match (&header.encryption, &header.sealed, unlock) {
+        (Some(params), _, Some(Unlock::Passphrase(passphrase))) => {
+            crypto::open(params, &payload, passphrase, &aad)?
+        }
+        (Some(_), _, _) => return Err(StegError::PassphraseRequired),
+        (None, Some(params), Some(Unlock::Secret(secret))) => {
+            recipient::open(params, &payload, secret, &aad)?
+        }
+        (None, Some(_), _) => return Err(StegError::SecretKeyRequired),
+        (None, None, Some(_)) => return Err(StegError::NotEncrypted),
+        (None, None, None) => payload,
+    };
+    // Only now, when an encrypted payload has vouched for the window
+    if let Some(validity) = header.validity {
+        validity.check(embedder.config().clock.now())?;
+    }
+    let message = match header.compression {
+        Some(compression) => compress::decompress(
+            compression,
+            &message,
+            embedder.config().decompression_limit,
+        )?,
+        None => message,
+    };
+    let (message, signature) = if header.signed {
+        signature::open(message)?
+    } else {
+        (message, SignatureStatus::Unsigned)
+    };
+    Ok(Decoded {
+        header,
+        message,
+        errors_corrected,
+        signature,
+    })
+}
+
+/// Which part of a message a payload carries.
+#[derive(Clone, Copy)]
+pub(crate) enum Part {
+    Whole,
+    Shard(ShardInfo),
+    Share(ShareInfo),
+}
+
+/// Turn `message`, or the `part` of one, into a header and the bytes to embed
+/// after it: sign it and compress it if configured (compression only when
+/// worthwhile), encrypt it as `lock` says if given, binding in the validity
+/// window and the part, then apply error correction.
+pub(crate) fn prepare(
+    config: &EmbedConfig,
+    message: &[u8],
+    lock: Option<Lock<'_>>,
+    part: Part,
+) -> Result<(Header, Vec<u8>), StegError> {
+    let signed = config
+        .signer
+        .as_ref()
+        .map(|signer| signature::sign(message, signer));
+    let message = signed.as_deref().unwrap_or(message);
+    let (compression, body) = match config
+        .compress
+        .then(|| compress::compress(message, COMPRESSION_LEN))
+        .flatten()
+    {
+        Some((compression, compressed)) => (Some(compression), compressed),
+        None => (None, message.to_vec()),
+    };
+    let validity = config.validity;
+    let (shard, share) = match part {
+        Part::Whole => (None, None),
+        Part::Shard(shard) => (Some(shard), None),
+        Part::Share(share) => (None, Some(share)),
+    };
+    let aad = associated_data(validity, shard, share);
+    let (encryption, sealed, body) = match lock {
+        Some(Lock::Passphrase(passphrase)) => {
+            let (params, ciphertext) = crypto::seal(&body, passphrase, &aad);
+            (Some(params), None, ciphertext)
+        }
+        Some(Lock::Recipient(recipient)) => {
+            let (params, ciphertext) = recipient::seal(&body, recipient, &aad)?;
+            (None, Some(params), ciphertext)
+        }
+        None => (None, None, body),
+    };
+    let header = Header {
+        encryption,
+        sealed,
+        compression,
+        signed: signed.is_some(),
+        container: config.container,
+        validity,
+        shard,
+        share,
+        ..Header::for_payload(&body)
+    };
+    Ok(protect(config, header, &body))
+}
+
+/// Header fields an encrypted payload authenticates along with the message:
+/// the `validity` window and the shard or share it is, so that neither can be
+/// moved or renumbered without breaking decryption. Empty for a whole message
+/// without a window, as payloads were sealed before these fields existed.
+fn associated_data(
+    validity: Option<Validity>,
+    shard: Option<ShardInfo>,
+    share: Option<ShareInfo>,
+) -> Vec<u8> {
+    let mut aad = Vec::new();
+    if let Some(validity) = validity {
+        aad.extend_from_slice(&validity.to_bytes());
+    }
+    if let Some(shard) = shard {
+        aad.extend_from_slice(&shard.to_bytes());
+    }
+    if let Some(share) = share {
+        aad.extend_from_slice(&share.to_bytes());
+    }
+    aad
+}
+
+/// Apply the error correction configured in `config` to `payload`, returning
+/// the header to embed and the bytes to embed after it. The header of an
+/// error-corrected payload is triplicated, since the codewords cannot repair
+/// it.
+fn protect(config: &EmbedConfig, mut header: Header, payload: &[u8]) -> (Header, Vec<u8>) {
+    match config.fec {
+        Some(redundancy) => {
+            let parity = redundancy.parity();
+            let encoded = fec::encode(payload, parity);
+            header.fec = Some(FecParams {
+                parity,
+                data_len: payload.len(),
+            });
+            header.payload_len = encoded.len();
+            header.triplicated = true;
+            (header, encoded)
+        }
+        None => (header, payload.to_vec()),
+    }
+}
+
+/// Largest payload that fits behind `header` once the configured validity
+/// window and error correction are applied, less room for a signature if the
+/// configuration asks for one.
+pub(crate) fn data_capacity<E: Embedder + ?Sized>(
+    embedder: &E,
+    img: &DynamicImage,
+    mut header: Header,
+) -> usize {
+    header.validity = embedder.config().validity;
+    let (header, _) = protect(embedder.config(), header, &[]);
+    let available = embedder.payload_capacity(img, header.encoded_len());
+    let data = match header.fec {
+        Some(params) => fec::max_data_len(available, params.parity),
+        None => available,
+    };
+    match embedder.config().signer {
+        Some(_) => data.saturating_sub(SIGNATURE_BLOCK_LEN),
+        None => data,
+    }
+}
+
+/// Names of the schemes `by_name` can build.
+pub const SCHEMES: &[&str] = &[
+    LsbEmbedder::NAME,
+    MatrixEmbedder::NAME,
+    AdaptiveEmbedder::NAME,
+];
+
+/// Build the scheme called `name` with the given configuration.
+pub fn by_name(name: &str, config: EmbedConfig) -> Result<Box<dyn Embedder>, StegError> {
+    match name {
+        LsbEmbedder::NAME => Ok(Box::new(LsbEmbedder::new(config)?)),
+        MatrixEmbedder::NAME => Ok(Box::new(MatrixEmbedder::new(config)?)),
+        AdaptiveEmbedder::NAME => Ok(Box::new(AdaptiveEmbedder::new(config)?)),
+        _ => Err(StegError::UnknownScheme(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_by_name_roundtrip() {
+        // Textured, so the adaptive scheme has room too
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(60, 60, |x, y| {
+            let v = ((x * 37 + y * 91) % 251) as u8;
+            image::Rgba([v, v.wrapping_mul(3), 255 - v, 255])
+        }));
+        for &name in SCHEMES {
+            let embedder = by_name(name, EmbedConfig::default()).unwrap();
+            assert_eq!(embedder.name(), name);
+            let encoded = embedder.embed(&img, b"picked at runtime").unwrap();
+            assert_eq!(embedder.extract(&encoded).unwrap(), b"picked at runtime");
+        }
+    }
+
+    #[test]
+    fn test_carriers_keep_their_colour_type_and_depth() {
+        use image::{ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+        let v = |x: u32, y: u32| ((x * 37 + y * 91) % 251) as u16;
+        let carriers = [
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(60, 60, |x, y| Luma([v(x, y) as u8]))),
+            DynamicImage::ImageLumaA8(ImageBuffer::from_fn(60, 60, |x, y| {
+                LumaA([v(x, y) as u8, 200])
+            })),
+            DynamicImage::ImageRgb8(ImageBuffer::from_fn(60, 60, |x, y| {
+                let v = v(x, y) as u8;
+                Rgb([v, v.wrapping_mul(3), 255 - v])
+            })),
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(60, 60, |x, y| Luma([v(x, y) * 257]))),
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(60, 60, |x, y| {
+                Rgb([v(x, y) * 257, v(y, x) * 261, 65535 - v(x, y) * 255])
+            })),
+            DynamicImage::ImageRgba16(ImageBuffer::from_fn(60, 60, |x, y| {
+                Rgba([v(x, y) * 257, v(y, x) * 261, 65535 - v(x, y) * 255, 40000])
+            })),
+        ];
+        for img in &carriers {
+            for &name in SCHEMES {
+                let embedder = by_name(name, EmbedConfig::default()).unwrap();
+                let encoded = embedder.embed(img, b"native").unwrap();
+                assert_eq!(encoded.color(), img.color(), "{} in {:?}", name, img.color());
+                assert_eq!(embedder.extract(&encoded).unwrap(), b"native");
+            }
+            // Only the low bits of colour samples change, at the carrier's depth
+            let lsb = by_name(LsbEmbedder::NAME, EmbedConfig::default()).unwrap();
+            let message = vec![0xA5; lsb.capacity(img)];
+            let encoded = lsb.embed(img, &message).unwrap();
+            let (before, after) = (
+                Samples::from_image(img).unwrap(),
+                Samples::from_image(&encoded).unwrap(),
+            );
+            assert!((0..before.slot_count()).all(|s| before.get(s) >> 1 == after.get(s) >> 1));
+            assert_eq!(encoded.as_bytes().len(), img.as_bytes().len());
+            let stats = EmbedStats::between(img, &encoded);
+            assert_eq!(stats.total_values, before.slot_count());
+            assert!(stats.changed_values > 0);
+        }
+
+        // One slot per grey pixel, three per colour one; 22 header bytes
+        let lsb = LsbEmbedder::default();
+        assert_eq!(lsb.capacity(&carriers[0]), 3600 / 8 - 22);
+        assert_eq!(lsb.capacity(&carriers[1]), 3600 / 8 - 22);
+        assert_eq!(lsb.capacity(&carriers[4]), 10800 / 8 - 22);
+        assert!(matches!(
+            lsb.embed(&carriers[0].to_rgb32f().into(), b"x"),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+    }
+
+    #[test]
+    fn test_encryption_composes_with_any_configuration() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            60,
+            60,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let config = EmbedConfig::default()
+            .with_bits_per_channel(3)
+            .with_key(b"slot key");
+        let embedder = by_name(LsbEmbedder::NAME, config).unwrap();
+        let encoded = embedder
+            .embed_encrypted(&img, b"sealed and scattered", b"passphrase")
+            .unwrap();
+        let decoded = embedder
+            .extract_message(&encoded, Some(b"passphrase"))
+            .unwrap();
+        assert!(decoded.header.is_encrypted());
+        assert_eq!(decoded.header.scheme_param, 3);
+        assert_eq!(decoded.message, b"sealed and scattered");
+    }
+
+    #[test]
+    fn test_sealed_message_needs_the_secret_key() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            60,
+            60,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let embedder = by_name(MatrixEmbedder::NAME, EmbedConfig::default()).unwrap();
+        let secret = RecipientSecret::generate();
+        let message = vec![b'k'; embedder.capacity_sealed(&img)];
+        let encoded = embedder
+            .embed_sealed(&img, &message, &secret.public_key())
+            .unwrap();
+
+        let decoded = embedder.extract_sealed(&encoded, &secret).unwrap();
+        assert!(decoded.header.is_encrypted());
+        assert_eq!(decoded.message, message);
+        assert!(matches!(
+            embedder.extract_sealed(&encoded, &RecipientSecret::generate()),
+            Err(StegError::DecryptionFailed)
+        ));
+        for passphrase in [None, Some(&b"pw"[..])] {
+            assert!(matches!(
+                embedder.extract_message(&encoded, passphrase),
+                Err(StegError::SecretKeyRequired)
+            ));
+        }
+        let plain = embedder.embed(&img, b"open").unwrap();
+        assert!(matches!(
+            embedder.extract_sealed(&plain, &secret),
+            Err(StegError::NotEncrypted)
+        ));
+        let locked = embedder.embed_encrypted(&img, b"open", b"pw").unwrap();
+        assert!(matches!(
+            embedder.extract_sealed(&locked, &secret),
+            Err(StegError::PassphraseRequired)
+        ));
+        assert!(embedder
+            .embed_sealed(&img, &[message, vec![0]].concat(), &secret.public_key())
+            .is_err());
+    }
+
+    #[test]
+    fn test_signature_travels_inside_the_payload() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            60,
+            60,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let signer = crate::SignerSecret::generate();
+        let config = EmbedConfig::default()
+            .with_compression(true)
+            .with_signer(signer.clone());
+        let embedder = by_name(LsbEmbedder::NAME, config).unwrap();
+        let plain = by_name(LsbEmbedder::NAME, EmbedConfig::default()).unwrap();
+        assert_eq!(
+            embedder.capacity_encrypted(&img),
+            plain.capacity_encrypted(&img) - SIGNATURE_BLOCK_LEN
+        );
+
+        let message = vec![b'z'; embedder.capacity_encrypted(&img)];
+        let encoded = embedder.embed_encrypted(&img, &message, b"pw").unwrap();
+        // Decoding needs no signing configuration
+        let decoded = plain.extract_message(&encoded, Some(b"pw")).unwrap();
+        assert!(decoded.header.signed);
+        assert_eq!(decoded.message, message);
+        assert_eq!(
+            decoded.signature,
+            SignatureStatus::Valid(signer.public_key())
+        );
+
+        let unsigned = plain.embed(&img, b"anonymous").unwrap();
+        let decoded = plain.extract_message(&unsigned, None).unwrap();
+        assert_eq!(decoded.signature, SignatureStatus::Unsigned);
+    }
+
+    #[test]
+    fn test_container_flag_is_recorded() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            40,
+            40,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let container = crate::Container::new(b"col,val\n1,2\n".to_vec()).with_filename("a.csv");
+        let config = EmbedConfig::default()
+            .with_container(true)
+            .with_compression(true);
+        let embedder = by_name(MatrixEmbedder::NAME, config).unwrap();
+        let encoded = embedder.embed(&img, &container.to_bytes()).unwrap();
+
+        let plain = by_name(MatrixEmbedder::NAME, EmbedConfig::default()).unwrap();
+        let decoded = plain.extract_message(&encoded, None).unwrap();
+        assert!(decoded.header.container);
+        assert_eq!(
+            crate::Container::from_bytes(&decoded.message).unwrap(),
+            container
+        );
+        let raw = plain.embed(&img, b"raw bytes").unwrap();
+        assert!(!plain.extract_message(&raw, None).unwrap().header.container);
+    }
+
+    #[test]
+    fn test_validity_window_is_enforced_and_authenticated() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            40,
+            40,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let config = EmbedConfig::default()
+            .with_not_before(1_000)
+            .with_expires_at(2_000);
+        let embedder = by_name(LsbEmbedder::NAME, config).unwrap();
+        let plain = by_name(LsbEmbedder::NAME, EmbedConfig::default()).unwrap();
+        assert_eq!(embedder.capacity(&img), plain.capacity(&img) - 16);
+        let at = |secs| {
+            by_name(
+                LsbEmbedder::NAME,
+                EmbedConfig::default().with_clock(Clock::Fixed(secs)),
+            )
+            .unwrap()
+        };
+
+        let encoded = embedder.embed_encrypted(&img, b"burn after reading", b"pw").unwrap();
+        let decoded = at(1_500).extract_message(&encoded, Some(b"pw")).unwrap();
+        assert_eq!(decoded.message, b"burn after reading");
+        assert_eq!(
+            decoded.header.validity,
+            Some(Validity {
+                not_before: Some(1_000),
+                expires_at: Some(2_000),
+            })
+        );
+        assert!(matches!(
+            at(999).extract_message(&encoded, Some(b"pw")),
+            Err(StegError::NotYetValid { not_before: 1_000 })
+        ));
+        assert!(matches!(
+            at(2_000).extract_message(&encoded, Some(b"pw")),
+            Err(StegError::Expired { expires_at: 2_000 })
+        ));
+
+        // Stretching the window of an encrypted payload breaks decryption
+        let (mut header, payload) = prepare(
+            embedder.config(),
+            b"burn",
+            Some(Lock::Passphrase(b"pw")),
+            Part::Whole,
+        )
+        .unwrap();
+        header.validity = Some(Validity {
+            not_before: Some(1_000),
+            expires_at: Some(9_000),
+        });
+        let stretched = embedder.embed_payload(&img, &header, &payload).unwrap();
+        assert!(matches!(
+            at(2_500).extract_message(&stretched, Some(b"pw")),
+            Err(StegError::DecryptionFailed)
+        ));
+
+        let unlocked = embedder.embed(&img, b"plain drop").unwrap();
+        assert!(matches!(
+            at(5_000).extract(&unlocked),
+            Err(StegError::Expired { .. })
+        ));
+        assert!(matches!(
+            by_name(
+                LsbEmbedder::NAME,
+                EmbedConfig::default().with_not_before(5).with_expires_at(5)
+            ),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_fec_repairs_flipped_bits_and_reports_them() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            60,
+            60,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let config = EmbedConfig::default().with_fec(fec::Redundancy::Medium);
+        let embedder = by_name(LsbEmbedder::NAME, config).unwrap();
+        let message: Vec<u8> = (0..=255).cycle().take(embedder.capacity(&img)).collect();
+        let mut encoded = embedder.embed(&img, &message).unwrap().into_rgba8();
+        // Flip red in a run of 30 pixels well past the 81-byte triplicated
+        // header: bits 930..=1017 of the stream, i.e. 12 consecutive bytes
+        for x in 10..40 {
+            encoded.get_pixel_mut(x, 5)[0] ^= 1;
+        }
+        let decoded = embedder
+            .extract_message(&DynamicImage::ImageRgba8(encoded), None)
+            .unwrap();
+        assert_eq!(decoded.message, message);
+        assert_eq!(decoded.errors_corrected, 12);
+        assert!(embedder.capacity(&img) < LsbEmbedder::default().capacity(&img));
+        assert!(embedder.embed(&img, &[message, vec![0]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_fec_header_survives_flipped_bits() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            60,
+            60,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let config = EmbedConfig::default().with_fec(fec::Redundancy::Low);
+        let embedder = by_name(LsbEmbedder::NAME, config).unwrap();
+        let message = b"the header is the weak point".to_vec();
+        let mut encoded = embedder.embed(&img, &message).unwrap().into_rgba8();
+        // Flip red in the first two rows: every third bit of the first 45
+        // stream bytes, all inside the header, and in every copy of its bytes
+        for y in 0..2 {
+            for x in 0..60 {
+                encoded.get_pixel_mut(x, y)[0] ^= 1;
+            }
+        }
+        let decoded = embedder
+            .extract_message(&DynamicImage::ImageRgba8(encoded), None)
+            .unwrap();
+        assert_eq!(decoded.message, message);
+        assert_eq!(decoded.errors_corrected, 0);
+    }
+
+    #[test]
+    fn test_compression_is_transparent_and_extends_capacity() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            40,
+            40,
+            image::Rgba([90, 140, 200, 255]),
+        ));
+        let config = EmbedConfig::default().with_compression(true);
+        let embedder = by_name(LsbEmbedder::NAME, config.clone()).unwrap();
+        let message = b"the package is under the third bench. ".repeat(40);
+        assert!(message.len() > embedder.capacity(&img));
+        let plain = by_name(LsbEmbedder::NAME, EmbedConfig::default()).unwrap();
+        assert!(matches!(
+            plain.embed(&img, &message),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+
+        for passphrase in [None, Some(&b"pw"[..])] {
+            let encoded = match passphrase {
+                Some(p) => embedder.embed_encrypted(&img, &message, p).unwrap(),
+                None => embedder.embed(&img, &message).unwrap(),
+            };
+            // Decoding needs no compression setting; the header says it all
+            let decoded = plain.extract_message(&encoded, passphrase).unwrap();
+            assert!(decoded.header.compression.is_some());
+            assert_eq!(decoded.message, message);
+
+            let strict = by_name(
+                LsbEmbedder::NAME,
+                EmbedConfig::default().with_decompression_limit(100),
+            )
+            .unwrap();
+            assert!(matches!(
+                strict.extract_message(&encoded, passphrase),
+                Err(StegError::DecompressionLimit { limit: 100 })
+            ));
+        }
+
+        // Incompressible messages are stored as-is
+        let encoded = embedder.embed(&img, b"q9").unwrap();
+        let decoded = plain.extract_message(&encoded, None).unwrap();
+        assert_eq!(decoded.header.compression, None);
+    }
+
+    #[test]
+    fn test_by_name_unknown() {
+        assert!(matches!(
+            by_name("dct", EmbedConfig::default()),
+            Err(StegError::UnknownScheme(name)) if name == "dct"
+        ));
+    }
+}
@@ -8,15 +8,90 @@ pub enum StegError {
     #[error("image error: {0}")]
     Image(#[from] image::ImageError),
 
+    #[error("unsupported carrier image: {0}")]
+    UnsupportedCarrier(String),
+
     #[error("no hidden message found (magic marker mismatch)")]
     NoMessageFound,
 
+    #[error("hidden message header is corrupted (checksum mismatch)")]
+    CorruptHeader,
+
+    #[error("unsupported message header: {0}")]
+    UnsupportedHeader(String),
+
+    #[error("message present but corrupted (payload checksum mismatch)")]
+    ChecksumMismatch,
+
+    #[error("message present but corrupted beyond what error correction can repair")]
+    Uncorrectable,
+
+    #[error("hidden message could not be decompressed: {0}")]
+    Decompression(String),
+
+    #[error("hidden message decompresses to more than the {limit}-byte limit")]
+    DecompressionLimit { limit: usize },
+
+    #[error("dead drop expired at {}", crate::clock::format_unix_time(*.expires_at))]
+    Expired { expires_at: u64 },
+
+    #[error("dead drop cannot be read before {}", crate::clock::format_unix_time(*.not_before))]
+    NotYetValid { not_before: u64 },
+
+    #[error("malformed message container: {0}")]
+    MalformedContainer(String),
+
     #[error("invalid message length encoded in header")]
     InvalidLength,
 
+    #[error("unsupported bit depth: {0} bits per channel (expected 1-4)")]
+    InvalidBitDepth(u8),
+
+    #[error("unknown embedding scheme: {0}")]
+    UnknownScheme(String),
+
+    #[error("sharded message incomplete: missing shard(s) {missing:?} of {total}")]
+    MissingShards { missing: Vec<u16>, total: u16 },
+
+    #[error("shard(s) {0:?} supplied more than once")]
+    DuplicateShards(Vec<u16>),
+
+    #[error("shards do not belong together: {0}")]
+    ShardMismatch(String),
+
+    #[error("not enough shares: found {found}, {threshold} needed")]
+    NotEnoughShares { found: usize, threshold: u8 },
+
+    #[error("shares do not belong together: {0}")]
+    ShareMismatch(String),
+
+    #[error("unsuitable output file: {0}")]
+    OutputFormat(String),
+
+    #[error("written image does not read back as embedded; the hidden message would be lost")]
+    RoundTripFailed,
+
+    #[error("incompatible embedding options: {0}")]
+    IncompatibleOptions(String),
+
     #[error("UTF-8 decode error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
 
+    #[error("decryption failed: wrong passphrase or tampered payload")]
+    DecryptionFailed,
+
+    #[error("hidden message is encrypted; a passphrase is required")]
+    PassphraseRequired,
+
+    #[error("hidden message is sealed to a recipient key; their secret key is required")]
+    SecretKeyRequired,
+
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("hidden message is not encrypted")]
+    NotEncrypted,
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 }
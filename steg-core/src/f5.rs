@@ -0,0 +1,370 @@
+use image::DynamicImage;
+
+use crate::config::{EmbedConfig, EmbedStrategy};
+use crate::embedder::Embedder;
+use crate::error::StegError;
+use crate::header::{FormatVersion, Header};
+use crate::jpeg::{coefficient, plane_value};
+use crate::keyed::SlotPermutation;
+use crate::lsb::to_bits;
+
+/// F5 embedding in the quantised DCT coefficients of a JPEG.
+///
+/// Only non-zero AC coefficients carry data. Each holds one bit, its low bit
+/// for positive values and the inverse for negative ones, and a bit is
+/// changed by moving the coefficient one step towards zero, so the histogram
+/// keeps its shape instead of showing the pairs of values LSB replacement
+/// leaves. A coefficient that reaches zero no longer counts (shrinkage): the
+/// decoder skips it, so the embedder writes the same bits again further on.
+///
+/// The payload uses matrix embedding like the matrix scheme, `k` bits in
+/// each group of `2^k - 1` non-zero coefficients at the cost of at most one
+/// change, with `k` picked per message and recorded in the header. The
+/// header itself is written one bit per coefficient.
+///
+/// The embedder works on the coefficient plane of a `JpegImage` (see
+/// `Carrier::embedder`). A key scatters the bits over a key-driven order of
+/// the coefficients. Changes always shrink coefficients, so the scheme
+/// requires LSB replacement at 1 bit per channel.
+#[derive(Debug, Clone, Default)]
+pub struct F5Embedder {
+    config: EmbedConfig,
+}
+
+impl F5Embedder {
+    pub const NAME: &'static str = "f5";
+
+    /// Scheme id recorded in the header.
+    pub const ID: u8 = 4;
+
+    /// Largest code parameter tried.
+    pub const MAX_K: u8 = 16;
+
+    pub fn new(config: EmbedConfig) -> Result<Self, StegError> {
+        config.validate()?;
+        if config.bits_per_channel != 1 {
+            return Err(StegError::InvalidBitDepth(config.bits_per_channel));
+        }
+        if config.strategy != EmbedStrategy::Replace {
+            return Err(StegError::IncompatibleOptions(
+                "F5 always moves coefficients towards zero; LSB matching does not apply".into(),
+            ));
+        }
+        Ok(Self { config })
+    }
+}
+
+impl Embedder for F5Embedder {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn config(&self) -> &EmbedConfig {
+        &self.config
+    }
+
+    /// Counts only coefficients of magnitude 2 or more, which take a bit
+    /// whatever it is. Coefficients of 1 that happen to survive leave room
+    /// for larger matrix codes.
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize {
+        match Coefficients::new(img, &self.config) {
+            Ok(coefficients) => {
+                let sure = coefficients
+                    .order
+                    .iter()
+                    .filter(|&&i| coefficients.values[i].unsigned_abs() >= 2)
+                    .count();
+                sure.saturating_sub(header_len * 8) / 8
+            }
+            Err(_) => 0,
+        }
+    }
+
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let mut header = header.clone();
+        if header.version == FormatVersion::Legacy {
+            return Err(StegError::UnsupportedHeader(
+                "legacy headers only support the LSB scheme".into(),
+            ));
+        }
+        let header_len = header.encoded_len();
+        let too_large = || StegError::MessageTooLarge {
+            needed: (header_len + payload.len()) * 8,
+            capacity: self.payload_capacity(img, header_len) * 8,
+        };
+        let coefficients = Coefficients::new(img, &self.config)?;
+        let bits: Vec<u8> = to_bits(payload).collect();
+        let nonzero = coefficients
+            .order
+            .iter()
+            .filter(|&&i| coefficients.values[i] != 0)
+            .count();
+
+        // The header records k, so try each k on a copy, largest first
+        header.scheme = Self::ID;
+        for k in (1..=Self::MAX_K).rev() {
+            if bits.len().div_ceil(k as usize) * ((1 << k) - 1) > nonzero {
+                continue;
+            }
+            header.scheme_param = k;
+            let mut attempt = coefficients.clone();
+            let cursor = to_bits(&header.to_bytes()?)
+                .try_fold(0, |cursor, bit| attempt.embed_group(cursor, 1, &[bit]));
+            let Some(cursor) = cursor else {
+                return Err(too_large());
+            };
+            let fits = bits
+                .chunks(k as usize)
+                .try_fold(cursor, |cursor, word| attempt.embed_group(cursor, k, word))
+                .is_some();
+            if fits {
+                return Ok(attempt.into_image());
+            }
+        }
+        Err(too_large())
+    }
+
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError> {
+        let coefficients = Coefficients::new(img, &self.config)?;
+        // The bits of the non-zero coefficients, in embedding order
+        let bits: Vec<u8> = coefficients
+            .order
+            .iter()
+            .map(|&i| coefficients.values[i])
+            .filter(|&c| c != 0)
+            .map(bit_of)
+            .collect();
+        let read_bytes = |offset: usize, count: usize| -> Vec<u8> {
+            bits[offset * 8..(offset + count) * 8]
+                .chunks(8)
+                .map(|byte| byte.iter().fold(0u8, |acc, &bit| (acc << 1) | bit))
+                .collect()
+        };
+        let header = Header::read(read_bytes, bits.len() / 8)?;
+
+        if header.version == FormatVersion::Legacy || header.scheme != Self::ID {
+            return Err(StegError::UnsupportedHeader(format!(
+                "embedding scheme {}",
+                header.scheme
+            )));
+        }
+        let k = header.scheme_param;
+        if !(1..=Self::MAX_K).contains(&k) {
+            return Err(StegError::UnsupportedHeader(format!("F5 code k = {}", k)));
+        }
+
+        let start = header.encoded_len() * 8;
+        let n = (1usize << k) - 1;
+        let payload_bits = header.payload_len * 8;
+        let groups = payload_bits.div_ceil(k as usize);
+        if start + groups * n > bits.len() {
+            return Err(StegError::InvalidLength);
+        }
+        let mut payload_bits_read = Vec::with_capacity(groups * k as usize);
+        for group in bits[start..start + groups * n].chunks(n) {
+            let word = syndrome(group.iter().copied());
+            payload_bits_read.extend((0..k).rev().map(|i| ((word >> i) & 1) as u8));
+        }
+        let payload = payload_bits_read[..payload_bits]
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, &bit| (acc << 1) | bit))
+            .collect();
+        Ok((header, payload))
+    }
+}
+
+/// Bit carried by a non-zero coefficient.
+fn bit_of(c: i16) -> u8 {
+    (c & 1) as u8 ^ (c < 0) as u8
+}
+
+/// XOR of the 1-based positions of the set bits.
+fn syndrome(bits: impl Iterator<Item = u8>) -> usize {
+    bits.enumerate()
+        .filter(|&(_, bit)| bit == 1)
+        .fold(0, |acc, (i, _)| acc ^ (i + 1))
+}
+
+/// The coefficients of a plane with the AC positions in embedding order.
+#[derive(Clone)]
+struct Coefficients {
+    values: Vec<i16>,
+    /// Indices into `values` of every AC coefficient, raster or key order.
+    order: Vec<usize>,
+}
+
+impl Coefficients {
+    fn new(img: &DynamicImage, config: &EmbedConfig) -> Result<Self, StegError> {
+        let plane = img
+            .as_luma16()
+            .filter(|plane| plane.width() == 64)
+            .ok_or_else(|| {
+                StegError::UnsupportedCarrier(
+                    "F5 embedding needs the coefficients of a JPEG image".into(),
+                )
+            })?;
+        let values: Vec<i16> = plane.iter().map(|&v| coefficient(v)).collect();
+        let ac: Vec<usize> = (0..values.len()).filter(|i| i % 64 != 0).collect();
+        let order = match &config.key {
+            Some(key) => {
+                let perm = SlotPermutation::new(ac.len(), key);
+                (0..ac.len()).map(|slot| ac[perm.get(slot)]).collect()
+            }
+            None => ac,
+        };
+        Ok(Self { values, order })
+    }
+
+    /// Embed `word` in the next group of `2^k - 1` non-zero coefficients
+    /// from `cursor`, a position in `order`, and return where the group
+    /// ended, or `None` if the coefficients run out. A change that shrinks a
+    /// coefficient to zero is redone over the group that remains.
+    fn embed_group(&mut self, cursor: usize, k: u8, word: &[u8]) -> Option<usize> {
+        let n = (1usize << k) - 1;
+        let target = word
+            .iter()
+            .fold(0usize, |acc, &bit| (acc << 1) | bit as usize);
+        // A short final word is read back in the high bits
+        let target = target << (k as usize - word.len());
+        loop {
+            let group: Vec<usize> = self.order[cursor..]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &i)| self.values[i] != 0)
+                .map(|(offset, _)| cursor + offset)
+                .take(n)
+                .collect();
+            if group.len() < n {
+                return None;
+            }
+            let end = group[n - 1] + 1;
+            let bits = group.iter().map(|&p| bit_of(self.values[self.order[p]]));
+            let flip = syndrome(bits) ^ target;
+            if flip == 0 {
+                return Some(end);
+            }
+            let value = &mut self.values[self.order[group[flip - 1]]];
+            *value -= value.signum();
+            if *value != 0 {
+                return Some(end);
+            }
+        }
+    }
+
+    fn into_image(self) -> DynamicImage {
+        let rows = (self.values.len() / 64) as u32;
+        let data = self.values.into_iter().map(plane_value).collect();
+        DynamicImage::ImageLuma16(image::ImageBuffer::from_raw(64, rows, data).expect("same size"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{load_carrier, Carrier};
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{Rgb, RgbImage};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn jpeg_carrier(w: u32, h: u32) -> Carrier {
+        let mut rng = ChaCha20Rng::seed_from_u64(23);
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+            let noise: u8 = rng.gen_range(0..96);
+            Rgb([x as u8 + noise, y as u8, ((x ^ y) as u8) / 2 + noise])
+        }));
+        let mut bytes = Vec::new();
+        img.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 85))
+            .unwrap();
+        load_carrier(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip_through_the_jpeg_file() {
+        let carrier = jpeg_carrier(128, 96);
+        assert!(matches!(carrier, Carrier::Jpeg(_)));
+        let embedder = carrier.embedder("lsb", EmbedConfig::default()).unwrap();
+        assert_eq!(embedder.name(), F5Embedder::NAME);
+        let capacity = embedder.capacity(carrier.image());
+        assert!(capacity > 100, "capacity {}", capacity);
+
+        for message in [
+            Vec::new(),
+            b"meet at the pier".to_vec(),
+            vec![0xA5; capacity],
+        ] {
+            let encoded = embedder.embed(carrier.image(), &message).unwrap();
+            let bytes = carrier
+                .with_image(encoded.clone())
+                .unwrap()
+                .to_bytes()
+                .unwrap();
+            let reread = load_carrier(&bytes).unwrap();
+            assert_eq!(embedder.extract(reread.image()).unwrap(), message);
+
+            // Only AC coefficients move, each one step towards zero
+            let before = carrier.image().as_luma16().unwrap();
+            let after = encoded.as_luma16().unwrap();
+            for (i, (&a, &b)) in before.iter().zip(after.iter()).enumerate() {
+                let (a, b) = (coefficient(a), coefficient(b));
+                if a != b {
+                    assert_ne!(i % 64, 0, "DC coefficient changed");
+                    assert_eq!((b - a).abs(), 1);
+                    assert!(b.abs() < a.abs());
+                }
+            }
+            let pixels = image::load_from_memory(&bytes).unwrap();
+            assert_eq!((pixels.width(), pixels.height()), (128, 96));
+        }
+    }
+
+    #[test]
+    fn test_keyed_embedding_survives_shrinkage() {
+        let carrier = jpeg_carrier(64, 64);
+        let config = EmbedConfig::default().with_key(b"harbour");
+        let embedder = carrier.embedder("lsb", config).unwrap();
+        let capacity = embedder.capacity(carrier.image());
+        let mut rng = ChaCha20Rng::seed_from_u64(24);
+        let message: Vec<u8> = (0..capacity).map(|_| rng.gen()).collect();
+        let encoded = embedder.embed(carrier.image(), &message).unwrap();
+        let shrunk = carrier
+            .image()
+            .as_luma16()
+            .unwrap()
+            .iter()
+            .zip(encoded.as_luma16().unwrap().iter())
+            .filter(|&(&a, &b)| coefficient(a) != 0 && coefficient(b) == 0)
+            .count();
+        assert!(shrunk > 0);
+        assert_eq!(embedder.extract(&encoded).unwrap(), message);
+        let unkeyed = F5Embedder::default();
+        assert!(unkeyed.extract(&encoded).is_err());
+
+        let nonzero = carrier.image().as_luma16().unwrap().len() / 8;
+        assert!(matches!(
+            embedder.embed(carrier.image(), &vec![1; nonzero]),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_needs_a_coefficient_plane_and_replacement() {
+        let rgb = DynamicImage::ImageRgb8(RgbImage::new(16, 16));
+        let embedder = F5Embedder::default();
+        assert_eq!(embedder.capacity(&rgb), 0);
+        assert!(matches!(
+            embedder.extract(&rgb),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+        let matching = EmbedConfig::default().with_strategy(EmbedStrategy::Match { seed: None });
+        assert!(matches!(
+            F5Embedder::new(matching),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+    }
+}
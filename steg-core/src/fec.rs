@@ -0,0 +1,385 @@
+use crate::error::StegError;
+
+/// Longest codeword over GF(2^8).
+const CODEWORD_LEN: usize = 255;
+
+/// x^8 + x^4 + x^3 + x^2 + 1, the usual primitive polynomial for GF(2^8).
+const PRIMITIVE: u16 = 0x11d;
+
+struct Tables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const TABLES: Tables = build_tables();
+
+const fn build_tables() -> Tables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    Tables { exp, log }
+}
+
+/// How much of the embedded payload is spent on Reed-Solomon parity. Each
+/// codeword carries `parity` check bytes and repairs `parity / 2` corrupted
+/// bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redundancy {
+    /// 16 parity bytes per codeword: repairs 8 bytes in 255 (~3%).
+    Low,
+    /// 32 parity bytes per codeword: repairs 16 bytes in 255 (~6%).
+    Medium,
+    /// 64 parity bytes per codeword: repairs 32 bytes in 255 (~12%).
+    High,
+}
+
+impl Redundancy {
+    /// Names accepted by `by_name`.
+    pub const NAMES: &'static [&'static str] = &["low", "medium", "high"];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "low" => Some(Redundancy::Low),
+            "medium" => Some(Redundancy::Medium),
+            "high" => Some(Redundancy::High),
+            _ => None,
+        }
+    }
+
+    /// Parity bytes per codeword.
+    pub fn parity(&self) -> u8 {
+        match self {
+            Redundancy::Low => 16,
+            Redundancy::Medium => 32,
+            Redundancy::High => 64,
+        }
+    }
+}
+
+/// Number of codewords used for `data_len` bytes.
+fn block_count(data_len: usize, parity: u8) -> usize {
+    data_len.div_ceil(CODEWORD_LEN - parity as usize)
+}
+
+/// Data bytes in each codeword; blocks are balanced to within one byte.
+fn block_sizes(data_len: usize, parity: u8) -> Vec<usize> {
+    let blocks = block_count(data_len, parity);
+    (0..blocks)
+        .map(|b| data_len / blocks + usize::from(b < data_len % blocks))
+        .collect()
+}
+
+/// Size of `data_len` bytes once encoded with `parity` bytes per codeword.
+pub fn encoded_len(data_len: usize, parity: u8) -> usize {
+    data_len + block_count(data_len, parity) * parity as usize
+}
+
+/// Largest data length whose encoding fits in `available` bytes.
+pub fn max_data_len(available: usize, parity: u8) -> usize {
+    let parity = parity as usize;
+    let full_blocks = available / CODEWORD_LEN;
+    let filled = full_blocks * (CODEWORD_LEN - parity);
+    let with_partial = available.saturating_sub((full_blocks + 1) * parity);
+    filled.max(with_partial)
+}
+
+/// Encode `data` into Reed-Solomon codewords over GF(2^8) of up to 255 bytes
+/// with `parity` check bytes each. Codewords are interleaved byte by byte so
+/// a run of damaged carrier pixels is spread over many codewords instead of
+/// exhausting one.
+pub fn encode(data: &[u8], parity: u8) -> Vec<u8> {
+    let generator = generator_poly(parity as usize);
+    let mut codewords = Vec::new();
+    let mut rest = data;
+    for size in block_sizes(data.len(), parity) {
+        let (chunk, tail) = rest.split_at(size);
+        rest = tail;
+        let mut codeword = chunk.to_vec();
+        codeword.extend(parity_bytes(chunk, &generator));
+        codewords.push(codeword);
+    }
+    interleave(&codewords)
+}
+
+/// Decode interleaved codewords back into `data_len` bytes, repairing what
+/// the parity allows. Returns the data and the number of bytes corrected.
+pub fn decode(encoded: &[u8], data_len: usize, parity: u8) -> Result<(Vec<u8>, usize), StegError> {
+    if encoded.len() != encoded_len(data_len, parity) {
+        return Err(StegError::InvalidLength);
+    }
+    let sizes = block_sizes(data_len, parity);
+    let lengths: Vec<usize> = sizes.iter().map(|size| size + parity as usize).collect();
+    let mut data = Vec::with_capacity(data_len);
+    let mut corrected = 0;
+    for (mut codeword, size) in deinterleave(encoded, &lengths).into_iter().zip(sizes) {
+        corrected += correct(&mut codeword, parity as usize)?;
+        data.extend_from_slice(&codeword[..size]);
+    }
+    Ok((data, corrected))
+}
+
+/// Emit byte `j` of every codeword before byte `j + 1` of any.
+fn interleave(codewords: &[Vec<u8>]) -> Vec<u8> {
+    let longest = codewords.iter().map(Vec::len).max().unwrap_or(0);
+    let mut out = Vec::with_capacity(codewords.iter().map(Vec::len).sum());
+    for j in 0..longest {
+        for codeword in codewords {
+            if let Some(&byte) = codeword.get(j) {
+                out.push(byte);
+            }
+        }
+    }
+    out
+}
+
+fn deinterleave(stream: &[u8], lengths: &[usize]) -> Vec<Vec<u8>> {
+    let mut codewords: Vec<Vec<u8>> = lengths.iter().map(|&len| Vec::with_capacity(len)).collect();
+    let longest = lengths.iter().copied().max().unwrap_or(0);
+    let mut bytes = stream.iter();
+    for j in 0..longest {
+        for (codeword, &len) in codewords.iter_mut().zip(lengths) {
+            if j < len {
+                codeword.push(*bytes.next().expect("stream length checked against lengths"));
+            }
+        }
+    }
+    codewords
+}
+
+// Polynomials below are coefficient vectors, highest degree first.
+
+/// Product in GF(256).
+pub(crate) fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+}
+
+/// Quotient in GF(256); `b` must be non-zero.
+pub(crate) fn div(a: u8, b: u8) -> u8 {
+    debug_assert!(b != 0);
+    if a == 0 {
+        return 0;
+    }
+    TABLES.exp[(TABLES.log[a as usize] as usize + 255 - TABLES.log[b as usize] as usize) % 255]
+}
+
+/// alpha^power.
+fn alpha(power: usize) -> u8 {
+    TABLES.exp[power % 255]
+}
+
+fn poly_scale(p: &[u8], factor: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, factor)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut out = vec![0u8; len];
+    for (i, &c) in p.iter().enumerate() {
+        out[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        out[i + len - q.len()] ^= c;
+    }
+    out
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; p.len() + q.len() - 1];
+    for (i, &a) in p.iter().enumerate() {
+        for (j, &b) in q.iter().enumerate() {
+            out[i + j] ^= mul(a, b);
+        }
+    }
+    out
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().fold(0, |acc, &c| mul(acc, x) ^ c)
+}
+
+/// prod (x - alpha^i) for i in 0..nsym.
+fn generator_poly(nsym: usize) -> Vec<u8> {
+    (0..nsym).fold(vec![1], |g, i| poly_mul(&g, &[1, alpha(i)]))
+}
+
+/// Remainder of data * x^nsym divided by the generator.
+fn parity_bytes(data: &[u8], generator: &[u8]) -> Vec<u8> {
+    let nsym = generator.len() - 1;
+    let mut work = data.to_vec();
+    work.resize(data.len() + nsym, 0);
+    for i in 0..data.len() {
+        let coef = work[i];
+        if coef != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                work[i + j] ^= mul(g, coef);
+            }
+        }
+    }
+    work.split_off(data.len())
+}
+
+/// Syndromes with a leading zero, so index `i + 1` is the codeword at alpha^i.
+fn syndromes(codeword: &[u8], nsym: usize) -> Vec<u8> {
+    let mut synd = vec![0u8; nsym + 1];
+    for i in 0..nsym {
+        synd[i + 1] = poly_eval(codeword, alpha(i));
+    }
+    synd
+}
+
+/// Repair `codeword` in place (Berlekamp-Massey, Chien search, Forney).
+/// Returns the number of corrected bytes.
+fn correct(codeword: &mut [u8], nsym: usize) -> Result<usize, StegError> {
+    let synd = syndromes(codeword, nsym);
+    if synd.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    let locator = error_locator(&synd, nsym);
+    let errors = locator.len() - 1;
+    if errors * 2 > nsym {
+        return Err(StegError::Uncorrectable);
+    }
+
+    // Chien search over the reversed locator: a root at alpha^i marks an
+    // error at coefficient i, counted from the end of the codeword.
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let n = codeword.len();
+    let coef_pos: Vec<usize> = (0..n)
+        .filter(|&i| poly_eval(&reversed, alpha(i)) == 0)
+        .collect();
+    if coef_pos.len() != errors {
+        return Err(StegError::Uncorrectable);
+    }
+
+    // Forney: error magnitudes from the evaluator polynomial.
+    let errata_locator = coef_pos
+        .iter()
+        .fold(vec![1], |loc, &i| poly_mul(&loc, &[alpha(i), 1]));
+    let synd_rev: Vec<u8> = synd.iter().rev().copied().collect();
+    let product = poly_mul(&synd_rev, &errata_locator);
+    let evaluator = &product[product.len() - errata_locator.len()..];
+    let x: Vec<u8> = coef_pos.iter().map(|&i| alpha(i)).collect();
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = div(1, xi);
+        let locator_prime = x
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, &xj)| mul(acc, 1 ^ mul(xi_inv, xj)));
+        if locator_prime == 0 {
+            return Err(StegError::Uncorrectable);
+        }
+        let y = mul(xi, poly_eval(evaluator, xi_inv));
+        codeword[n - 1 - coef_pos[i]] ^= div(y, locator_prime);
+    }
+
+    if syndromes(codeword, nsym).iter().any(|&s| s != 0) {
+        return Err(StegError::Uncorrectable);
+    }
+    Ok(errors)
+}
+
+/// Berlekamp-Massey over the padded syndromes.
+fn error_locator(synd: &[u8], nsym: usize) -> Vec<u8> {
+    let mut locator = vec![1u8];
+    let mut previous = vec![1u8];
+    for i in 0..nsym {
+        let k = i + 1;
+        let mut delta = synd[k];
+        for j in 1..locator.len() {
+            delta ^= mul(locator[locator.len() - 1 - j], synd[k - j]);
+        }
+        previous.push(0);
+        if delta != 0 {
+            if previous.len() > locator.len() {
+                let scaled = poly_scale(&previous, delta);
+                previous = poly_scale(&locator, div(1, delta));
+                locator = scaled;
+            }
+            locator = poly_add(&locator, &poly_scale(&previous, delta));
+        }
+    }
+    let leading = locator.iter().take_while(|&&c| c == 0).count();
+    locator.split_off(leading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_roundtrip_without_errors() {
+        for len in [0, 1, 100, 239, 240, 1000] {
+            let data = sample(len);
+            let encoded = encode(&data, 16);
+            assert_eq!(encoded.len(), encoded_len(len, 16));
+            assert_eq!(decode(&encoded, len, 16).unwrap(), (data, 0));
+        }
+    }
+
+    #[test]
+    fn test_corrects_up_to_half_the_parity_per_codeword() {
+        let mut rng = ChaCha20Rng::seed_from_u64(5);
+        let data = sample(2000);
+        for redundancy in [Redundancy::Low, Redundancy::Medium, Redundancy::High] {
+            let parity = redundancy.parity();
+            let mut encoded = encode(&data, parity);
+            let blocks = block_count(data.len(), parity);
+            // Interleaving puts consecutive bytes in different codewords, so a
+            // burst of blocks * t bytes costs each codeword exactly t errors.
+            let burst = blocks * (parity as usize / 2);
+            let start = rng.gen_range(0..encoded.len() - burst);
+            for byte in &mut encoded[start..start + burst] {
+                *byte ^= rng.gen_range(1..=255u8);
+            }
+            let (decoded, corrected) = decode(&encoded, data.len(), parity).unwrap();
+            assert_eq!(decoded, data, "{:?}", redundancy);
+            assert_eq!(corrected, burst);
+        }
+    }
+
+    #[test]
+    fn test_too_many_errors_is_reported() {
+        let data = sample(200);
+        let mut encoded = encode(&data, 16);
+        for byte in &mut encoded[..40] {
+            *byte ^= 0x5A;
+        }
+        assert!(decode(&encoded, data.len(), 16).is_err());
+    }
+
+    #[test]
+    fn test_max_data_len_is_tight() {
+        for available in [0, 10, 17, 255, 256, 270, 1000, 5000] {
+            for parity in [16, 32, 64] {
+                let max = max_data_len(available, parity);
+                assert!(encoded_len(max, parity) <= available);
+                assert!(encoded_len(max + 1, parity) > available);
+            }
+        }
+    }
+}
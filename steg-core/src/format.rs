@@ -0,0 +1,842 @@
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::qoi::QoiEncoder;
+use image::codecs::tiff::TiffEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::error::{DecodingError, EncodingError, ImageFormatHint};
+use image::{
+    DynamicImage, GrayImage, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageReader,
+};
+
+use crate::config::EmbedConfig;
+use crate::embedder::{self, Embedder};
+use crate::error::StegError;
+use crate::f5::F5Embedder;
+use crate::jpeg::JpegImage;
+use crate::palette::PaletteEmbedder;
+
+/// PNG file signature.
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Offset of the colour type byte in a PNG file: signature, IHDR length and
+/// type, width, height and bit depth.
+const PNG_COLOR_TYPE_OFFSET: usize = 25;
+
+/// PNG colour type of indexed (palette) images.
+const PNG_INDEXED: u8 = 3;
+
+/// Lossless formats sample images are written back in. Images read from any
+/// other format are written as PNG.
+const SAMPLE_FORMATS: [ImageFormat; 5] = [
+    ImageFormat::Png,
+    ImageFormat::Bmp,
+    ImageFormat::Tiff,
+    ImageFormat::WebP,
+    ImageFormat::Qoi,
+];
+
+/// A carrier image as read from a file.
+#[derive(Debug, Clone)]
+pub enum Carrier {
+    /// Greyscale or colour samples, embedded in directly.
+    Image(SampleImage),
+    /// A palette image, embedded in through its colour indices so that the
+    /// palette and file format survive.
+    Indexed(IndexedImage),
+    /// A baseline JPEG, embedded in through its DCT coefficients so that it
+    /// is never recompressed.
+    Jpeg(JpegImage),
+}
+
+impl Carrier {
+    /// The image embedders work on: the samples themselves, the plane of
+    /// palette indices of an indexed image or the coefficient plane of a
+    /// JPEG.
+    pub fn image(&self) -> &DynamicImage {
+        match self {
+            Carrier::Image(img) => img.image(),
+            Carrier::Indexed(indexed) => indexed.indices(),
+            Carrier::Jpeg(jpeg) => jpeg.coefficients(),
+        }
+    }
+
+    /// The embedder to use on this carrier: the scheme called `scheme` for
+    /// samples, and always the palette scheme for indexed images and F5 for
+    /// JPEGs, which no other scheme can embed in without wrecking the
+    /// picture or losing the message on the next save.
+    pub fn embedder(
+        &self,
+        scheme: &str,
+        config: EmbedConfig,
+    ) -> Result<Box<dyn Embedder>, StegError> {
+        match self {
+            Carrier::Image(_) => embedder::by_name(scheme, config),
+            Carrier::Indexed(indexed) => {
+                Ok(Box::new(PaletteEmbedder::new(config, indexed.palette())?))
+            }
+            Carrier::Jpeg(_) => Ok(Box::new(F5Embedder::new(config)?)),
+        }
+    }
+
+    /// This carrier with its image replaced by `encoded`, as produced from
+    /// `image()` by the carrier's embedder.
+    pub fn with_image(&self, encoded: DynamicImage) -> Result<Self, StegError> {
+        match self {
+            Carrier::Image(img) => Ok(Carrier::Image(img.with_image(encoded))),
+            Carrier::Indexed(indexed) => Ok(Carrier::Indexed(indexed.with_indices(encoded)?)),
+            Carrier::Jpeg(jpeg) => Ok(Carrier::Jpeg(jpeg.with_coefficients(encoded)?)),
+        }
+    }
+
+    /// Encode the carrier for writing out: samples in their original lossless
+    /// format or PNG, indexed images in their original format with their
+    /// original palette, and JPEGs from their coefficients without
+    /// recompression.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StegError> {
+        match self {
+            Carrier::Image(img) => img.to_bytes(),
+            Carrier::Indexed(indexed) => indexed.to_bytes(),
+            Carrier::Jpeg(jpeg) => jpeg.to_bytes(),
+        }
+    }
+
+    /// Format `to_bytes` writes the carrier in.
+    pub fn format(&self) -> ImageFormat {
+        match self {
+            Carrier::Image(img) => img.format(),
+            Carrier::Indexed(indexed) => indexed.format(),
+            Carrier::Jpeg(_) => ImageFormat::Jpeg,
+        }
+    }
+
+    /// Check that `path` does not name a file in another format than the one
+    /// the carrier is written in, so that `out.jpg` never holds PNG bytes.
+    /// Paths without a known image extension are accepted.
+    pub fn check_output_path(&self, path: impl AsRef<Path>) -> Result<(), StegError> {
+        let path = path.as_ref();
+        let named = match ImageFormat::from_path(path) {
+            Ok(named) if named != self.format() => named,
+            _ => return Ok(()),
+        };
+        let extension = self.format().extensions_str()[0];
+        let reason = if is_lossy(named) {
+            format!(
+                "{} is lossy and would destroy the hidden message",
+                name(named)
+            )
+        } else {
+            format!(
+                "the carrier is written as {}, not {}",
+                name(self.format()),
+                name(named)
+            )
+        };
+        Err(StegError::OutputFormat(format!(
+            "{}: {}; give it a .{} extension",
+            path.display(),
+            reason,
+            extension
+        )))
+    }
+
+    /// `path`, with its extension replaced by the carrier format's if
+    /// `check_output_path` would refuse it.
+    pub fn output_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        match self.check_output_path(path) {
+            Ok(()) => path.to_path_buf(),
+            Err(_) => path.with_extension(self.format().extensions_str()[0]),
+        }
+    }
+
+    /// Write the carrier to `path` (see `to_bytes`), then decode the written
+    /// file to confirm the message survived. Fails with `OutputFormat` if the
+    /// extension of `path` names another format (see `check_output_path`)
+    /// and with `RoundTripFailed` if the file reads back differently.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StegError> {
+        let path = path.as_ref();
+        self.check_output_path(path)?;
+        let bytes = self.to_bytes()?;
+        // Check the encoding before replacing whatever is at `path`
+        self.read_back(&bytes)?;
+        std::fs::write(path, &bytes)?;
+        self.read_back(&std::fs::read(path)?)
+    }
+
+    /// Confirm that `bytes` decode to this carrier's image. The message is a
+    /// function of that image alone, so it survives if the image does.
+    fn read_back(&self, bytes: &[u8]) -> Result<(), StegError> {
+        match load_carrier(bytes) {
+            Ok(carrier) if carrier.image() == self.image() => Ok(()),
+            _ => Err(StegError::RoundTripFailed),
+        }
+    }
+}
+
+/// A greyscale or colour image, with the file format and metadata it is
+/// written back with.
+#[derive(Debug, Clone)]
+pub struct SampleImage {
+    image: DynamicImage,
+    /// One of `SAMPLE_FORMATS`.
+    format: ImageFormat,
+    icc_profile: Option<Vec<u8>>,
+    exif: Option<Vec<u8>>,
+}
+
+impl SampleImage {
+    /// `image`, to be written as PNG without metadata.
+    pub fn new(image: DynamicImage) -> Self {
+        Self {
+            image,
+            format: ImageFormat::Png,
+            icc_profile: None,
+            exif: None,
+        }
+    }
+
+    pub fn image(&self) -> &DynamicImage {
+        &self.image
+    }
+
+    /// Format the image is written back in.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// This image with its samples replaced, keeping format and metadata.
+    pub fn with_image(&self, image: DynamicImage) -> Self {
+        Self {
+            image,
+            ..self.clone()
+        }
+    }
+
+    /// Encode the image in its format, with its ICC profile and EXIF data
+    /// where the format's encoder can write them.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StegError> {
+        let mut bytes = Vec::new();
+        match self.format {
+            ImageFormat::Bmp => self.write(BmpEncoder::new(&mut bytes))?,
+            ImageFormat::Tiff => self.write(TiffEncoder::new(Cursor::new(&mut bytes)))?,
+            ImageFormat::WebP => self.write(WebPEncoder::new_lossless(&mut bytes))?,
+            ImageFormat::Qoi => self.write(QoiEncoder::new(&mut bytes))?,
+            _ => self.write(PngEncoder::new(&mut bytes))?,
+        }
+        Ok(bytes)
+    }
+
+    fn write(&self, mut encoder: impl ImageEncoder) -> Result<(), ImageError> {
+        // Formats without room for a profile or EXIF data go without
+        if let Some(icc_profile) = &self.icc_profile {
+            let _ = encoder.set_icc_profile(icc_profile.clone());
+        }
+        if let Some(exif) = &self.exif {
+            let _ = encoder.set_exif_metadata(exif.clone());
+        }
+        self.image.write_with_encoder(encoder)
+    }
+
+    /// Decode any image the `image` crate reads, keeping its format if it is
+    /// one of `SAMPLE_FORMATS`. Lossy WebP files are written back as PNG,
+    /// and animated ones are refused.
+    fn decode(bytes: &[u8]) -> Result<Self, StegError> {
+        let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+        let mut format = reader.format().unwrap_or(ImageFormat::Png);
+        if format == ImageFormat::WebP {
+            let chunks = webp_chunks(bytes);
+            if chunks.iter().any(|&fourcc| fourcc == b"ANIM") {
+                return Err(StegError::UnsupportedCarrier(
+                    "animated WebPs are not supported".into(),
+                ));
+            }
+            if chunks.iter().any(|&fourcc| fourcc == b"VP8 ") {
+                format = ImageFormat::Png;
+            }
+        }
+        if !SAMPLE_FORMATS.contains(&format) {
+            format = ImageFormat::Png;
+        }
+        let mut decoder = reader.into_decoder()?;
+        let icc_profile = decoder.icc_profile()?;
+        let exif = decoder.exif_metadata()?;
+        Ok(Self {
+            image: DynamicImage::from_decoder(decoder)?,
+            format,
+            icc_profile,
+            exif,
+        })
+    }
+}
+
+/// A palette image: one palette index per pixel, with everything needed to
+/// write the file back unchanged apart from those indices.
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+    /// Palette indices as 8-bit greyscale values.
+    indices: DynamicImage,
+    /// Palette entries as RGBA, alpha from the transparency information.
+    palette: Vec<[u8; 4]>,
+    source: Source,
+}
+
+/// The file an indexed image came from, less its pixel data.
+#[derive(Debug, Clone)]
+enum Source {
+    Png(Box<png::Info<'static>>),
+    Gif {
+        global_palette: Option<Vec<u8>>,
+        frame: Box<gif::Frame<'static>>,
+    },
+}
+
+impl IndexedImage {
+    /// Palette indices, one 8-bit greyscale value per pixel.
+    pub fn indices(&self) -> &DynamicImage {
+        &self.indices
+    }
+
+    /// Palette entries as RGBA.
+    pub fn palette(&self) -> &[[u8; 4]] {
+        &self.palette
+    }
+
+    /// Format the image is written back in.
+    pub fn format(&self) -> ImageFormat {
+        match self.source {
+            Source::Png(_) => ImageFormat::Png,
+            Source::Gif { .. } => ImageFormat::Gif,
+        }
+    }
+
+    /// This image with its indices replaced. `indices` must be 8-bit
+    /// greyscale, the same size, and index into the palette.
+    pub fn with_indices(&self, indices: DynamicImage) -> Result<Self, StegError> {
+        let plane = indices
+            .as_luma8()
+            .filter(|plane| plane.dimensions() == self.indices.as_luma8().unwrap().dimensions())
+            .ok_or_else(|| {
+                StegError::UnsupportedCarrier("indices do not fit the palette image".into())
+            })?;
+        check_indices(plane.as_raw(), self.palette.len())?;
+        Ok(Self {
+            indices,
+            ..self.clone()
+        })
+    }
+
+    /// Encode the image in its original format, with the original palette
+    /// and, for PNG, the original ancillary chunks.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StegError> {
+        let plane = self
+            .indices
+            .as_luma8()
+            .expect("indices are 8-bit greyscale");
+        match &self.source {
+            Source::Png(info) => encode_png(info, plane).map_err(|e| encoding(ImageFormat::Png, e)),
+            Source::Gif {
+                global_palette,
+                frame,
+            } => encode_gif(global_palette.as_deref(), frame, plane)
+                .map_err(|e| encoding(ImageFormat::Gif, e)),
+        }
+    }
+
+    fn decode_png(bytes: &[u8]) -> Result<Self, StegError> {
+        let fail = |e| decoding(ImageFormat::Png, e);
+        let mut decoder = png::Decoder::new(Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().map_err(fail)?;
+        if reader.info().animation_control.is_some() {
+            return Err(StegError::UnsupportedCarrier(
+                "animated PNGs are not supported".into(),
+            ));
+        }
+        let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let frame = reader.next_frame(&mut buf).map_err(fail)?;
+        let depth = frame.bit_depth as u8;
+        let (width, height) = (frame.width, frame.height);
+        let indices: Vec<u8> = buf
+            .chunks(frame.line_size)
+            .take(height as usize)
+            .flat_map(|row| unpack_row(row, depth, width as usize))
+            .collect();
+
+        let mut info = reader.info().clone();
+        // The pixels are written back progressively
+        info.interlaced = false;
+        let rgb = info.palette.as_deref().unwrap_or_default();
+        let alpha = info.trns.as_deref().unwrap_or_default();
+        let palette: Vec<[u8; 4]> = rgb
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, c)| [c[0], c[1], c[2], alpha.get(i).copied().unwrap_or(255)])
+            .collect();
+        check_indices(&indices, palette.len())?;
+        Ok(Self {
+            indices: plane(width, height, indices)?,
+            palette,
+            source: Source::Png(Box::new(info)),
+        })
+    }
+
+    fn decode_gif(bytes: &[u8]) -> Result<Self, StegError> {
+        let fail = |e| decoding(ImageFormat::Gif, e);
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(Cursor::new(bytes)).map_err(fail)?;
+        let (width, height) = (decoder.width(), decoder.height());
+        let global_palette = decoder.global_palette().map(<[u8]>::to_vec);
+        let mut frame = decoder
+            .read_next_frame()
+            .map_err(fail)?
+            .ok_or_else(|| StegError::UnsupportedCarrier("GIF without an image".into()))?
+            .clone();
+        if (frame.left, frame.top, frame.width, frame.height) != (0, 0, width, height) {
+            return Err(StegError::UnsupportedCarrier(
+                "GIF frame does not cover the whole image".into(),
+            ));
+        }
+        if decoder.next_frame_info().map_err(fail)?.is_some() {
+            return Err(StegError::UnsupportedCarrier(
+                "animated GIFs are not supported".into(),
+            ));
+        }
+
+        let rgb = frame
+            .palette
+            .as_deref()
+            .or(global_palette.as_deref())
+            .unwrap_or_default();
+        let palette: Vec<[u8; 4]> = rgb
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, c)| {
+                let alpha = if frame.transparent == Some(i as u8) {
+                    0
+                } else {
+                    255
+                };
+                [c[0], c[1], c[2], alpha]
+            })
+            .collect();
+        let indices = std::mem::take(&mut frame.buffer).into_owned();
+        check_indices(&indices, palette.len())?;
+        // The reader deinterlaces the pixels, and they are written back so
+        frame.interlaced = false;
+        Ok(Self {
+            indices: plane(width as u32, height as u32, indices)?,
+            palette,
+            source: Source::Gif {
+                global_palette,
+                frame: Box::new(frame),
+            },
+        })
+    }
+}
+
+/// Decode a carrier from its file contents, whatever their name says:
+/// indexed PNGs and GIFs as `Carrier::Indexed`, keeping their palette, JPEGs
+/// as `Carrier::Jpeg`, and everything else the `image` crate reads as
+/// `Carrier::Image`.
+pub fn load_carrier(bytes: &[u8]) -> Result<Carrier, StegError> {
+    if is_indexed_png(bytes) {
+        return IndexedImage::decode_png(bytes).map(Carrier::Indexed);
+    }
+    if bytes.starts_with(b"GIF8") {
+        return IndexedImage::decode_gif(bytes).map(Carrier::Indexed);
+    }
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return JpegImage::decode(bytes).map(Carrier::Jpeg);
+    }
+    SampleImage::decode(bytes).map(Carrier::Image)
+}
+
+/// Read and decode the carrier image at `path` (see `load_carrier`).
+pub fn open_carrier(path: impl AsRef<Path>) -> Result<Carrier, StegError> {
+    load_carrier(&std::fs::read(path)?)
+}
+
+/// Name of `format` for messages: "PNG", "JPEG".
+fn name(format: ImageFormat) -> String {
+    format!("{:?}", format).to_uppercase()
+}
+
+/// Formats whose encoders throw away detail, and with it any message.
+fn is_lossy(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Jpeg | ImageFormat::Avif)
+}
+
+/// FourCCs of the top-level chunks of a WebP file.
+fn webp_chunks(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while let Some(header) = bytes.get(offset..offset + 8) {
+        chunks.push(&header[..4]);
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        // Chunks are padded to an even length
+        offset = offset.saturating_add(8 + len + len % 2);
+    }
+    chunks
+}
+
+fn is_indexed_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PNG_SIGNATURE)
+        && bytes.get(12..16) == Some(b"IHDR")
+        && bytes.get(PNG_COLOR_TYPE_OFFSET) == Some(&PNG_INDEXED)
+}
+
+fn check_indices(indices: &[u8], palette_len: usize) -> Result<(), StegError> {
+    match indices.iter().find(|&&index| index as usize >= palette_len) {
+        Some(index) => Err(StegError::UnsupportedCarrier(format!(
+            "pixel index {} outside a palette of {} colours",
+            index, palette_len
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn plane(width: u32, height: u32, indices: Vec<u8>) -> Result<DynamicImage, StegError> {
+    GrayImage::from_raw(width, height, indices)
+        .map(DynamicImage::ImageLuma8)
+        .ok_or(StegError::InvalidLength)
+}
+
+/// Indices of one row of `width` pixels packed at `depth` bits, first pixel
+/// in the high bits.
+fn unpack_row(row: &[u8], depth: u8, width: usize) -> impl Iterator<Item = u8> + '_ {
+    let per_byte = 8 / depth as usize;
+    let mask = ((1u16 << depth) - 1) as u8;
+    (0..width).map(move |x| {
+        let shift = 8 - depth as usize * (x % per_byte + 1);
+        (row[x / per_byte] >> shift) & mask
+    })
+}
+
+/// Pack one row of indices at `depth` bits, the inverse of `unpack_row`.
+fn pack_row(indices: &[u8], depth: u8) -> Vec<u8> {
+    let per_byte = 8 / depth as usize;
+    indices
+        .chunks(per_byte)
+        .map(|chunk| {
+            chunk.iter().enumerate().fold(0u8, |acc, (i, &index)| {
+                acc | index << (8 - depth as usize * (i + 1))
+            })
+        })
+        .collect()
+}
+
+fn encode_png(info: &png::Info<'static>, plane: &GrayImage) -> Result<Vec<u8>, png::EncodingError> {
+    let depth = info.bit_depth as u8;
+    let data: Vec<u8> = plane
+        .as_raw()
+        .chunks(plane.width() as usize)
+        .flat_map(|row| pack_row(row, depth))
+        .collect();
+    let mut bytes = Vec::new();
+    let mut writer = png::Encoder::with_info(&mut bytes, info.clone())?.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(bytes)
+}
+
+fn encode_gif(
+    global_palette: Option<&[u8]>,
+    frame: &gif::Frame<'static>,
+    plane: &GrayImage,
+) -> Result<Vec<u8>, gif::EncodingError> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(
+            &mut bytes,
+            frame.width,
+            frame.height,
+            global_palette.unwrap_or_default(),
+        )?;
+        encoder.write_frame(&gif::Frame {
+            buffer: Cow::Borrowed(plane.as_raw()),
+            ..frame.clone()
+        })?;
+    }
+    Ok(bytes)
+}
+
+fn decoding(format: ImageFormat, e: impl std::error::Error + Send + Sync + 'static) -> StegError {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(format), e)).into()
+}
+
+fn encoding(format: ImageFormat, e: impl std::error::Error + Send + Sync + 'static) -> StegError {
+    ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), e)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// A `width` x `height` indexed PNG at `depth` bits cycling through a
+    /// palette of `colours` greys, the first of them transparent.
+    fn indexed_png(width: u32, height: u32, depth: png::BitDepth, colours: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(
+            (0..colours)
+                .flat_map(|i| [i * 9, i * 9, i * 9])
+                .collect::<Vec<_>>(),
+        );
+        encoder.set_trns(vec![0u8]);
+        encoder
+            .add_text_chunk("Title".into(), "Harbour at dusk".into())
+            .unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        let indices: Vec<u8> = (0..width * height)
+            .map(|i| (i % colours as u32) as u8)
+            .collect();
+        let data: Vec<u8> = indices
+            .chunks(width as usize)
+            .flat_map(|row| pack_row(row, depth as u8))
+            .collect();
+        writer.write_image_data(&data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_truecolour_images_load_as_samples() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([10, 20, 30])));
+        let mut png = Vec::new();
+        img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let carrier = load_carrier(&png).unwrap();
+        assert!(matches!(carrier, Carrier::Image(_)));
+        assert_eq!(carrier.image(), &img);
+    }
+
+    #[test]
+    fn test_indexed_png_keeps_palette_depth_and_chunks() {
+        for depth in [
+            png::BitDepth::Two,
+            png::BitDepth::Four,
+            png::BitDepth::Eight,
+        ] {
+            let bytes = indexed_png(7, 3, depth, 4);
+            let Carrier::Indexed(indexed) = load_carrier(&bytes).unwrap() else {
+                panic!("indexed PNG loaded as samples");
+            };
+            assert_eq!(indexed.format(), ImageFormat::Png);
+            assert_eq!(indexed.palette()[0], [0, 0, 0, 0]);
+            assert_eq!(indexed.palette()[3], [27, 27, 27, 255]);
+            let plane = indexed.indices().as_luma8().unwrap();
+            assert_eq!(plane.get_pixel(6, 1).0, [1]);
+
+            let mut indices = indexed.indices().clone();
+            indices
+                .as_mut_luma8()
+                .unwrap()
+                .put_pixel(6, 1, image::Luma([3]));
+            let out = indexed.with_indices(indices).unwrap().to_bytes().unwrap();
+            let reader = png::Decoder::new(Cursor::new(&out)).read_info().unwrap();
+            let info = reader.info();
+            assert_eq!(
+                (info.color_type, info.bit_depth),
+                (png::ColorType::Indexed, depth)
+            );
+            assert_eq!(
+                info.palette.as_deref(),
+                Some(&[0, 0, 0, 9, 9, 9, 18, 18, 18, 27, 27, 27][..])
+            );
+            assert_eq!(info.trns.as_deref(), Some(&[0][..]));
+            assert_eq!(info.uncompressed_latin1_text[0].text, "Harbour at dusk");
+
+            let Carrier::Indexed(reread) = load_carrier(&out).unwrap() else {
+                panic!("output is no longer indexed");
+            };
+            assert_eq!(reread.indices().as_luma8().unwrap().get_pixel(6, 1).0, [3]);
+        }
+    }
+
+    #[test]
+    fn test_gif_keeps_its_palette() {
+        let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut bytes, 5, 2, &palette).unwrap();
+            let frame = gif::Frame {
+                width: 5,
+                height: 2,
+                transparent: Some(2),
+                buffer: Cow::Owned(vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1]),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+        let Carrier::Indexed(indexed) = load_carrier(&bytes).unwrap() else {
+            panic!("GIF loaded as samples");
+        };
+        assert_eq!(indexed.format(), ImageFormat::Gif);
+        assert_eq!(indexed.palette()[1], [255, 0, 0, 255]);
+        assert_eq!(indexed.palette()[2], [0, 255, 0, 0]);
+
+        let mut indices = indexed.indices().clone();
+        indices
+            .as_mut_luma8()
+            .unwrap()
+            .put_pixel(0, 0, image::Luma([1]));
+        let out = indexed.with_indices(indices).unwrap().to_bytes().unwrap();
+        let Carrier::Indexed(reread) = load_carrier(&out).unwrap() else {
+            panic!("output is no longer indexed");
+        };
+        assert_eq!(reread.palette(), indexed.palette());
+        assert_eq!(
+            reread.indices().as_luma8().unwrap().as_raw()[..3],
+            [1, 1, 2]
+        );
+
+        // Indices must stay inside the palette
+        let mut indices = indexed.indices().clone();
+        indices
+            .as_mut_luma8()
+            .unwrap()
+            .put_pixel(0, 0, image::Luma([4]));
+        assert!(matches!(
+            indexed.with_indices(indices),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+    }
+
+    #[test]
+    fn test_output_path_must_match_the_written_format() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([10, 20, 30])));
+        let carrier = Carrier::Image(SampleImage::new(img));
+        assert_eq!(carrier.format(), ImageFormat::Png);
+        for ok in ["out.png", "OUT.PNG", "out", "out.bin"] {
+            assert!(carrier.check_output_path(ok).is_ok(), "{}", ok);
+            assert_eq!(carrier.output_path(ok), Path::new(ok));
+        }
+        let Err(StegError::OutputFormat(reason)) = carrier.check_output_path("out.jpg") else {
+            panic!("JPEG output accepted for a PNG carrier");
+        };
+        assert!(reason.contains("lossy"), "{}", reason);
+        assert!(carrier.check_output_path("dir/out.gif").is_err());
+        assert_eq!(
+            carrier.output_path("uploads/p.jpeg"),
+            Path::new("uploads/p.png")
+        );
+
+        let bytes = indexed_png(5, 3, png::BitDepth::Four, 16);
+        let indexed = load_carrier(&bytes).unwrap();
+        assert!(indexed.check_output_path("out.png").is_ok());
+        assert_eq!(indexed.output_path("out.gif"), Path::new("out.png"));
+    }
+
+    #[test]
+    fn test_read_back_detects_a_changed_image() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(6, 6, |x, y| {
+            Rgb([x as u8 * 40, y as u8 * 40, 7])
+        }));
+        let carrier = Carrier::Image(SampleImage::new(img));
+        let bytes = carrier.to_bytes().unwrap();
+        assert!(carrier.read_back(&bytes).is_ok());
+
+        // A lossy re-encode of the same picture no longer carries its bits
+        let mut jpeg = Vec::new();
+        carrier
+            .image()
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        assert!(matches!(
+            carrier.read_back(&jpeg),
+            Err(StegError::RoundTripFailed)
+        ));
+        assert!(matches!(
+            carrier.read_back(&bytes[..bytes.len() / 2]),
+            Err(StegError::RoundTripFailed)
+        ));
+    }
+
+    #[test]
+    fn test_lossless_formats_are_kept_with_their_metadata() {
+        let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(48, 32, |x, y| {
+            image::Rgba([x as u8 * 5, y as u8 * 7, 90, 255 - x as u8])
+        }));
+        let icc_profile = b"not really a colour profile".to_vec();
+        let exif = b"Exif-ish bytes".to_vec();
+        let original = SampleImage {
+            icc_profile: Some(icc_profile.clone()),
+            exif: Some(exif.clone()),
+            ..SampleImage::new(img.clone())
+        };
+        let embedder = crate::LsbEmbedder::default();
+        for format in SAMPLE_FORMATS {
+            let bytes = SampleImage {
+                format,
+                ..original.clone()
+            }
+            .to_bytes()
+            .unwrap();
+            let carrier = load_carrier(&bytes).unwrap();
+            assert_eq!(carrier.format(), format);
+            let output = carrier.output_path("painting.bin.png");
+            assert!(carrier.check_output_path(&output).is_ok(), "{:?}", output);
+
+            let encoded = embedder.embed(carrier.image(), b"lossless").unwrap();
+            let bytes = carrier.with_image(encoded).unwrap().to_bytes().unwrap();
+            let Carrier::Image(reread) = load_carrier(&bytes).unwrap() else {
+                panic!("{:?} no longer reads as samples", format);
+            };
+            assert_eq!(reread.format(), format);
+            assert_eq!(embedder.extract(reread.image()).unwrap(), b"lossless");
+            let metadata = (reread.icc_profile.as_ref(), reread.exif.as_ref());
+            match format {
+                ImageFormat::Png | ImageFormat::WebP => {
+                    assert_eq!(metadata, (Some(&icc_profile), Some(&exif)), "{:?}", format)
+                }
+                ImageFormat::Tiff => assert_eq!(metadata, (Some(&icc_profile), None)),
+                _ => assert_eq!(metadata, (None, None), "{:?}", format),
+            }
+        }
+    }
+
+    #[test]
+    fn test_other_formats_are_written_as_png() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(3, 3, Rgb([1, 2, 3])));
+        let mut pnm = Vec::new();
+        img.write_to(&mut Cursor::new(&mut pnm), ImageFormat::Pnm)
+            .unwrap();
+        let carrier = load_carrier(&pnm).unwrap();
+        assert_eq!(carrier.format(), ImageFormat::Png);
+        assert!(carrier.check_output_path("out.ppm").is_err());
+
+        // A lossy VP8 chunk, and an animation inside an extended file
+        let riff = |chunks: &[(&[u8; 4], &[u8])]| {
+            let mut bytes = b"RIFF\0\0\0\0WEBP".to_vec();
+            for (fourcc, data) in chunks {
+                bytes.extend_from_slice(*fourcc);
+                bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(data);
+                if data.len() % 2 == 1 {
+                    bytes.push(0);
+                }
+            }
+            bytes
+        };
+        assert_eq!(
+            webp_chunks(&riff(&[
+                (b"VP8X", &[0; 10]),
+                (b"ALPH", &[1, 2, 3]),
+                (b"VP8 ", &[])
+            ])),
+            [&b"VP8X"[..], b"ALPH", b"VP8 "]
+        );
+        assert!(matches!(
+            load_carrier(&riff(&[(b"VP8X", &[2; 10]), (b"ANIM", &[0; 6])])),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+    }
+}
@@ -0,0 +1,839 @@
+use std::fmt;
+
+use crate::compress::Compression;
+use crate::crypto::{EncryptionParams, NONCE_LEN, SALT_LEN};
+use crate::error::StegError;
+use crate::fec;
+use crate::recipient::{SealedParams, KEY_LEN};
+
+/// Magic bytes opening a versioned header: "SDD".
+pub const MAGIC: [u8; 3] = *b"SDD";
+
+/// Magic bytes of the original, unversioned header: 0xDEAD.
+pub const LEGACY_MAGIC: [u8; 2] = [0xDE, 0xAD];
+
+/// Legacy header size: 2 bytes magic + 4 bytes u32 length.
+pub const LEGACY_HEADER_LEN: usize = 6;
+
+/// Version byte written by this library.
+pub const CURRENT_VERSION: u8 = 2;
+
+/// Payload is encrypted; the extension block carries salt and nonce.
+pub const FLAG_ENCRYPTED: u16 = 1 << 0;
+
+/// Payload is covered by a CRC-32 carried in the extension block.
+pub const FLAG_CHECKSUM: u16 = 1 << 1;
+
+/// Payload is Reed-Solomon encoded; the extension block carries the parity
+/// per codeword and the decoded length.
+pub const FLAG_FEC: u16 = 1 << 2;
+
+/// Message was compressed before encryption; the extension block carries the
+/// algorithm id.
+pub const FLAG_COMPRESSED: u16 = 1 << 3;
+
+/// Payload is one shard of a message split across several carriers; the
+/// extension block carries the message id, sequence number and shard count.
+pub const FLAG_SHARD: u16 = 1 << 4;
+
+/// Payload is one share of a message split with k-of-n secret sharing; the
+/// extension block carries the secret id, share index, threshold and count.
+pub const FLAG_SHARE: u16 = 1 << 5;
+
+/// Payload is sealed to a recipient's X25519 key; the extension block
+/// carries the sender's ephemeral public key.
+pub const FLAG_SEALED: u16 = 1 << 6;
+
+/// The message ends in the signer's Ed25519 public key and a signature over
+/// it, inside any encryption. No extension field.
+pub const FLAG_SIGNED: u16 = 1 << 7;
+
+/// The message is a `Container` holding content with its filename, MIME type
+/// and metadata rather than raw bytes. No extension field.
+pub const FLAG_CONTAINER: u16 = 1 << 8;
+
+/// The message may only be read within a time window; the extension block
+/// carries the not-before and expiry times. When the payload is encrypted the
+/// field is authenticated along with it.
+pub const FLAG_VALIDITY: u16 = 1 << 9;
+
+/// Flags this library understands. Any other bit makes a header unreadable,
+/// since it may change how the payload has to be interpreted.
+const KNOWN_FLAGS: u16 = FLAG_ENCRYPTED
+    | FLAG_CHECKSUM
+    | FLAG_FEC
+    | FLAG_COMPRESSED
+    | FLAG_SHARD
+    | FLAG_SHARE
+    | FLAG_SEALED
+    | FLAG_SIGNED
+    | FLAG_CONTAINER
+    | FLAG_VALIDITY;
+
+/// Size of the payload CRC-32 extension field.
+const PAYLOAD_CHECKSUM_LEN: usize = 4;
+
+/// Size of the FEC extension field: parity byte and u32 data length.
+const FEC_LEN: usize = 5;
+
+/// Size of the compression extension field: algorithm id.
+pub(crate) const COMPRESSION_LEN: usize = 1;
+
+/// Size of the shard extension field: u64 message id, u16 sequence number
+/// and u16 shard count.
+pub(crate) const SHARD_LEN: usize = 12;
+
+/// Size of the share extension field: u64 secret id, then share index,
+/// threshold and share count as single bytes.
+pub(crate) const SHARE_LEN: usize = 11;
+
+/// Size of the validity extension field: u64 not-before and u64 expiry times
+/// in seconds since the Unix epoch, 0 where unset.
+pub(crate) const VALIDITY_LEN: usize = 16;
+
+/// Bytes before the extension block: magic, version, flags, scheme, scheme
+/// parameter, payload length and extension length.
+const FIXED_LEN: usize = 14;
+
+/// Trailing CRC-32 over everything before it.
+const CHECKSUM_LEN: usize = 4;
+
+/// Layout generation of an embedded header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatVersion {
+    /// `[0xDE, 0xAD] [u32 length]`, as written before headers were versioned.
+    Legacy,
+    /// Versioned header with flags and checksum.
+    V2,
+}
+
+impl FormatVersion {
+    pub fn number(&self) -> u8 {
+        match self {
+            FormatVersion::Legacy => 1,
+            FormatVersion::V2 => 2,
+        }
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatVersion::Legacy => write!(f, "v1 (legacy 0xDEAD)"),
+            FormatVersion::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// Self-describing header written in front of every payload.
+///
+/// v2 layout (all integers big-endian):
+///
+/// | offset | size | field                                        |
+/// |--------|------|----------------------------------------------|
+/// | 0      | 3    | magic "SDD"                                  |
+/// | 3      | 1    | version (2)                                  |
+/// | 4      | 2    | flags                                        |
+/// | 6      | 1    | embedding scheme id                          |
+/// | 7      | 1    | scheme parameter (bits per channel for LSB)  |
+/// | 8      | 4    | payload length                               |
+/// | 12     | 2    | extension length N                           |
+/// | 14     | N    | extension fields, in flag-bit order          |
+/// | 14+N   | 4    | CRC-32 of bytes 0..14+N                      |
+///
+/// New options claim a flag bit and, if they need one, an extension field,
+/// so older readers reject payloads they cannot interpret instead of
+/// returning garbage.
+///
+/// A `triplicated` header is written with every byte repeated three times,
+/// "SSSDDD..." rather than "SDD...", and read back by a bitwise majority vote
+/// over each triple, so a flipped bit anywhere in it is outvoted before the
+/// CRC is checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub version: FormatVersion,
+    /// Identifier of the embedding scheme that wrote the payload.
+    pub scheme: u8,
+    /// Scheme-specific parameter.
+    pub scheme_param: u8,
+    /// Length in bytes of the payload following the header.
+    pub payload_len: usize,
+    pub encryption: Option<EncryptionParams>,
+    /// CRC-32 of the payload after encryption and before error correction.
+    pub payload_checksum: Option<u32>,
+    pub fec: Option<FecParams>,
+    /// Algorithm the message was compressed with, if any.
+    pub compression: Option<Compression>,
+    /// Position of this payload in a message split across carriers.
+    pub shard: Option<ShardInfo>,
+    /// Secret-sharing parameters when the payload is one share of a message.
+    pub share: Option<ShareInfo>,
+    /// Present when the payload is sealed to a recipient's public key.
+    pub sealed: Option<SealedParams>,
+    /// Whether the message carries a sender signature.
+    pub signed: bool,
+    /// Whether the message is a `Container` encoding.
+    pub container: bool,
+    /// Time window in which the message may be read.
+    pub validity: Option<Validity>,
+    /// Whether the header is written three times over, byte by byte (see
+    /// above). Error-corrected payloads get one, so that the header is not
+    /// the weak point of the error correction.
+    pub triplicated: bool,
+}
+
+/// Reed-Solomon parameters of an error-corrected payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FecParams {
+    /// Parity bytes per codeword.
+    pub parity: u8,
+    /// Payload length before encoding.
+    pub data_len: usize,
+}
+
+/// Identifies one shard of a message split across several carriers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardInfo {
+    /// Random id shared by all shards of one message.
+    pub message_id: u64,
+    /// Position of this shard, counting from 1.
+    pub sequence: u16,
+    /// Number of shards the message was split into.
+    pub total: u16,
+}
+
+impl ShardInfo {
+    /// The extension field, which encrypted payloads also authenticate.
+    pub(crate) fn to_bytes(self) -> [u8; SHARD_LEN] {
+        let mut bytes = [0u8; SHARD_LEN];
+        bytes[..8].copy_from_slice(&self.message_id.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[10..].copy_from_slice(&self.total.to_be_bytes());
+        bytes
+    }
+}
+
+/// Identifies one share of a message split with k-of-n secret sharing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShareInfo {
+    /// Random id shared by all shares of one message.
+    pub secret_id: u64,
+    /// Evaluation point of this share, 1 to `total`.
+    pub index: u8,
+    /// Shares needed to recover the message.
+    pub threshold: u8,
+    /// Number of shares issued.
+    pub total: u8,
+}
+
+impl ShareInfo {
+    /// The extension field, which encrypted payloads also authenticate.
+    pub(crate) fn to_bytes(self) -> [u8; SHARE_LEN] {
+        let mut bytes = [0u8; SHARE_LEN];
+        bytes[..8].copy_from_slice(&self.secret_id.to_be_bytes());
+        bytes[8..].copy_from_slice(&[self.index, self.threshold, self.total]);
+        bytes
+    }
+}
+
+/// Time window in which a message may be read, in seconds since the Unix
+/// epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Validity {
+    /// The message must not be read before this time.
+    pub not_before: Option<u64>,
+    /// The message must not be read at or after this time.
+    pub expires_at: Option<u64>,
+}
+
+impl Validity {
+    /// Check the window against `now`, failing with `NotYetValid` or
+    /// `Expired` outside it.
+    pub fn check(&self, now: u64) -> Result<(), StegError> {
+        if let Some(not_before) = self.not_before {
+            if now < not_before {
+                return Err(StegError::NotYetValid { not_before });
+            }
+        }
+        if let Some(expires_at) = self.expires_at {
+            if now >= expires_at {
+                return Err(StegError::Expired { expires_at });
+            }
+        }
+        Ok(())
+    }
+
+    /// The extension field, which encrypted payloads also authenticate.
+    pub(crate) fn to_bytes(self) -> [u8; VALIDITY_LEN] {
+        let mut bytes = [0u8; VALIDITY_LEN];
+        bytes[..8].copy_from_slice(&self.not_before.unwrap_or(0).to_be_bytes());
+        bytes[8..].copy_from_slice(&self.expires_at.unwrap_or(0).to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let time = |field: &[u8]| {
+            let mut secs = [0u8; 8];
+            secs.copy_from_slice(field);
+            Some(u64::from_be_bytes(secs)).filter(|&secs| secs != 0)
+        };
+        Self {
+            not_before: time(&bytes[..8]),
+            expires_at: time(&bytes[8..]),
+        }
+    }
+}
+
+impl Header {
+    /// A current-version header for a plain payload of `payload_len` bytes.
+    pub fn new(payload_len: usize) -> Self {
+        Self {
+            version: FormatVersion::V2,
+            scheme: 0,
+            scheme_param: 0,
+            payload_len,
+            encryption: None,
+            payload_checksum: None,
+            fec: None,
+            compression: None,
+            shard: None,
+            share: None,
+            sealed: None,
+            signed: false,
+            container: false,
+            validity: None,
+            triplicated: false,
+        }
+    }
+
+    /// A current-version header describing `payload`, including its checksum.
+    pub fn for_payload(payload: &[u8]) -> Self {
+        Self {
+            payload_checksum: Some(crc32fast::hash(payload)),
+            ..Self::new(payload.len())
+        }
+    }
+
+    /// A legacy `0xDEAD` header, for writing carriers older readers understand.
+    pub fn legacy(payload_len: usize) -> Self {
+        Self {
+            version: FormatVersion::Legacy,
+            ..Self::new(payload_len)
+        }
+    }
+
+    pub fn flags(&self) -> u16 {
+        let mut flags = 0;
+        if self.encryption.is_some() {
+            flags |= FLAG_ENCRYPTED;
+        }
+        if self.payload_checksum.is_some() {
+            flags |= FLAG_CHECKSUM;
+        }
+        if self.fec.is_some() {
+            flags |= FLAG_FEC;
+        }
+        if self.compression.is_some() {
+            flags |= FLAG_COMPRESSED;
+        }
+        if self.shard.is_some() {
+            flags |= FLAG_SHARD;
+        }
+        if self.share.is_some() {
+            flags |= FLAG_SHARE;
+        }
+        if self.sealed.is_some() {
+            flags |= FLAG_SEALED;
+        }
+        if self.signed {
+            flags |= FLAG_SIGNED;
+        }
+        if self.container {
+            flags |= FLAG_CONTAINER;
+        }
+        if self.validity.is_some() {
+            flags |= FLAG_VALIDITY;
+        }
+        flags
+    }
+
+    /// Whether the payload is encrypted, under a passphrase or to a recipient.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some() || self.sealed.is_some()
+    }
+
+    /// Encoded size in bytes.
+    pub fn encoded_len(&self) -> usize {
+        match self.version {
+            FormatVersion::Legacy => LEGACY_HEADER_LEN,
+            FormatVersion::V2 if self.triplicated => {
+                3 * (FIXED_LEN + self.extension_len() + CHECKSUM_LEN)
+            }
+            FormatVersion::V2 => FIXED_LEN + self.extension_len() + CHECKSUM_LEN,
+        }
+    }
+
+    fn extension_len(&self) -> usize {
+        let mut len = 0;
+        if self.encryption.is_some() {
+            len += SALT_LEN + NONCE_LEN;
+        }
+        if self.payload_checksum.is_some() {
+            len += PAYLOAD_CHECKSUM_LEN;
+        }
+        if self.fec.is_some() {
+            len += FEC_LEN;
+        }
+        if self.compression.is_some() {
+            len += COMPRESSION_LEN;
+        }
+        if self.shard.is_some() {
+            len += SHARD_LEN;
+        }
+        if self.share.is_some() {
+            len += SHARE_LEN;
+        }
+        if self.sealed.is_some() {
+            len += KEY_LEN;
+        }
+        if self.validity.is_some() {
+            len += VALIDITY_LEN;
+        }
+        len
+    }
+
+    /// Serialise the header. Legacy headers cannot carry any options.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StegError> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        match self.version {
+            FormatVersion::Legacy => {
+                if self.flags() != 0 {
+                    return Err(StegError::UnsupportedHeader(
+                        "legacy headers cannot carry options".into(),
+                    ));
+                }
+                bytes.extend_from_slice(&LEGACY_MAGIC);
+                bytes.extend_from_slice(&(self.payload_len as u32).to_be_bytes());
+            }
+            FormatVersion::V2 => {
+                bytes.extend_from_slice(&MAGIC);
+                bytes.push(CURRENT_VERSION);
+                bytes.extend_from_slice(&self.flags().to_be_bytes());
+                bytes.push(self.scheme);
+                bytes.push(self.scheme_param);
+                bytes.extend_from_slice(&(self.payload_len as u32).to_be_bytes());
+                bytes.extend_from_slice(&(self.extension_len() as u16).to_be_bytes());
+                if let Some(encryption) = &self.encryption {
+                    bytes.extend_from_slice(&encryption.salt);
+                    bytes.extend_from_slice(&encryption.nonce);
+                }
+                if let Some(checksum) = self.payload_checksum {
+                    bytes.extend_from_slice(&checksum.to_be_bytes());
+                }
+                if let Some(fec) = &self.fec {
+                    bytes.push(fec.parity);
+                    bytes.extend_from_slice(&(fec.data_len as u32).to_be_bytes());
+                }
+                if let Some(compression) = self.compression {
+                    bytes.push(compression.id());
+                }
+                if let Some(shard) = self.shard {
+                    bytes.extend_from_slice(&shard.to_bytes());
+                }
+                if let Some(share) = self.share {
+                    bytes.extend_from_slice(&share.to_bytes());
+                }
+                if let Some(sealed) = &self.sealed {
+                    bytes.extend_from_slice(&sealed.ephemeral);
+                }
+                if let Some(validity) = self.validity {
+                    bytes.extend_from_slice(&validity.to_bytes());
+                }
+                let checksum = crc32fast::hash(&bytes);
+                bytes.extend_from_slice(&checksum.to_be_bytes());
+                if self.triplicated {
+                    bytes = bytes.into_iter().flat_map(|byte| [byte; 3]).collect();
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Parse a header through `read(offset, count)`, which returns `count`
+    /// bytes starting at byte `offset` of the embedded stream. `available` is
+    /// the number of bytes the carrier can hold, used to bounds-check reads.
+    pub fn read<F>(mut read: F, available: usize) -> Result<Self, StegError>
+    where
+        F: FnMut(usize, usize) -> Vec<u8>,
+    {
+        if available >= 3 * (FIXED_LEN + CHECKSUM_LEN) {
+            let start = read(0, 3 * MAGIC.len());
+            if start[..MAGIC.len()] != MAGIC && majority(&start) == MAGIC {
+                let voted = |offset: usize, count: usize| majority(&read(3 * offset, 3 * count));
+                let header = Self::parse(voted, available / 3)?;
+                return Ok(Self {
+                    triplicated: true,
+                    ..header
+                });
+            }
+        }
+        Self::parse(read, available)
+    }
+
+    /// Parse a header written once, through `read` as for `read`.
+    fn parse<F>(mut read: F, available: usize) -> Result<Self, StegError>
+    where
+        F: FnMut(usize, usize) -> Vec<u8>,
+    {
+        if available < LEGACY_HEADER_LEN {
+            return Err(StegError::NoMessageFound);
+        }
+
+        let start = read(0, LEGACY_HEADER_LEN);
+        if start[0..3] != MAGIC {
+            if start[0..2] != LEGACY_MAGIC {
+                return Err(StegError::NoMessageFound);
+            }
+            let payload_len = u32::from_be_bytes([start[2], start[3], start[4], start[5]]);
+            return Ok(Self::legacy(payload_len as usize));
+        }
+
+        if available < FIXED_LEN + CHECKSUM_LEN {
+            return Err(StegError::NoMessageFound);
+        }
+        let mut bytes = start;
+        bytes.extend(read(LEGACY_HEADER_LEN, FIXED_LEN - LEGACY_HEADER_LEN));
+        let extension_len = u16::from_be_bytes([bytes[12], bytes[13]]) as usize;
+        if FIXED_LEN + extension_len + CHECKSUM_LEN > available {
+            return Err(StegError::CorruptHeader);
+        }
+        bytes.extend(read(FIXED_LEN, extension_len + CHECKSUM_LEN));
+
+        let (body, checksum) = bytes.split_at(FIXED_LEN + extension_len);
+        let checksum = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        if crc32fast::hash(body) != checksum {
+            return Err(StegError::CorruptHeader);
+        }
+
+        let version = body[3];
+        if version != CURRENT_VERSION {
+            return Err(StegError::UnsupportedHeader(format!(
+                "format version {}",
+                version
+            )));
+        }
+        let flags = u16::from_be_bytes([body[4], body[5]]);
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(StegError::UnsupportedHeader(format!(
+                "unknown flags {:#06x}",
+                flags & !KNOWN_FLAGS
+            )));
+        }
+
+        let mut header = Self {
+            version: FormatVersion::V2,
+            scheme: body[6],
+            scheme_param: body[7],
+            payload_len: u32::from_be_bytes([body[8], body[9], body[10], body[11]]) as usize,
+            encryption: None,
+            payload_checksum: None,
+            fec: None,
+            compression: None,
+            shard: None,
+            share: None,
+            sealed: None,
+            signed: flags & FLAG_SIGNED != 0,
+            container: flags & FLAG_CONTAINER != 0,
+            validity: None,
+            triplicated: false,
+        };
+
+        let mut extension = &body[FIXED_LEN..];
+        if flags & FLAG_ENCRYPTED != 0 {
+            let fields = take(&mut extension, SALT_LEN + NONCE_LEN)?;
+            let mut salt = [0u8; SALT_LEN];
+            salt.copy_from_slice(&fields[..SALT_LEN]);
+            let mut nonce = [0u8; NONCE_LEN];
+            nonce.copy_from_slice(&fields[SALT_LEN..]);
+            header.encryption = Some(EncryptionParams { salt, nonce });
+        }
+        if flags & FLAG_CHECKSUM != 0 {
+            let field = take(&mut extension, PAYLOAD_CHECKSUM_LEN)?;
+            header.payload_checksum = Some(u32::from_be_bytes([
+                field[0], field[1], field[2], field[3],
+            ]));
+        }
+        if flags & FLAG_FEC != 0 {
+            let field = take(&mut extension, FEC_LEN)?;
+            let parity = field[0];
+            let data_len = u32::from_be_bytes([field[1], field[2], field[3], field[4]]) as usize;
+            if parity == 0
+                || parity as usize >= 255
+                || fec::encoded_len(data_len, parity) != header.payload_len
+            {
+                return Err(StegError::CorruptHeader);
+            }
+            header.fec = Some(FecParams { parity, data_len });
+        }
+        if flags & FLAG_COMPRESSED != 0 {
+            let id = take(&mut extension, COMPRESSION_LEN)?[0];
+            header.compression = Some(Compression::from_id(id).ok_or_else(|| {
+                StegError::UnsupportedHeader(format!("compression algorithm {}", id))
+            })?);
+        }
+        if flags & FLAG_SHARD != 0 {
+            let field = take(&mut extension, SHARD_LEN)?;
+            let mut id = [0u8; 8];
+            id.copy_from_slice(&field[..8]);
+            let shard = ShardInfo {
+                message_id: u64::from_be_bytes(id),
+                sequence: u16::from_be_bytes([field[8], field[9]]),
+                total: u16::from_be_bytes([field[10], field[11]]),
+            };
+            if shard.sequence == 0 || shard.sequence > shard.total {
+                return Err(StegError::CorruptHeader);
+            }
+            header.shard = Some(shard);
+        }
+        if flags & FLAG_SHARE != 0 {
+            let field = take(&mut extension, SHARE_LEN)?;
+            let mut id = [0u8; 8];
+            id.copy_from_slice(&field[..8]);
+            let share = ShareInfo {
+                secret_id: u64::from_be_bytes(id),
+                index: field[8],
+                threshold: field[9],
+                total: field[10],
+            };
+            if share.index == 0
+                || share.index > share.total
+                || share.threshold == 0
+                || share.threshold > share.total
+            {
+                return Err(StegError::CorruptHeader);
+            }
+            header.share = Some(share);
+        }
+        if flags & FLAG_SEALED != 0 {
+            let mut ephemeral = [0u8; KEY_LEN];
+            ephemeral.copy_from_slice(take(&mut extension, KEY_LEN)?);
+            header.sealed = Some(SealedParams { ephemeral });
+        }
+        if flags & FLAG_VALIDITY != 0 {
+            let validity = Validity::from_bytes(take(&mut extension, VALIDITY_LEN)?);
+            if let (Some(not_before), Some(expires_at)) = (validity.not_before, validity.expires_at)
+            {
+                if not_before >= expires_at {
+                    return Err(StegError::CorruptHeader);
+                }
+            }
+            header.validity = Some(validity);
+        }
+        if !extension.is_empty() {
+            return Err(StegError::CorruptHeader);
+        }
+
+        Ok(header)
+    }
+
+    /// Check `payload` against the recorded checksum, if there is one.
+    pub fn verify_payload(&self, payload: &[u8]) -> Result<(), StegError> {
+        match self.payload_checksum {
+            Some(checksum) if crc32fast::hash(payload) != checksum => {
+                Err(StegError::ChecksumMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Bitwise majority of each run of three bytes in `bytes`.
+fn majority(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .chunks_exact(3)
+        .map(|copies| {
+            let [a, b, c] = [copies[0], copies[1], copies[2]];
+            (a & b) | (a & c) | (b & c)
+        })
+        .collect()
+}
+
+/// Split `count` bytes off the front of an extension block.
+fn take<'a>(extension: &mut &'a [u8], count: usize) -> Result<&'a [u8], StegError> {
+    if extension.len() < count {
+        return Err(StegError::CorruptHeader);
+    }
+    let (field, rest) = extension.split_at(count);
+    *extension = rest;
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Result<Header, StegError> {
+        let mut padded = bytes.to_vec();
+        padded.resize(bytes.len() + 64, 0);
+        Header::read(|offset, count| padded[offset..offset + count].to_vec(), padded.len())
+    }
+
+    #[test]
+    fn test_v2_roundtrip() {
+        let header = Header {
+            scheme: 3,
+            scheme_param: 2,
+            encryption: Some(EncryptionParams {
+                salt: [7; SALT_LEN],
+                nonce: [9; NONCE_LEN],
+            }),
+            payload_checksum: Some(0xC0FFEE),
+            fec: Some(FecParams {
+                parity: 32,
+                data_len: 1000,
+            }),
+            compression: Some(Compression::Deflate),
+            shard: Some(ShardInfo {
+                message_id: 0x0123_4567_89AB_CDEF,
+                sequence: 2,
+                total: 3,
+            }),
+            share: Some(ShareInfo {
+                secret_id: 42,
+                index: 5,
+                threshold: 3,
+                total: 5,
+            }),
+            sealed: Some(SealedParams { ephemeral: [3; KEY_LEN] }),
+            signed: true,
+            container: true,
+            validity: Some(Validity {
+                not_before: None,
+                expires_at: Some(1_792_300_558),
+            }),
+            ..Header::new(fec::encoded_len(1000, 32))
+        };
+        let bytes = header.to_bytes().unwrap();
+        assert_eq!(bytes.len(), header.encoded_len());
+        assert_eq!(&bytes[..4], b"SDD\x02");
+        assert_eq!(parse(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn test_triplicated_header_outvotes_flipped_bits() {
+        let header = Header {
+            fec: Some(FecParams {
+                parity: 16,
+                data_len: 100,
+            }),
+            triplicated: true,
+            ..Header::new(fec::encoded_len(100, 16))
+        };
+        let mut bytes = header.to_bytes().unwrap();
+        assert_eq!(bytes.len(), header.encoded_len());
+        assert_eq!(&bytes[..6], b"SSSDDD");
+        assert_eq!(parse(&bytes).unwrap(), header);
+        // One bad copy of every byte, including the magic
+        for copies in bytes.chunks_mut(3) {
+            copies[1] ^= 0b1010_0101;
+        }
+        assert_eq!(parse(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn test_legacy_is_detected() {
+        let header = parse(&[0xDE, 0xAD, 0, 0, 0, 5]).unwrap();
+        assert_eq!(header.version, FormatVersion::Legacy);
+        assert_eq!(header.payload_len, 5);
+        assert_eq!(header.encoded_len(), LEGACY_HEADER_LEN);
+    }
+
+    #[test]
+    fn test_checksum_rejects_corruption() {
+        let mut bytes = Header::new(42).to_bytes().unwrap();
+        bytes[9] ^= 0x10;
+        assert!(matches!(parse(&bytes), Err(StegError::CorruptHeader)));
+    }
+
+    #[test]
+    fn test_unknown_version_and_flags_are_rejected() {
+        let mut bytes = Header::new(42).to_bytes().unwrap();
+        bytes[3] = 9;
+        let crc = crc32fast::hash(&bytes[..FIXED_LEN]).to_be_bytes();
+        bytes[FIXED_LEN..].copy_from_slice(&crc);
+        assert!(matches!(
+            parse(&bytes),
+            Err(StegError::UnsupportedHeader(_))
+        ));
+
+        let mut bytes = Header::new(42).to_bytes().unwrap();
+        bytes[4] = 0x80;
+        let crc = crc32fast::hash(&bytes[..FIXED_LEN]).to_be_bytes();
+        bytes[FIXED_LEN..].copy_from_slice(&crc);
+        assert!(matches!(
+            parse(&bytes),
+            Err(StegError::UnsupportedHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_payload() {
+        let header = Header::for_payload(b"dead drop");
+        assert!(header.verify_payload(b"dead drop").is_ok());
+        assert!(matches!(
+            header.verify_payload(b"dead drip"),
+            Err(StegError::ChecksumMismatch)
+        ));
+        assert!(Header::new(9).verify_payload(b"anything!").is_ok());
+    }
+
+    #[test]
+    fn test_shard_sequence_is_validated() {
+        let mut header = Header::new(4);
+        header.shard = Some(ShardInfo {
+            message_id: 1,
+            sequence: 4,
+            total: 3,
+        });
+        let bytes = header.to_bytes().unwrap();
+        assert!(matches!(parse(&bytes), Err(StegError::CorruptHeader)));
+    }
+
+    #[test]
+    fn test_validity_window() {
+        let validity = Validity {
+            not_before: Some(100),
+            expires_at: Some(200),
+        };
+        assert!(matches!(
+            validity.check(99),
+            Err(StegError::NotYetValid { not_before: 100 })
+        ));
+        assert!(validity.check(100).is_ok());
+        assert!(matches!(
+            validity.check(200),
+            Err(StegError::Expired { expires_at: 200 })
+        ));
+        assert!(Validity::default().check(0).is_ok());
+
+        let mut header = Header::new(4);
+        header.validity = Some(Validity {
+            not_before: Some(200),
+            expires_at: Some(100),
+        });
+        let bytes = header.to_bytes().unwrap();
+        assert!(matches!(parse(&bytes), Err(StegError::CorruptHeader)));
+    }
+
+    #[test]
+    fn test_legacy_cannot_carry_options() {
+        let header = Header {
+            encryption: Some(EncryptionParams {
+                salt: [0; SALT_LEN],
+                nonce: [0; NONCE_LEN],
+            }),
+            ..Header::legacy(3)
+        };
+        assert!(header.to_bytes().is_err());
+    }
+}
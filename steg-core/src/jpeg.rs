@@ -0,0 +1,1002 @@
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageBuffer, ImageError, ImageFormat, Luma};
+
+use crate::error::StegError;
+
+const SOF0: u8 = 0xC0;
+const SOF1: u8 = 0xC1;
+const DHT: u8 = 0xC4;
+const RST0: u8 = 0xD0;
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DRI: u8 = 0xDD;
+
+/// Added to each coefficient in the plane returned by
+/// `JpegImage::coefficients`, so that the plane can hold negative values.
+pub const COEFFICIENT_BIAS: i32 = 32768;
+
+/// Coefficient stored at `value` in a coefficient plane.
+pub fn coefficient(value: u16) -> i16 {
+    (value as i32 - COEFFICIENT_BIAS) as i16
+}
+
+/// Plane value storing `coefficient`.
+pub fn plane_value(coefficient: i16) -> u16 {
+    (coefficient as i32 + COEFFICIENT_BIAS) as u16
+}
+
+/// A baseline JPEG held as its quantised DCT coefficients.
+///
+/// Reading stops at the entropy-coded data: the coefficients are Huffman
+/// decoded but never dequantised or transformed, and writing encodes them
+/// again under the original quantisation tables, so a file that is read and
+/// written back decodes to exactly the same pixels. Huffman tables are
+/// rebuilt to fit the coefficients written; every other segment (JFIF and
+/// Exif data, comments, quantisation tables, restart intervals) is copied
+/// through unchanged.
+///
+/// Sequential Huffman-coded JPEGs with 8-bit samples are supported, in one
+/// or several scans. Progressive, lossless and arithmetic-coded files are
+/// refused with `UnsupportedCarrier`.
+#[derive(Debug, Clone)]
+pub struct JpegImage {
+    width: u32,
+    height: u32,
+    components: Vec<Component>,
+    scans: Vec<Scan>,
+    /// Marker segments in file order, with scans in their place.
+    segments: Vec<Segment>,
+    /// The coefficient plane (see `coefficients`).
+    coefficients: DynamicImage,
+}
+
+#[derive(Debug, Clone)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    /// Blocks per row and column, padded to whole MCUs.
+    blocks_wide: usize,
+    blocks_high: usize,
+    /// Blocks per row and column covering the image itself.
+    used_wide: usize,
+    used_high: usize,
+    /// Plane row of the component's first block.
+    first_block: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Scan {
+    /// SOS segment body, written back as it was.
+    header: Vec<u8>,
+    /// Components in the scan with their DC and AC table ids.
+    components: Vec<ScanComponent>,
+    restart_interval: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScanComponent {
+    component: usize,
+    dc_table: usize,
+    ac_table: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Marker(u8, Vec<u8>),
+    Scan(usize),
+}
+
+impl JpegImage {
+    /// Read the coefficients and segments of a baseline JPEG.
+    pub fn decode(bytes: &[u8]) -> Result<Self, StegError> {
+        if !bytes.starts_with(&[0xFF, SOI]) {
+            return Err(malformed("missing start of image"));
+        }
+        let mut pos = 2;
+        let mut dc_tables: [Option<HuffmanTable>; 4] = Default::default();
+        let mut ac_tables: [Option<HuffmanTable>; 4] = Default::default();
+        let mut restart_interval = 0;
+        let mut frame: Option<(u32, u32, Vec<Component>)> = None;
+        let mut data: Vec<u16> = Vec::new();
+        let mut scans = Vec::new();
+        let mut segments = Vec::new();
+
+        loop {
+            if bytes.get(pos) != Some(&0xFF) {
+                return Err(malformed("expected a marker"));
+            }
+            while bytes.get(pos) == Some(&0xFF) {
+                pos += 1;
+            }
+            let marker = *bytes
+                .get(pos)
+                .ok_or_else(|| malformed("missing end of image"))?;
+            pos += 1;
+            if marker == EOI {
+                break;
+            }
+            if (RST0..RST0 + 8).contains(&marker) || marker == 0x01 {
+                continue;
+            }
+            let len = bytes
+                .get(pos..pos + 2)
+                .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                .filter(|&len| len >= 2)
+                .ok_or_else(|| malformed("truncated segment"))?;
+            let body = bytes
+                .get(pos + 2..pos + len)
+                .ok_or_else(|| malformed("segment runs past the end"))?;
+            pos += len;
+
+            match marker {
+                SOF0 | SOF1 => {
+                    if frame.is_some() {
+                        return Err(malformed("more than one frame"));
+                    }
+                    let parsed = parse_frame(body)?;
+                    let total: usize = parsed.2.iter().map(|c| c.blocks_wide * c.blocks_high).sum();
+                    // The frame header alone sets the plane size, so a few
+                    // bytes could otherwise claim gigabytes
+                    let plane_bytes = (total * 64 * std::mem::size_of::<u16>()) as u64;
+                    if image::Limits::default()
+                        .max_alloc
+                        .is_some_and(|max| plane_bytes > max)
+                    {
+                        return Err(StegError::UnsupportedCarrier(format!(
+                            "{}x{} JPEG too large to load",
+                            parsed.0, parsed.1
+                        )));
+                    }
+                    data = vec![plane_value(0); total * 64];
+                    frame = Some(parsed);
+                    segments.push(Segment::Marker(marker, body.to_vec()));
+                }
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    return Err(StegError::UnsupportedCarrier(
+                        "only baseline (sequential, Huffman-coded) JPEGs are supported".into(),
+                    ));
+                }
+                DHT => parse_huffman_tables(body, &mut dc_tables, &mut ac_tables)?,
+                DRI => {
+                    if body.len() != 2 {
+                        return Err(malformed("bad restart interval"));
+                    }
+                    restart_interval = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    segments.push(Segment::Marker(marker, body.to_vec()));
+                }
+                SOS => {
+                    let (width, height, components) = frame
+                        .as_ref()
+                        .ok_or_else(|| malformed("scan before frame header"))?;
+                    let scan = parse_scan(body, components, &scans, restart_interval)?;
+                    let end = entropy_data_end(bytes, pos);
+                    let mut reader = BitReader::new(&bytes[pos..end]);
+                    let mcus = mcus(*width, *height, components, &scan);
+                    let mut predictions = vec![0i16; scan.components.len()];
+                    for (i, mcu) in mcus.iter().enumerate() {
+                        if scan.restart_interval > 0 && i > 0 && i % scan.restart_interval == 0 {
+                            reader.restart()?;
+                            predictions.iter_mut().for_each(|p| *p = 0);
+                        }
+                        for &(slot, block) in mcu {
+                            let sc = scan.components[slot];
+                            let dc = dc_tables[sc.dc_table]
+                                .as_ref()
+                                .ok_or_else(|| malformed("missing DC table"))?;
+                            let ac = ac_tables[sc.ac_table]
+                                .as_ref()
+                                .ok_or_else(|| malformed("missing AC table"))?;
+                            let coefficients = &mut data[block * 64..block * 64 + 64];
+                            decode_block(
+                                &mut reader,
+                                dc,
+                                ac,
+                                &mut predictions[slot],
+                                coefficients,
+                            )?;
+                        }
+                    }
+                    pos = end;
+                    segments.push(Segment::Scan(scans.len()));
+                    scans.push(scan);
+                }
+                _ => segments.push(Segment::Marker(marker, body.to_vec())),
+            }
+        }
+
+        let (width, height, components) = frame.ok_or_else(|| malformed("no frame header"))?;
+        if scans.is_empty() {
+            return Err(malformed("no image data"));
+        }
+        let blocks = data.len() / 64;
+        let coefficients = ImageBuffer::<Luma<u16>, _>::from_raw(64, blocks as u32, data)
+            .map(DynamicImage::ImageLuma16)
+            .ok_or(StegError::InvalidLength)?;
+        Ok(Self {
+            width,
+            height,
+            components,
+            scans,
+            segments,
+            coefficients,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The quantised DCT coefficients as a 16-bit greyscale plane: one row
+    /// of 64 per 8x8 block, in zigzag order with the DC coefficient first,
+    /// component after component, each value offset by `COEFFICIENT_BIAS`.
+    pub fn coefficients(&self) -> &DynamicImage {
+        &self.coefficients
+    }
+
+    /// This image with its coefficients replaced by `plane`, laid out as
+    /// `coefficients` returns them.
+    pub fn with_coefficients(&self, plane: DynamicImage) -> Result<Self, StegError> {
+        let fits = plane
+            .as_luma16()
+            .is_some_and(|p| p.dimensions() == self.coefficients.as_luma16().unwrap().dimensions());
+        if !fits {
+            return Err(StegError::UnsupportedCarrier(
+                "coefficients do not fit the JPEG image".into(),
+            ));
+        }
+        Ok(Self {
+            coefficients: plane,
+            ..self.clone()
+        })
+    }
+
+    /// Encode the coefficients back into a JPEG with the original segments.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StegError> {
+        let data = self
+            .coefficients
+            .as_luma16()
+            .expect("coefficients are 16-bit");
+        let mut out = vec![0xFF, SOI];
+        for segment in &self.segments {
+            match segment {
+                Segment::Marker(marker, body) => write_segment(&mut out, *marker, body),
+                Segment::Scan(index) => self.write_scan(&mut out, &self.scans[*index], data)?,
+            }
+        }
+        out.extend_from_slice(&[0xFF, EOI]);
+        Ok(out)
+    }
+
+    fn write_scan(&self, out: &mut Vec<u8>, scan: &Scan, data: &[u16]) -> Result<(), StegError> {
+        let mcus = mcus(self.width, self.height, &self.components, scan);
+        let block = |index: usize| -> [i16; 64] {
+            std::array::from_fn(|k| coefficient(data[index * 64 + k]))
+        };
+        let each_block = |f: &mut dyn FnMut(usize, [i16; 64], i16)| {
+            let mut predictions = vec![0i16; scan.components.len()];
+            for (i, mcu) in mcus.iter().enumerate() {
+                if scan.restart_interval > 0 && i > 0 && i % scan.restart_interval == 0 {
+                    predictions.iter_mut().for_each(|p| *p = 0);
+                }
+                for &(slot, index) in mcu {
+                    let coefficients = block(index);
+                    f(slot, coefficients, predictions[slot]);
+                    predictions[slot] = coefficients[0];
+                }
+            }
+        };
+
+        // First pass: symbol frequencies, per table id
+        let mut dc_counts = [[0u32; 256]; 4];
+        let mut ac_counts = [[0u32; 256]; 4];
+        let mut in_range = true;
+        each_block(&mut |slot, coefficients, prediction| {
+            let sc = scan.components[slot];
+            let mut symbols = Vec::with_capacity(64);
+            in_range &= block_symbols(&coefficients, prediction, &mut symbols);
+            let (dc, ac) = symbols.split_first().expect("every block has a DC symbol");
+            dc_counts[sc.dc_table][dc.0 as usize] += 1;
+            for symbol in ac {
+                ac_counts[sc.ac_table][symbol.0 as usize] += 1;
+            }
+        });
+        if !in_range {
+            return Err(StegError::UnsupportedCarrier(
+                "coefficient out of range for a baseline JPEG".into(),
+            ));
+        }
+
+        // Tables fitted to those frequencies, for the ids this scan uses
+        let mut dht = Vec::new();
+        let mut dc_codes: [Option<HuffmanCodes>; 4] = Default::default();
+        let mut ac_codes: [Option<HuffmanCodes>; 4] = Default::default();
+        for sc in &scan.components {
+            for (class, id, counts, codes) in [
+                (0, sc.dc_table, &dc_counts, &mut dc_codes),
+                (1, sc.ac_table, &ac_counts, &mut ac_codes),
+            ] {
+                if codes[id].is_none() {
+                    let (lengths, values) = optimal_table(&counts[id]);
+                    dht.push((class << 4) | id as u8);
+                    dht.extend_from_slice(&lengths);
+                    dht.extend_from_slice(&values);
+                    codes[id] = Some(HuffmanCodes::new(&lengths, &values));
+                }
+            }
+        }
+        write_segment(out, DHT, &dht);
+        write_segment(out, SOS, &scan.header);
+
+        // Second pass: the entropy-coded data
+        let mut writer = BitWriter::new(out);
+        let mut symbols = Vec::with_capacity(64);
+        let mut mcu_blocks = mcus.iter().map(Vec::len);
+        let mut left_in_mcu = 0;
+        let mut mcu = 0;
+        each_block(&mut |slot, coefficients, prediction| {
+            if left_in_mcu == 0 {
+                if scan.restart_interval > 0 && mcu > 0 && mcu % scan.restart_interval == 0 {
+                    writer.restart(((mcu / scan.restart_interval - 1) % 8) as u8);
+                }
+                left_in_mcu = mcu_blocks.next().unwrap_or(0);
+                mcu += 1;
+            }
+            left_in_mcu -= 1;
+            let sc = scan.components[slot];
+            let dc = dc_codes[sc.dc_table].as_ref().expect("table built above");
+            let ac = ac_codes[sc.ac_table].as_ref().expect("table built above");
+            symbols.clear();
+            block_symbols(&coefficients, prediction, &mut symbols);
+            for (i, &(symbol, extra, extra_len)) in symbols.iter().enumerate() {
+                let codes = if i == 0 { dc } else { ac };
+                let (code, len) = codes.get(symbol);
+                writer.write(code as u32, len);
+                writer.write(extra as u32, extra_len);
+            }
+        });
+        writer.flush();
+        Ok(())
+    }
+}
+
+fn malformed(reason: &str) -> StegError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Jpeg),
+        reason.to_string(),
+    ))
+    .into()
+}
+
+fn write_segment(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+fn parse_frame(body: &[u8]) -> Result<(u32, u32, Vec<Component>), StegError> {
+    if body.len() < 6 {
+        return Err(malformed("truncated frame header"));
+    }
+    if body[0] != 8 {
+        return Err(StegError::UnsupportedCarrier(format!(
+            "{}-bit JPEG samples",
+            body[0]
+        )));
+    }
+    let height = u16::from_be_bytes([body[1], body[2]]) as u32;
+    let width = u16::from_be_bytes([body[3], body[4]]) as u32;
+    let count = body[5] as usize;
+    if width == 0 || height == 0 || count == 0 || body.len() != 6 + 3 * count {
+        return Err(malformed("bad frame header"));
+    }
+    let mut components: Vec<Component> = body[6..]
+        .chunks_exact(3)
+        .map(|c| Component {
+            id: c[0],
+            h: (c[1] >> 4) as usize,
+            v: (c[1] & 0x0F) as usize,
+            blocks_wide: 0,
+            blocks_high: 0,
+            used_wide: 0,
+            used_high: 0,
+            first_block: 0,
+        })
+        .collect();
+    if components
+        .iter()
+        .any(|c| !(1..=4).contains(&c.h) || !(1..=4).contains(&c.v))
+    {
+        return Err(malformed("bad sampling factors"));
+    }
+    let h_max = components.iter().map(|c| c.h).max().unwrap_or(1);
+    let v_max = components.iter().map(|c| c.v).max().unwrap_or(1);
+    let mcus_wide = (width as usize).div_ceil(8 * h_max);
+    let mcus_high = (height as usize).div_ceil(8 * v_max);
+    let mut first_block = 0;
+    for c in &mut components {
+        c.blocks_wide = mcus_wide * c.h;
+        c.blocks_high = mcus_high * c.v;
+        c.used_wide = (width as usize * c.h).div_ceil(h_max).div_ceil(8);
+        c.used_high = (height as usize * c.v).div_ceil(v_max).div_ceil(8);
+        c.first_block = first_block;
+        first_block += c.blocks_wide * c.blocks_high;
+    }
+    Ok((width, height, components))
+}
+
+fn parse_scan(
+    body: &[u8],
+    components: &[Component],
+    earlier: &[Scan],
+    restart_interval: usize,
+) -> Result<Scan, StegError> {
+    let count = *body
+        .first()
+        .ok_or_else(|| malformed("truncated scan header"))? as usize;
+    if count == 0 || body.len() != 4 + 2 * count {
+        return Err(malformed("bad scan header"));
+    }
+    if body[1 + 2 * count..] != [0, 63, 0] {
+        return Err(StegError::UnsupportedCarrier(
+            "only baseline (sequential, Huffman-coded) JPEGs are supported".into(),
+        ));
+    }
+    let scan_components = body[1..1 + 2 * count]
+        .chunks_exact(2)
+        .map(|c| {
+            let component = components
+                .iter()
+                .position(|comp| comp.id == c[0])
+                .ok_or_else(|| malformed("scan of an unknown component"))?;
+            let seen = earlier
+                .iter()
+                .flat_map(|scan| &scan.components)
+                .any(|sc| sc.component == component);
+            let (dc_table, ac_table) = ((c[1] >> 4) as usize, (c[1] & 0x0F) as usize);
+            if seen || dc_table > 3 || ac_table > 3 {
+                return Err(malformed("bad scan component"));
+            }
+            Ok(ScanComponent {
+                component,
+                dc_table,
+                ac_table,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Scan {
+        header: body.to_vec(),
+        components: scan_components,
+        restart_interval,
+    })
+}
+
+fn parse_huffman_tables(
+    mut body: &[u8],
+    dc_tables: &mut [Option<HuffmanTable>; 4],
+    ac_tables: &mut [Option<HuffmanTable>; 4],
+) -> Result<(), StegError> {
+    while !body.is_empty() {
+        if body.len() < 17 {
+            return Err(malformed("truncated Huffman table"));
+        }
+        let (class, id) = (body[0] >> 4, (body[0] & 0x0F) as usize);
+        let lengths: [u8; 16] = body[1..17].try_into().unwrap();
+        let total: usize = lengths.iter().map(|&n| n as usize).sum();
+        let values = body
+            .get(17..17 + total)
+            .ok_or_else(|| malformed("truncated Huffman table"))?;
+        if class > 1 || id > 3 {
+            return Err(malformed("bad Huffman table id"));
+        }
+        let table = Some(HuffmanTable::new(&lengths, values));
+        match class {
+            0 => dc_tables[id] = table,
+            _ => ac_tables[id] = table,
+        }
+        body = &body[17 + total..];
+    }
+    Ok(())
+}
+
+/// Offset where the entropy-coded data starting at `start` ends: the first
+/// marker other than a restart marker.
+fn entropy_data_end(bytes: &[u8], start: usize) -> usize {
+    let mut pos = start;
+    while pos + 1 < bytes.len() {
+        if bytes[pos] == 0xFF && bytes[pos + 1] != 0 && !(RST0..RST0 + 8).contains(&bytes[pos + 1])
+        {
+            return pos;
+        }
+        pos += 1;
+    }
+    bytes.len()
+}
+
+/// The blocks of each MCU of `scan` in coding order, as (scan component,
+/// plane row) pairs. A scan of one component codes the blocks covering the
+/// image one at a time; an interleaved scan codes every component's blocks
+/// of each MCU together.
+fn mcus(
+    width: u32,
+    height: u32,
+    components: &[Component],
+    scan: &Scan,
+) -> Vec<Vec<(usize, usize)>> {
+    if let [only] = scan.components[..] {
+        let c = &components[only.component];
+        return (0..c.used_high)
+            .flat_map(|y| {
+                (0..c.used_wide).map(move |x| vec![(0, c.first_block + y * c.blocks_wide + x)])
+            })
+            .collect();
+    }
+    let h_max = components.iter().map(|c| c.h).max().unwrap_or(1);
+    let v_max = components.iter().map(|c| c.v).max().unwrap_or(1);
+    let mcus_wide = (width as usize).div_ceil(8 * h_max);
+    let mcus_high = (height as usize).div_ceil(8 * v_max);
+    let mut mcus = Vec::with_capacity(mcus_wide * mcus_high);
+    for my in 0..mcus_high {
+        for mx in 0..mcus_wide {
+            let mut mcu = Vec::new();
+            for (slot, sc) in scan.components.iter().enumerate() {
+                let c = &components[sc.component];
+                for v in 0..c.v {
+                    for h in 0..c.h {
+                        let (x, y) = (mx * c.h + h, my * c.v + v);
+                        mcu.push((slot, c.first_block + y * c.blocks_wide + x));
+                    }
+                }
+            }
+            mcus.push(mcu);
+        }
+    }
+    mcus
+}
+
+fn decode_block(
+    reader: &mut BitReader<'_>,
+    dc: &HuffmanTable,
+    ac: &HuffmanTable,
+    prediction: &mut i16,
+    out: &mut [u16],
+) -> Result<(), StegError> {
+    let size = dc.decode(reader)?;
+    let diff = reader.receive_extend(size)?;
+    *prediction = prediction.wrapping_add(diff);
+    out[0] = plane_value(*prediction);
+    let mut k = 1;
+    while k < 64 {
+        let symbol = ac.decode(reader)?;
+        let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            return Err(malformed("coefficient index past the block"));
+        }
+        out[k] = plane_value(reader.receive_extend(size)?);
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Huffman symbols of one block with their extra bits, DC first: (symbol,
+/// extra bits, number of extra bits). Returns false if a value is too large
+/// for baseline coding.
+fn block_symbols(block: &[i16; 64], prediction: i16, symbols: &mut Vec<(u8, u16, u8)>) -> bool {
+    let mut in_range = true;
+    let mut magnitude = |value: i16, limit: u8| -> (u8, u16) {
+        let size = (16 - value.unsigned_abs().leading_zeros()) as u8;
+        in_range &= size <= limit;
+        let extra = if value < 0 {
+            value.wrapping_sub(1)
+        } else {
+            value
+        } as u16
+            & ((1u32 << size) - 1) as u16;
+        (size, extra)
+    };
+    let (size, extra) = magnitude(block[0].wrapping_sub(prediction), 11);
+    symbols.push((size, extra, size));
+    let mut run = 0;
+    for &value in &block[1..] {
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            symbols.push((0xF0, 0, 0));
+            run -= 16;
+        }
+        let (size, extra) = magnitude(value, 10);
+        symbols.push(((run << 4) | size, extra, size));
+        run = 0;
+    }
+    if run > 0 {
+        symbols.push((0x00, 0, 0));
+    }
+    in_range
+}
+
+/// Huffman code lengths and symbols fitted to symbol `counts`, limited to
+/// 16-bit codes, as in section K.2 of the JPEG standard.
+fn optimal_table(counts: &[u32; 256]) -> ([u8; 16], Vec<u8>) {
+    // Symbol 256 is reserved so that no code is all ones
+    let mut freq: Vec<u64> = counts.iter().map(|&c| c as u64).collect();
+    freq.push(1);
+    let mut code_size = [0usize; 257];
+    let mut others = [usize::MAX; 257];
+    loop {
+        let smallest = |skip: Option<usize>| {
+            (0..257)
+                .filter(|&i| freq[i] > 0 && Some(i) != skip)
+                .min_by_key(|&i| (freq[i], std::cmp::Reverse(i)))
+        };
+        let Some(c1) = smallest(None) else { break };
+        let Some(c2) = smallest(Some(c1)) else { break };
+        freq[c1] += freq[c2];
+        freq[c2] = 0;
+        let mut c = c1;
+        code_size[c] += 1;
+        while others[c] != usize::MAX {
+            c = others[c];
+            code_size[c] += 1;
+        }
+        others[c] = c2;
+        let mut c = c2;
+        code_size[c] += 1;
+        while others[c] != usize::MAX {
+            c = others[c];
+            code_size[c] += 1;
+        }
+    }
+    let mut bits = [0usize; 33];
+    for &size in code_size.iter().filter(|&&s| s > 0) {
+        bits[size] += 1;
+    }
+    for i in (17..=32).rev() {
+        while bits[i] > 0 {
+            let mut j = i - 2;
+            while bits[j] == 0 {
+                j -= 1;
+            }
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+    }
+    let mut i = 16;
+    while bits[i] == 0 {
+        i -= 1;
+    }
+    bits[i] -= 1;
+
+    let lengths: [u8; 16] = std::array::from_fn(|i| bits[i + 1] as u8);
+    let mut values = Vec::new();
+    for size in 1..=32 {
+        values.extend((0..256).filter(|&s| code_size[s] == size).map(|s| s as u8));
+    }
+    values.truncate(lengths.iter().map(|&n| n as usize).sum());
+    (lengths, values)
+}
+
+/// A Huffman table for decoding, in the form of section F.2.2.3 of the JPEG
+/// standard.
+#[derive(Debug, Clone)]
+struct HuffmanTable {
+    values: Vec<u8>,
+    max_code: [i32; 17],
+    min_code: [i32; 17],
+    first_value: [usize; 17],
+}
+
+impl HuffmanTable {
+    fn new(lengths: &[u8; 16], values: &[u8]) -> Self {
+        let mut table = Self {
+            values: values.to_vec(),
+            max_code: [-1; 17],
+            min_code: [0; 17],
+            first_value: [0; 17],
+        };
+        let (mut code, mut k) = (0i32, 0usize);
+        for len in 1..=16 {
+            let n = lengths[len - 1] as usize;
+            table.first_value[len] = k;
+            table.min_code[len] = code;
+            code += n as i32;
+            k += n;
+            if n > 0 {
+                table.max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, reader: &mut BitReader<'_>) -> Result<u8, StegError> {
+        let mut code = 0i32;
+        for len in 1..=16 {
+            code = (code << 1) | reader.bit()? as i32;
+            if code <= self.max_code[len] {
+                let index = self.first_value[len] + (code - self.min_code[len]) as usize;
+                return self
+                    .values
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| malformed("bad Huffman code"));
+            }
+        }
+        Err(malformed("bad Huffman code"))
+    }
+}
+
+/// Huffman codes for encoding: (code, length) per symbol.
+struct HuffmanCodes([(u16, u8); 256]);
+
+impl HuffmanCodes {
+    fn new(lengths: &[u8; 16], values: &[u8]) -> Self {
+        let mut codes = [(0, 0); 256];
+        let (mut code, mut k) = (0u16, 0);
+        for len in 1..=16u8 {
+            for _ in 0..lengths[len as usize - 1] {
+                codes[values[k] as usize] = (code, len);
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        Self(codes)
+    }
+
+    fn get(&self, symbol: u8) -> (u16, u8) {
+        self.0[symbol as usize]
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    byte: u8,
+    bits_left: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            byte: 0,
+            bits_left: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<u8, StegError> {
+        if self.bits_left == 0 {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| malformed("entropy-coded data ends early"))?;
+            if byte == 0xFF {
+                if self.data.get(self.pos + 1) != Some(&0) {
+                    return Err(malformed("entropy-coded data ends early"));
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.byte = byte;
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+        Ok((self.byte >> self.bits_left) & 1)
+    }
+
+    /// Read a `size`-bit value and extend it to a signed coefficient.
+    fn receive_extend(&mut self, size: u8) -> Result<i16, StegError> {
+        if size > 11 {
+            return Err(malformed("coefficient too large"));
+        }
+        let mut value = 0i32;
+        for _ in 0..size {
+            value = (value << 1) | self.bit()? as i32;
+        }
+        if size > 0 && value < 1 << (size - 1) {
+            value -= (1 << size) - 1;
+        }
+        Ok(value as i16)
+    }
+
+    /// Skip the padding and the restart marker that follow an interval.
+    fn restart(&mut self) -> Result<(), StegError> {
+        self.bits_left = 0;
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xFF, marker]) if (RST0..RST0 + 8).contains(marker) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(malformed("missing restart marker")),
+        }
+    }
+}
+
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(out: &'a mut Vec<u8>) -> Self {
+        Self {
+            out,
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, len: u8) {
+        for i in (0..len).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> i) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.push(self.buffer as u8);
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.out.push(byte);
+        if byte == 0xFF {
+            self.out.push(0);
+        }
+        self.buffer = 0;
+        self.bits = 0;
+    }
+
+    /// Pad the last byte with ones.
+    fn flush(&mut self) {
+        if self.bits > 0 {
+            let pad = 8 - self.bits;
+            self.write((1 << pad) - 1, pad);
+        }
+    }
+
+    fn restart(&mut self, n: u8) {
+        self.flush();
+        self.out.extend_from_slice(&[0xFF, RST0 + n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{GrayImage, Rgb, RgbImage};
+
+    fn sample_jpeg(img: &DynamicImage, quality: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        img.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))
+            .unwrap();
+        bytes
+    }
+
+    fn photo(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(w, h, |x, y| {
+            let n = (x * 7919 + y * 104_729) % 61;
+            Rgb([
+                (x * 255 / w) as u8 ^ n as u8,
+                (y * 255 / h) as u8,
+                ((x + y) % 256) as u8 / 2 + n as u8,
+            ])
+        }))
+    }
+
+    #[test]
+    fn test_rewrite_keeps_coefficients_pixels_and_segments() {
+        let grey = DynamicImage::ImageLuma8(GrayImage::from_fn(45, 30, |x, y| {
+            image::Luma([(x * y % 256) as u8])
+        }));
+        for img in [photo(61, 37), grey] {
+            let original = sample_jpeg(&img, 85);
+            let jpeg = JpegImage::decode(&original).unwrap();
+            assert_eq!((jpeg.width(), jpeg.height()), (img.width(), img.height()));
+            let rewritten = jpeg.to_bytes().unwrap();
+            assert!(rewritten.windows(4).any(|w| w == b"JFIF"));
+
+            let reread = JpegImage::decode(&rewritten).unwrap();
+            assert_eq!(reread.coefficients(), jpeg.coefficients());
+            assert_eq!(
+                image::load_from_memory(&rewritten).unwrap(),
+                image::load_from_memory(&original).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_restart_intervals_are_kept() {
+        let original = sample_jpeg(&photo(50, 40), 90);
+        let mut jpeg = JpegImage::decode(&original).unwrap();
+        let first_scan = jpeg
+            .segments
+            .iter()
+            .position(|s| matches!(s, Segment::Scan(_)))
+            .unwrap();
+        jpeg.segments.insert(
+            first_scan,
+            Segment::Marker(DRI, 3u16.to_be_bytes().to_vec()),
+        );
+        jpeg.scans[0].restart_interval = 3;
+        let with_restarts = jpeg.to_bytes().unwrap();
+        assert!(with_restarts.windows(2).any(|w| w == [0xFF, RST0 + 7]));
+
+        let reread = JpegImage::decode(&with_restarts).unwrap();
+        assert_eq!(reread.scans[0].restart_interval, 3);
+        assert_eq!(reread.coefficients(), jpeg.coefficients());
+        assert_eq!(
+            image::load_from_memory(&with_restarts).unwrap(),
+            image::load_from_memory(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_progressive_and_broken_files_are_refused() {
+        let mut progressive = sample_jpeg(&photo(16, 16), 80);
+        let sof = progressive
+            .windows(2)
+            .position(|w| w == [0xFF, SOF0])
+            .unwrap();
+        progressive[sof + 1] = 0xC2;
+        assert!(matches!(
+            JpegImage::decode(&progressive),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+
+        let mut truncated = sample_jpeg(&photo(16, 16), 80);
+        truncated.truncate(truncated.len() - 40);
+        assert!(JpegImage::decode(&truncated).is_err());
+        assert!(JpegImage::decode(b"not a jpeg").is_err());
+    }
+
+    #[test]
+    fn test_huge_frame_is_refused_before_allocating() {
+        // SOI, a 65535x65535 frame with one component, EOI: 17 bytes that
+        // would otherwise ask for an 8.6 GB coefficient plane
+        let header = [
+            0xFF, SOI, 0xFF, SOF0, 0, 11, 8, 0xFF, 0xFF, 0xFF, 0xFF, 1, 1, 0x11, 0, 0xFF, EOI,
+        ];
+        assert!(matches!(
+            crate::format::load_carrier(&header),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+    }
+
+    #[test]
+    fn test_optimal_table_fits_in_16_bits() {
+        // Fibonacci frequencies force the longest possible codes
+        let mut counts = [0u32; 256];
+        let (mut a, mut b) = (1u32, 1u32);
+        for count in counts.iter_mut().take(30) {
+            *count = a;
+            (a, b) = (b, a.saturating_add(b));
+        }
+        let (lengths, values) = optimal_table(&counts);
+        assert_eq!(values.len(), 30);
+        let kraft: f64 = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| n as f64 / (1u64 << (i + 1)) as f64)
+            .sum();
+        assert!(kraft < 1.0);
+    }
+}
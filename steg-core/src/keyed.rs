@@ -0,0 +1,124 @@
+use sha2::{Digest, Sha256};
+
+/// Domain separator so a passphrase reused elsewhere never yields the same round keys.
+const DOMAIN: &[u8] = b"steg-core/slot-permutation/v1";
+
+/// Number of Feistel rounds used for the permutation.
+const ROUNDS: usize = 6;
+
+/// A key-driven permutation of the embedding slots of an image.
+///
+/// Slot `i` of the payload is stored in slot `get(i)` of the image. The
+/// permutation is a balanced Feistel network over the smallest even bit width
+/// that covers `len`, with cycle-walking to stay inside `0..len`, so it needs
+/// no per-image lookup table and any single position can be computed on demand.
+pub struct SlotPermutation {
+    len: u64,
+    half_bits: u32,
+    half_mask: u64,
+    round_keys: [u64; ROUNDS],
+}
+
+impl SlotPermutation {
+    /// Derive the permutation of `len` slots from a passphrase or key.
+    pub fn new(len: usize, key: &[u8]) -> Self {
+        let len = len as u64;
+        let total_bits = 64 - len.saturating_sub(1).leading_zeros();
+        let half_bits = total_bits.div_ceil(2).max(1);
+        let half_mask = (1u64 << half_bits) - 1;
+
+        let mut round_keys = [0u64; ROUNDS];
+        for (round, round_key) in round_keys.iter_mut().enumerate() {
+            let digest = Sha256::new()
+                .chain_update(DOMAIN)
+                .chain_update([round as u8])
+                .chain_update(key)
+                .finalize();
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&digest[..8]);
+            *round_key = u64::from_be_bytes(bytes);
+        }
+
+        Self {
+            len,
+            half_bits,
+            half_mask,
+            round_keys,
+        }
+    }
+
+    /// Number of slots being permuted.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Image slot holding payload slot `index`. `index` must be below `len()`.
+    pub fn get(&self, index: usize) -> usize {
+        debug_assert!((index as u64) < self.len);
+        // Cycle-walk: the Feistel domain is at most 4x larger than `len`, so
+        // this terminates after a handful of iterations on average.
+        let mut value = index as u64;
+        loop {
+            value = self.feistel(value);
+            if value < self.len {
+                return value as usize;
+            }
+        }
+    }
+
+    fn feistel(&self, value: u64) -> u64 {
+        let mut left = value >> self.half_bits;
+        let mut right = value & self.half_mask;
+        for &round_key in &self.round_keys {
+            let mixed = left ^ (mix64(right ^ round_key) & self.half_mask);
+            left = right;
+            right = mixed;
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+/// SplitMix64 finaliser, used as the Feistel round function.
+fn mix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_a_permutation() {
+        for len in [1, 2, 3, 7, 100, 30_000] {
+            let perm = SlotPermutation::new(len, b"passphrase");
+            let mut seen = vec![false; len];
+            for i in 0..len {
+                let slot = perm.get(i);
+                assert!(!seen[slot], "slot {} produced twice for len {}", slot, len);
+                seen[slot] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_changes_order() {
+        let a = SlotPermutation::new(30_000, b"alpha");
+        let b = SlotPermutation::new(30_000, b"bravo");
+        let same = (0..64).filter(|&i| a.get(i) == b.get(i)).count();
+        assert!(same < 4);
+    }
+
+    #[test]
+    fn test_does_not_start_at_origin() {
+        let perm = SlotPermutation::new(30_000, b"passphrase");
+        let first: Vec<usize> = (0..48).map(|i| perm.get(i)).collect();
+        assert!(first.iter().any(|&slot| slot >= 48));
+    }
+}
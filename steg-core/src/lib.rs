@@ -1,5 +1,47 @@
+pub mod adaptive;
+pub mod analysis;
+pub mod clock;
+pub mod compress;
+pub mod config;
+pub mod container;
+pub mod crypto;
+pub mod embedder;
 pub mod error;
+pub mod f5;
+pub mod fec;
+pub mod format;
+pub mod header;
+pub mod jpeg;
+pub mod keyed;
 pub mod lsb;
+pub mod matrix;
+pub mod multi;
+pub mod palette;
+pub mod recipient;
+pub mod samples;
+pub mod shard;
+pub mod sharing;
+pub mod signature;
 
+pub use adaptive::AdaptiveEmbedder;
+pub use clock::Clock;
+pub use compress::Compression;
+pub use config::{EmbedConfig, EmbedStrategy};
+pub use container::Container;
+pub use embedder::{Decoded, EmbedStats, Embedder};
 pub use error::StegError;
-pub use lsb::{capacity, decode, decode_string, encode};
+pub use f5::F5Embedder;
+pub use fec::Redundancy;
+pub use format::{Carrier, IndexedImage, SampleImage};
+pub use jpeg::JpegImage;
+pub use header::{FecParams, FormatVersion, Header, ShardInfo, ShareInfo, Validity};
+pub use keyed::SlotPermutation;
+pub use lsb::{
+    capacity, capacity_encrypted, capacity_with_config, decode, decode_encrypted, decode_string,
+    decode_with_key, encode, encode_encrypted, encode_with_config, encode_with_key, LsbEmbedder,
+};
+pub use matrix::MatrixEmbedder;
+pub use multi::{MultiEmbedder, MultiEncoded};
+pub use palette::PaletteEmbedder;
+pub use recipient::{RecipientKey, RecipientSecret};
+pub use signature::{SignatureStatus, SignerKey, SignerSecret, TrustedSigners, Verification};
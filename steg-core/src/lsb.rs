@@ -1,140 +1,335 @@
-use image::{DynamicImage, GenericImageView, RgbaImage};
+use image::DynamicImage;
+use rand::Rng;
 
+use crate::config::EmbedConfig;
+use crate::embedder::Embedder;
 use crate::error::StegError;
+use crate::header::{FormatVersion, Header};
+use crate::keyed::SlotPermutation;
+use crate::samples::{slot_count, Samples};
 
-/// Magic marker bytes: 0xDEAD (2 bytes = 16 bits)
-const MAGIC: [u8; 2] = [0xDE, 0xAD];
+/// The LSB scheme: payload bits in the low bits of the colour samples, R, G
+/// and B or grey, at the carrier's own depth.
+/// The header is always written at 1 bit per channel and records the depth
+/// used for the payload, so `extract` detects it without being told.
+#[derive(Debug, Clone, Default)]
+pub struct LsbEmbedder {
+    config: EmbedConfig,
+}
 
-/// Header size: 2 bytes magic + 4 bytes u32 length = 6 bytes = 48 bits
-const HEADER_BYTES: usize = 6;
+impl LsbEmbedder {
+    pub const NAME: &'static str = "lsb";
 
-/// Calculate the steganographic capacity of an image in bytes.
-/// We use 1 bit per channel (R, G, B) per pixel = 3 bits per pixel.
-pub fn capacity(img: &DynamicImage) -> usize {
-    let (w, h) = img.dimensions();
-    let total_bits = (w as usize) * (h as usize) * 3; // 3 channels
-    let total_bytes = total_bits / 8;
-    total_bytes.saturating_sub(HEADER_BYTES)
+    /// Scheme id recorded in the header.
+    pub const ID: u8 = 0;
+
+    pub fn new(config: EmbedConfig) -> Result<Self, StegError> {
+        config.validate()?;
+        Ok(Self { config })
+    }
 }
 
-/// Encode a message into an image using LSB steganography.
-/// Format: [0xDE, 0xAD] [u32 big-endian length] [message bytes]
-/// Each bit is stored in the LSB of one color channel (R, G, B only).
-pub fn encode(img: &DynamicImage, message: &[u8]) -> Result<RgbaImage, StegError> {
-    let cap = capacity(img);
-    if message.len() > cap {
-        return Err(StegError::MessageTooLarge {
-            needed: (HEADER_BYTES + message.len()) * 8,
-            capacity: cap * 8,
-        });
+impl Embedder for LsbEmbedder {
+    fn name(&self) -> &'static str {
+        Self::NAME
     }
 
-    // Build the payload: magic + length + message
-    let len_bytes = (message.len() as u32).to_be_bytes();
-    let mut payload = Vec::with_capacity(HEADER_BYTES + message.len());
-    payload.extend_from_slice(&MAGIC);
-    payload.extend_from_slice(&len_bytes);
-    payload.extend_from_slice(message);
+    fn config(&self) -> &EmbedConfig {
+        &self.config
+    }
 
-    // Convert payload to bits
-    let bits: Vec<u8> = payload
-        .iter()
-        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
-        .collect();
-
-    let (w, h) = img.dimensions();
-    let mut output = img.to_rgba8();
-    let mut bit_idx = 0;
-
-    'outer: for y in 0..h {
-        for x in 0..w {
-            let pixel = output.get_pixel_mut(x, y);
-            // Only modify R, G, B channels (indices 0, 1, 2), skip alpha
-            for ch in 0..3 {
-                if bit_idx >= bits.len() {
-                    break 'outer;
-                }
-                pixel[ch] = (pixel[ch] & 0xFE) | bits[bit_idx];
-                bit_idx += 1;
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize {
+        body_capacity(
+            slot_count(img),
+            header_len * 8,
+            self.config.bits_per_channel,
+        )
+    }
+
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let mut header = header.clone();
+        header.scheme = Self::ID;
+        header.scheme_param = self.config.bits_per_channel;
+        if header.version == FormatVersion::Legacy && self.config.bits_per_channel != 1 {
+            return Err(StegError::UnsupportedHeader(
+                "legacy headers only support 1 bit per channel".into(),
+            ));
+        }
+        let header = header.to_bytes()?;
+
+        match &self.config.key {
+            Some(key) => {
+                let perm = SlotPermutation::new(slot_count(img), key);
+                embed(img, &header, payload, &self.config, slot_count(img), |slot| {
+                    perm.get(slot)
+                })
             }
+            None => embed(img, &header, payload, &self.config, slot_count(img), |slot| slot),
         }
     }
 
-    Ok(output)
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError> {
+        let samples = Samples::from_image(img)?;
+        match &self.config.key {
+            Some(key) => {
+                let perm = SlotPermutation::new(samples.slot_count(), key);
+                extract(&samples, samples.slot_count(), |slot| perm.get(slot))
+            }
+            None => extract(&samples, samples.slot_count(), |slot| slot),
+        }
+    }
+}
+
+/// Calculate the steganographic capacity of an image in bytes.
+/// We use 1 bit per colour sample: 3 bits per pixel in colour images, 1 in
+/// greyscale ones.
+pub fn capacity(img: &DynamicImage) -> usize {
+    LsbEmbedder::default().capacity(img)
+}
+
+/// Calculate the capacity in bytes when embedding with `config`.
+pub fn capacity_with_config(img: &DynamicImage, config: &EmbedConfig) -> Result<usize, StegError> {
+    Ok(LsbEmbedder::new(config.clone())?.capacity(img))
+}
+
+/// Calculate the capacity in bytes for a message encrypted with `encode_encrypted`.
+pub fn capacity_encrypted(img: &DynamicImage) -> usize {
+    LsbEmbedder::default().capacity_encrypted(img)
+}
+
+/// Encode a message into an image using LSB steganography.
+/// Format: [versioned header] [message bytes] (see `Header`)
+/// Each bit is stored in the LSB of one colour sample (never alpha), and the
+/// result has the colour type and depth of `img`.
+pub fn encode(img: &DynamicImage, message: &[u8]) -> Result<DynamicImage, StegError> {
+    LsbEmbedder::default().embed(img, message)
+}
+
+/// Encode a message using the bit depth, strategy and key from `config`.
+/// `decode` detects the depth from the header.
+pub fn encode_with_config(
+    img: &DynamicImage,
+    message: &[u8],
+    config: &EmbedConfig,
+) -> Result<DynamicImage, StegError> {
+    LsbEmbedder::new(config.clone())?.embed(img, message)
+}
+
+/// Encode a message with its bits scattered over a key-driven permutation of
+/// the (pixel, channel) slots instead of raster order.
+/// Only `decode_with_key` with the same key can locate the header again.
+pub fn encode_with_key(
+    img: &DynamicImage,
+    message: &[u8],
+    key: &[u8],
+) -> Result<DynamicImage, StegError> {
+    encode_with_config(img, message, &EmbedConfig::default().with_key(key))
+}
+
+/// Encrypt a message under a passphrase and encode it.
+/// The salt and nonce are stored in the header's extension block.
+pub fn encode_encrypted(
+    img: &DynamicImage,
+    message: &[u8],
+    passphrase: &[u8],
+) -> Result<DynamicImage, StegError> {
+    LsbEmbedder::default().embed_encrypted(img, message, passphrase)
 }
 
 /// Decode a hidden message from an image.
-/// Returns the raw message bytes.
+/// Returns the raw message bytes. Accepts both versioned and legacy 0xDEAD headers.
+/// Fails with `PassphraseRequired` if the message was encrypted.
 pub fn decode(img: &DynamicImage) -> Result<Vec<u8>, StegError> {
-    let (w, h) = img.dimensions();
-
-    // Extract all LSBs from R, G, B channels
-    let mut bits: Vec<u8> = Vec::new();
-    for y in 0..h {
-        for x in 0..w {
-            let pixel = img.get_pixel(x, y);
-            for ch in 0..3 {
-                bits.push(pixel[ch] & 1);
-            }
+    LsbEmbedder::default().extract(img)
+}
+
+/// Decode a message written by `encode_with_key`.
+/// A wrong key is indistinguishable from a clean image and yields `NoMessageFound`.
+pub fn decode_with_key(img: &DynamicImage, key: &[u8]) -> Result<Vec<u8>, StegError> {
+    LsbEmbedder::new(EmbedConfig::default().with_key(key))?.extract(img)
+}
+
+/// Decode and decrypt a message written by `encode_encrypted`.
+/// Fails with `DecryptionFailed` on a wrong passphrase or tampered payload,
+/// and with `NotEncrypted` if the image carries a plaintext message.
+pub fn decode_encrypted(img: &DynamicImage, passphrase: &[u8]) -> Result<Vec<u8>, StegError> {
+    LsbEmbedder::default().extract_encrypted(img, passphrase)
+}
+
+/// Decode a hidden message and return it as a UTF-8 string.
+pub fn decode_string(img: &DynamicImage) -> Result<String, StegError> {
+    let bytes = decode(img)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Write header + body. The header always uses 1 bit per channel so a decoder
+/// can read it before knowing the depth; the body uses `config.bits_per_channel`.
+/// Logical slot `i` (of `total_slots`) lives in image slot `slot_of(i)`.
+pub(crate) fn embed<F>(
+    img: &DynamicImage,
+    header: &[u8],
+    body: &[u8],
+    config: &EmbedConfig,
+    total_slots: usize,
+    slot_of: F,
+) -> Result<DynamicImage, StegError>
+where
+    F: Fn(usize) -> usize,
+{
+    let mut samples = Samples::from_image(img)?;
+    let header_slots = header.len() * 8;
+    let body_capacity = body_capacity(total_slots, header_slots, config.bits_per_channel);
+    if body.len() > body_capacity {
+        return Err(StegError::MessageTooLarge {
+            needed: (header.len() + body.len()) * 8,
+            capacity: body_capacity * 8,
+        });
+    }
+
+    let max = samples.max_value();
+    let depth = config.bits_per_channel as usize;
+    let mut rng = config.strategy.rng();
+
+    // Only colour samples are slots, alpha is never touched. `bits` fill the
+    // slot's low `depth` bits most-significant first; a short final chunk
+    // leaves the remaining low bits as they were.
+    let mut write_slot = |slot: usize, depth: usize, bits: &[u8]| {
+        let slot = slot_of(slot);
+        let value = samples.get(slot);
+        let mut target = value;
+        for (i, &bit) in bits.iter().enumerate() {
+            let bit_pos = depth - 1 - i;
+            target = (target & !(1 << bit_pos)) | ((bit as u16) << bit_pos);
         }
+        let mask = (1u16 << depth) - 1;
+        samples.set(
+            slot,
+            match rng.as_mut() {
+                Some(rng) => match_low_bits(value, target, mask, max, rng),
+                None => target,
+            },
+        );
+    };
+
+    for (slot, bit) in to_bits(header).enumerate() {
+        write_slot(slot, 1, &[bit]);
     }
+    let body_bits: Vec<u8> = to_bits(body).collect();
+    for (i, chunk) in body_bits.chunks(depth).enumerate() {
+        write_slot(header_slots + i, depth, chunk);
+    }
+
+    Ok(samples.into_image())
+}
 
-    // We need at least HEADER_BYTES * 8 bits
-    let header_bits = HEADER_BYTES * 8;
-    if bits.len() < header_bits {
-        return Err(StegError::NoMessageFound);
+/// LSB matching: the value closest to `value` whose bits under `mask` equal
+/// those of `target`, picking randomly when one candidate lies above and one
+/// below at the same distance. Candidates outside 0..=`max` are never chosen.
+pub(crate) fn match_low_bits<R: Rng>(
+    value: u16,
+    target: u16,
+    mask: u16,
+    max: u16,
+    rng: &mut R,
+) -> u16 {
+    if value & mask == target & mask {
+        return value;
     }
+    let step = mask as i32 + 1;
+    let base = ((value & !mask) | (target & mask)) as i32;
+    let distance = |c: i32| (c - value as i32).abs();
+    let mut best: Option<i32> = None;
+    for candidate in [base - step, base, base + step] {
+        if !(0..=max as i32).contains(&candidate) {
+            continue;
+        }
+        best = match best {
+            Some(b) if distance(candidate) > distance(b) => Some(b),
+            Some(b) if distance(candidate) == distance(b) && rng.gen::<bool>() => Some(b),
+            _ => Some(candidate),
+        };
+    }
+    best.expect("base itself is always in range") as u16
+}
 
-    // Extract bytes from bits
-    let extract_bytes = |bit_slice: &[u8]| -> Vec<u8> {
-        bit_slice
-            .chunks(8)
-            .filter(|chunk| chunk.len() == 8)
-            .map(|chunk| {
-                chunk
-                    .iter()
-                    .fold(0u8, |acc, &bit| (acc << 1) | bit)
+/// Read header and payload, taking logical slot `i` (of `total_slots`) from
+/// image slot `slot_of(i)`.
+pub(crate) fn extract<F>(
+    samples: &Samples,
+    total_slots: usize,
+    slot_of: F,
+) -> Result<(Header, Vec<u8>), StegError>
+where
+    F: Fn(usize) -> usize,
+{
+    let read_bit =
+        |slot: usize, bit_pos: usize| -> u8 { ((samples.get(slot_of(slot)) >> bit_pos) & 1) as u8 };
+    // Read `count` bytes starting at logical slot `start_slot` with the given depth
+    let read_bytes = |start_slot: usize, depth: usize, count: usize| -> Vec<u8> {
+        (0..count)
+            .map(|byte_idx| {
+                (0..8).fold(0u8, |acc, i| {
+                    let bit_idx = byte_idx * 8 + i;
+                    let slot = start_slot + bit_idx / depth;
+                    (acc << 1) | read_bit(slot, depth - 1 - bit_idx % depth)
+                })
             })
             .collect()
     };
 
-    // Check magic marker
-    let magic_bytes = extract_bytes(&bits[0..16]);
-    if magic_bytes != MAGIC {
-        return Err(StegError::NoMessageFound);
-    }
+    let header = Header::read(
+        |offset, count| read_bytes(offset * 8, 1, count),
+        total_slots / 8,
+    )?;
 
-    // Extract length (4 bytes = 32 bits, starting at bit 16)
-    let len_bytes = extract_bytes(&bits[16..48]);
-    if len_bytes.len() < 4 {
-        return Err(StegError::InvalidLength);
-    }
-    let msg_len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+    let bits_per_channel = match header.version {
+        FormatVersion::Legacy => 1,
+        FormatVersion::V2 => {
+            if header.scheme != LsbEmbedder::ID {
+                return Err(StegError::UnsupportedHeader(format!(
+                    "embedding scheme {}",
+                    header.scheme
+                )));
+            }
+            header.scheme_param
+        }
+    };
+    EmbedConfig::default()
+        .with_bits_per_channel(bits_per_channel)
+        .validate()?;
 
     // Validate length
-    let total_needed_bits = (HEADER_BYTES + msg_len) * 8;
-    if total_needed_bits > bits.len() {
+    let header_slots = header.encoded_len() * 8;
+    if header.payload_len > body_capacity(total_slots, header_slots, bits_per_channel) {
         return Err(StegError::InvalidLength);
     }
 
-    // Extract message bytes
-    let msg_start = 48; // HEADER_BYTES * 8
-    let msg_bits = &bits[msg_start..msg_start + msg_len * 8];
-    let message = extract_bytes(msg_bits);
+    let payload = read_bytes(header_slots, bits_per_channel as usize, header.payload_len);
+    Ok((header, payload))
+}
 
-    Ok(message)
+/// Bytes of body that fit after `header_slots` slots at the given depth.
+pub(crate) fn body_capacity(total_slots: usize, header_slots: usize, bits_per_channel: u8) -> usize {
+    total_slots.saturating_sub(header_slots) * bits_per_channel as usize / 8
 }
 
-/// Decode a hidden message and return it as a UTF-8 string.
-pub fn decode_string(img: &DynamicImage) -> Result<String, StegError> {
-    let bytes = decode(img)?;
-    Ok(String::from_utf8(bytes)?)
+/// Expand bytes into bits, most significant bit first.
+pub(crate) fn to_bits(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use image::DynamicImage;
+    use crate::config::EmbedStrategy;
+    use image::{DynamicImage, RgbaImage};
 
     fn create_test_image(w: u32, h: u32) -> DynamicImage {
         DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |_, _| {
@@ -146,8 +341,8 @@ mod tests {
     fn test_capacity() {
         let img = create_test_image(100, 100);
         let cap = capacity(&img);
-        // 100*100 pixels * 3 bits/pixel = 30000 bits = 3750 bytes - 6 header = 3744
-        assert_eq!(cap, 3744);
+        // 100*100 pixels * 3 bits/pixel = 30000 bits = 3750 bytes - 22 header = 3728
+        assert_eq!(cap, 3728);
     }
 
     #[test]
@@ -155,8 +350,7 @@ mod tests {
         let img = create_test_image(100, 100);
         let message = b"Hello, steganography!";
         let encoded = encode(&img, message).unwrap();
-        let encoded_dyn = DynamicImage::ImageRgba8(encoded);
-        let decoded = decode(&encoded_dyn).unwrap();
+        let decoded = decode(&encoded).unwrap();
         assert_eq!(decoded, message);
     }
 
@@ -165,8 +359,7 @@ mod tests {
         let img = create_test_image(200, 200);
         let message = "Secret dead drop message: rendezvous at 0300 hours";
         let encoded = encode(&img, message.as_bytes()).unwrap();
-        let encoded_dyn = DynamicImage::ImageRgba8(encoded);
-        let decoded = decode_string(&encoded_dyn).unwrap();
+        let decoded = decode_string(&encoded).unwrap();
         assert_eq!(decoded, message);
     }
 
@@ -190,8 +383,7 @@ mod tests {
         let img = create_test_image(100, 100);
         let message = b"";
         let encoded = encode(&img, message).unwrap();
-        let encoded_dyn = DynamicImage::ImageRgba8(encoded);
-        let decoded = decode(&encoded_dyn).unwrap();
+        let decoded = decode(&encoded).unwrap();
         assert_eq!(decoded, message);
     }
 
@@ -200,8 +392,291 @@ mod tests {
         let img = create_test_image(100, 100);
         let message: Vec<u8> = (0..=255).collect();
         let encoded = encode(&img, &message).unwrap();
-        let encoded_dyn = DynamicImage::ImageRgba8(encoded);
-        let decoded = decode(&encoded_dyn).unwrap();
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_keyed_roundtrip() {
+        let img = create_test_image(100, 100);
+        let message = b"Meet at the north gate";
+        let encoded = encode_with_key(&img, message, b"correct horse").unwrap();
+        let decoded = decode_with_key(&encoded, b"correct horse").unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_keyed_wrong_key_or_no_key() {
+        let img = create_test_image(100, 100);
+        let encoded = encode_with_key(&img, b"hidden", b"correct horse").unwrap();
+        assert!(matches!(
+            decode_with_key(&encoded, b"battery staple"),
+            Err(StegError::NoMessageFound)
+        ));
+        assert!(matches!(decode(&encoded), Err(StegError::NoMessageFound)));
+    }
+
+    #[test]
+    fn test_keyed_leaves_first_rows_untouched() {
+        let img = create_test_image(100, 100);
+        let encoded = encode_with_key(&img, b"hidden", b"correct horse")
+            .unwrap()
+            .into_rgba8();
+        // Sequential encoding of a 6-byte message would touch the first 32 pixels
+        let changed = (0..32)
+            .filter(|&x| encoded.get_pixel(x, 0) != &image::Rgba([128, 128, 128, 255]))
+            .count();
+        assert!(changed < 8);
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let img = create_test_image(100, 100);
+        let message = b"Package is under the third bench";
+        let encoded = encode_encrypted(&img, message, b"hunter2").unwrap();
+        let decoded = decode_encrypted(&encoded, b"hunter2").unwrap();
         assert_eq!(decoded, message);
     }
+
+    #[test]
+    fn test_encrypted_wrong_passphrase() {
+        let img = create_test_image(100, 100);
+        let encoded = encode_encrypted(&img, b"secret", b"hunter2").unwrap();
+        assert!(matches!(
+            decode_encrypted(&encoded, b"hunter3"),
+            Err(StegError::DecryptionFailed)
+        ));
+        assert!(matches!(decode(&encoded), Err(StegError::PassphraseRequired)));
+    }
+
+    #[test]
+    fn test_encrypted_tampered_payload() {
+        let img = create_test_image(100, 100);
+        let mut encoded = encode_encrypted(&img, b"secret", b"hunter2")
+            .unwrap()
+            .into_rgba8();
+        // Flip the LSB of the first ciphertext bit (slot 50 * 8 = pixel 133, channel 1)
+        encoded.get_pixel_mut(33, 1)[1] ^= 1;
+        let encoded_dyn = DynamicImage::ImageRgba8(encoded);
+        assert!(matches!(
+            decode_encrypted(&encoded_dyn, b"hunter2"),
+            Err(StegError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_encrypted_tampering_with_fixed_checksum_fails_authentication() {
+        let img = create_test_image(100, 100);
+        let embedder = LsbEmbedder::default();
+        let encoded = embedder.embed_encrypted(&img, b"secret", b"hunter2").unwrap();
+        let (header, mut payload) = embedder.extract_payload(&encoded).unwrap();
+        payload[0] ^= 1;
+        let forged = Header {
+            payload_checksum: Some(crc32fast::hash(&payload)),
+            ..header
+        };
+        let forged = embedder.embed_payload(&img, &forged, &payload).unwrap();
+        assert!(matches!(
+            decode_encrypted(&forged, b"hunter2"),
+            Err(StegError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_flipped_payload_bit_is_reported_as_corruption() {
+        let img = create_test_image(50, 50);
+        let mut encoded = encode(&img, b"meet at the usual place").unwrap().into_rgba8();
+        // First message bit is slot 22 * 8 = pixel 58, channel 2
+        encoded.get_pixel_mut(8, 1)[2] ^= 1;
+        assert!(matches!(
+            decode(&DynamicImage::ImageRgba8(encoded)),
+            Err(StegError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_encrypted_capacity() {
+        let img = create_test_image(100, 100);
+        let cap = capacity_encrypted(&img);
+        // 3750 bytes - 50 header - 16 tag
+        assert_eq!(cap, 3684);
+        assert!(encode_encrypted(&img, &vec![0u8; cap], b"k").is_ok());
+        assert!(matches!(
+            encode_encrypted(&img, &vec![0u8; cap + 1], b"k"),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_encrypted_rejects_plaintext() {
+        let img = create_test_image(100, 100);
+        let encoded = encode(&img, b"plain").unwrap();
+        assert!(matches!(
+            decode_encrypted(&encoded, b"hunter2"),
+            Err(StegError::NotEncrypted)
+        ));
+    }
+
+    #[test]
+    fn test_bit_depth_roundtrip() {
+        let img = create_test_image(100, 100);
+        let message: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        for bits in 2..=4 {
+            let config = EmbedConfig::default().with_bits_per_channel(bits);
+            let encoded = encode_with_config(&img, &message, &config).unwrap();
+            assert_eq!(decode(&encoded).unwrap(), message, "depth {}", bits);
+        }
+    }
+
+    #[test]
+    fn test_bit_depth_capacity() {
+        let img = create_test_image(100, 100);
+        let depth = |bits| EmbedConfig::default().with_bits_per_channel(bits);
+        assert_eq!(capacity_with_config(&img, &depth(1)).unwrap(), capacity(&img));
+        // (30000 slots - 176 header slots) * 2 bits / 8 = 7456
+        assert_eq!(capacity_with_config(&img, &depth(2)).unwrap(), 7456);
+        assert_eq!(capacity_with_config(&img, &depth(4)).unwrap(), 14912);
+        assert!(matches!(
+            capacity_with_config(&img, &depth(5)),
+            Err(StegError::InvalidBitDepth(5))
+        ));
+    }
+
+    #[test]
+    fn test_bit_depth_message_too_large_reports_depth_capacity() {
+        let img = create_test_image(100, 100);
+        let config = EmbedConfig::default().with_bits_per_channel(3);
+        let result = encode_with_config(&img, &vec![0u8; 20_000], &config);
+        match result {
+            Err(StegError::MessageTooLarge { capacity, .. }) => assert_eq!(capacity, 11184 * 8),
+            other => panic!("expected MessageTooLarge, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_bit_depth_one_matches_legacy_encode() {
+        let img = create_test_image(50, 50);
+        let legacy = encode(&img, b"same bits").unwrap();
+        let configured = encode_with_config(&img, b"same bits", &EmbedConfig::default()).unwrap();
+        assert_eq!(legacy, configured);
+    }
+
+    fn create_flat_image(w: u32, h: u32, value: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |_, _| {
+            image::Rgba([value, value, value, 255])
+        }))
+    }
+
+    fn lsb_matching(seed: u64) -> EmbedConfig {
+        EmbedConfig::default().with_strategy(EmbedStrategy::Match { seed: Some(seed) })
+    }
+
+    #[test]
+    fn test_lsb_matching_roundtrip() {
+        let img = create_test_image(100, 100);
+        let message = b"LSB matching leaves no pairs-of-values artifact";
+        for bits in 1..=4 {
+            let config = lsb_matching(7).with_bits_per_channel(bits);
+            let encoded = encode_with_config(&img, message, &config).unwrap();
+            let decoded = decode(&encoded).unwrap();
+            assert_eq!(decoded, message, "depth {}", bits);
+        }
+    }
+
+    #[test]
+    fn test_lsb_matching_is_deterministic_per_seed() {
+        let img = create_test_image(100, 100);
+        let message: Vec<u8> = (0..=255).collect();
+        let a = encode_with_config(&img, &message, &lsb_matching(1)).unwrap();
+        let b = encode_with_config(&img, &message, &lsb_matching(1)).unwrap();
+        let c = encode_with_config(&img, &message, &lsb_matching(2)).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_lsb_matching_changes_by_one_in_both_directions() {
+        let img = create_test_image(100, 100);
+        let message: Vec<u8> = (0..=255).collect();
+        let encoded = encode_with_config(&img, &message, &lsb_matching(3))
+            .unwrap()
+            .into_rgba8();
+        let values: Vec<u8> = encoded.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+        // Replacement would only ever produce 128 or 129 from a 128 carrier
+        assert!(values.iter().all(|&v| (127..=129).contains(&v)));
+        assert!(values.contains(&127));
+        assert!(values.contains(&129));
+    }
+
+    #[test]
+    fn test_lsb_matching_clamps_at_extremes() {
+        let message = vec![0xA5u8; 200];
+        for (value, allowed) in [(0u8, [0u8, 1]), (255, [254, 255])] {
+            let img = create_flat_image(40, 40, value);
+            for bits in 1..=4 {
+                let config = lsb_matching(9).with_bits_per_channel(bits);
+                let encoded = encode_with_config(&img, &message, &config).unwrap();
+                if bits == 1 {
+                    assert!(encoded
+                        .as_rgba8()
+                        .unwrap()
+                        .pixels()
+                        .all(|p| allowed.contains(&p[0]) && allowed.contains(&p[1])));
+                }
+                let decoded = decode(&encoded).unwrap();
+                assert_eq!(decoded, message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lsb_embedder_keyed_with_depth() {
+        let img = create_test_image(100, 100);
+        let config = EmbedConfig::default()
+            .with_bits_per_channel(2)
+            .with_key(b"correct horse");
+        let embedder = LsbEmbedder::new(config).unwrap();
+        let message = vec![0x5Au8; 6000];
+        let encoded = embedder.embed(&img, &message).unwrap();
+        assert_eq!(embedder.extract(&encoded).unwrap(), message);
+        assert!(matches!(decode(&encoded), Err(StegError::NoMessageFound)));
+    }
+
+    #[test]
+    fn test_legacy_header_still_decodes() {
+        let img = create_test_image(50, 50);
+        let embedder = LsbEmbedder::default();
+        let message = b"written before headers were versioned";
+        let encoded = embedder
+            .embed_payload(&img, &Header::legacy(message.len()), message)
+            .unwrap();
+        let decoded = embedder.extract_message(&encoded, None).unwrap();
+        assert_eq!(decoded.header.version, FormatVersion::Legacy);
+        assert_eq!(decoded.message, message);
+        assert_eq!(decode(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_new_encodes_use_current_header() {
+        let img = create_test_image(50, 50);
+        let encoded = encode(&img, b"hello").unwrap();
+        let decoded = LsbEmbedder::default()
+            .extract_message(&encoded, None)
+            .unwrap();
+        assert_eq!(decoded.header.version, FormatVersion::V2);
+        assert_eq!(decoded.header.scheme, LsbEmbedder::ID);
+        assert_eq!(decoded.header.scheme_param, 1);
+    }
+
+    #[test]
+    fn test_corrupted_header_is_reported() {
+        let img = create_test_image(50, 50);
+        let mut encoded = encode(&img, b"hello").unwrap().into_rgba8();
+        // Payload length lives in header bytes 8..12; flip a bit of byte 10
+        encoded.get_pixel_mut(26, 0)[2] ^= 1;
+        assert!(matches!(
+            decode(&DynamicImage::ImageRgba8(encoded)),
+            Err(StegError::CorruptHeader)
+        ));
+    }
 }
@@ -0,0 +1,309 @@
+use image::DynamicImage;
+use rand_chacha::ChaCha20Rng;
+
+use crate::config::EmbedConfig;
+use crate::embedder::Embedder;
+use crate::error::StegError;
+use crate::header::{FormatVersion, Header};
+use crate::keyed::SlotPermutation;
+use crate::lsb::{match_low_bits, to_bits};
+use crate::samples::{slot_count, Samples};
+
+/// Matrix embedding with binary Hamming codes.
+///
+/// The payload is cut into `k`-bit words, each carried by the LSBs of a group
+/// of `2^k - 1` slots: the word is the XOR of the (1-based) indices of the
+/// slots whose LSB is set, so at most one LSB per group ever has to change.
+/// Smaller payloads leave room for larger groups and fewer changes per
+/// payload bit; `k` is picked per message and recorded in the header.
+///
+/// The header is written like the LSB scheme's, one bit per slot, and the
+/// strategy and key of the configuration apply as they do there. Only the
+/// least significant bit is used, so `bits_per_channel` must be 1.
+#[derive(Debug, Clone, Default)]
+pub struct MatrixEmbedder {
+    config: EmbedConfig,
+}
+
+impl MatrixEmbedder {
+    pub const NAME: &'static str = "matrix";
+
+    /// Scheme id recorded in the header.
+    pub const ID: u8 = 1;
+
+    /// Largest code parameter tried; groups of 65535 slots per 16 bits.
+    pub const MAX_K: u8 = 16;
+
+    pub fn new(config: EmbedConfig) -> Result<Self, StegError> {
+        config.validate()?;
+        if config.bits_per_channel != 1 {
+            return Err(StegError::InvalidBitDepth(config.bits_per_channel));
+        }
+        Ok(Self { config })
+    }
+
+    /// Largest `k` whose groups fit `payload_bits` into `available_slots`.
+    pub fn choose_k(available_slots: usize, payload_bits: usize) -> Option<u8> {
+        (1..=Self::MAX_K)
+            .rev()
+            .find(|&k| slots_needed(payload_bits, k) <= available_slots)
+    }
+}
+
+/// Slots taken by `payload_bits` bits in groups of `2^k - 1`.
+fn slots_needed(payload_bits: usize, k: u8) -> usize {
+    payload_bits.div_ceil(k as usize) * group_len(k)
+}
+
+fn group_len(k: u8) -> usize {
+    (1usize << k) - 1
+}
+
+impl Embedder for MatrixEmbedder {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn config(&self) -> &EmbedConfig {
+        &self.config
+    }
+
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize {
+        // k = 1 degenerates to one bit per slot, the densest packing
+        slot_count(img).saturating_sub(header_len * 8) / 8
+    }
+
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let total_slots = slot_count(img);
+        let mut header = header.clone();
+        if header.version == FormatVersion::Legacy {
+            return Err(StegError::UnsupportedHeader(
+                "legacy headers only support the LSB scheme".into(),
+            ));
+        }
+        let header_slots = header.encoded_len() * 8;
+        let k = Self::choose_k(total_slots.saturating_sub(header_slots), payload.len() * 8).ok_or(
+            StegError::MessageTooLarge {
+                needed: (header.encoded_len() + payload.len()) * 8,
+                capacity: self.payload_capacity(img, header.encoded_len()) * 8,
+            },
+        )?;
+        header.scheme = Self::ID;
+        header.scheme_param = k;
+        let header = header.to_bytes()?;
+
+        let mut carrier = Carrier::new(Samples::from_image(img)?, &self.config);
+        let mut rng = self.config.strategy.rng();
+
+        for (slot, bit) in to_bits(&header).enumerate() {
+            carrier.set_lsb(slot, bit, rng.as_mut());
+        }
+        let n = group_len(k);
+        let bits: Vec<u8> = to_bits(payload).collect();
+        for (group, word) in bits.chunks(k as usize).enumerate() {
+            let start = header_slots + group * n;
+            let target = word.iter().enumerate().fold(0usize, |acc, (i, &bit)| {
+                acc | (bit as usize) << (k as usize - 1 - i)
+            });
+            let flip = carrier.syndrome(start, n) ^ target;
+            if flip != 0 {
+                let slot = start + flip - 1;
+                let bit = carrier.lsb(slot) ^ 1;
+                carrier.set_lsb(slot, bit, rng.as_mut());
+            }
+        }
+
+        Ok(carrier.samples.into_image())
+    }
+
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError> {
+        let total_slots = slot_count(img);
+        let carrier = Carrier::new(Samples::from_image(img)?, &self.config);
+        let read_bytes = |offset: usize, count: usize| -> Vec<u8> {
+            (0..count)
+                .map(|byte| {
+                    (0..8).fold(0u8, |acc, i| {
+                        (acc << 1) | carrier.lsb((offset + byte) * 8 + i)
+                    })
+                })
+                .collect()
+        };
+        let header = Header::read(read_bytes, total_slots / 8)?;
+
+        if header.version == FormatVersion::Legacy || header.scheme != Self::ID {
+            return Err(StegError::UnsupportedHeader(format!(
+                "embedding scheme {}",
+                header.scheme
+            )));
+        }
+        let k = header.scheme_param;
+        if !(1..=Self::MAX_K).contains(&k) {
+            return Err(StegError::UnsupportedHeader(format!(
+                "matrix code k = {}",
+                k
+            )));
+        }
+
+        let header_slots = header.encoded_len() * 8;
+        let payload_bits = header.payload_len * 8;
+        if slots_needed(payload_bits, k) > total_slots.saturating_sub(header_slots) {
+            return Err(StegError::InvalidLength);
+        }
+
+        let n = group_len(k);
+        let mut bits = Vec::with_capacity(payload_bits + k as usize);
+        for group in 0..payload_bits.div_ceil(k as usize) {
+            let word = carrier.syndrome(header_slots + group * n, n);
+            bits.extend((0..k).rev().map(|i| ((word >> i) & 1) as u8));
+        }
+        let payload = bits[..payload_bits]
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, &bit| (acc << 1) | bit))
+            .collect();
+        Ok((header, payload))
+    }
+}
+
+/// An image viewed as a sequence of LSB slots, optionally key-permuted.
+struct Carrier {
+    samples: Samples,
+    permutation: Option<SlotPermutation>,
+}
+
+impl Carrier {
+    fn new(samples: Samples, config: &EmbedConfig) -> Self {
+        let permutation = config
+            .key
+            .as_ref()
+            .map(|key| SlotPermutation::new(samples.slot_count(), key));
+        Self {
+            samples,
+            permutation,
+        }
+    }
+
+    fn position(&self, slot: usize) -> usize {
+        match &self.permutation {
+            Some(perm) => perm.get(slot),
+            None => slot,
+        }
+    }
+
+    fn lsb(&self, slot: usize) -> u8 {
+        (self.samples.get(self.position(slot)) & 1) as u8
+    }
+
+    fn set_lsb(&mut self, slot: usize, bit: u8, rng: Option<&mut ChaCha20Rng>) {
+        let slot = self.position(slot);
+        let value = self.samples.get(slot);
+        let max = self.samples.max_value();
+        self.samples.set(
+            slot,
+            match rng {
+                Some(rng) => match_low_bits(value, bit as u16, 1, max, rng),
+                None => (value & !1) | bit as u16,
+            },
+        );
+    }
+
+    /// XOR of the 1-based indices of the set LSBs in `len` slots from `start`.
+    fn syndrome(&self, start: usize, len: usize) -> usize {
+        (0..len)
+            .filter(|&i| self.lsb(start + i) == 1)
+            .fold(0, |acc, i| acc ^ (i + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmbedStrategy;
+    use crate::embedder::EmbedStats;
+    use crate::lsb::LsbEmbedder;
+    use image::RgbaImage;
+    use rand::{Rng, SeedableRng};
+
+    fn noisy_image(w: u32, h: u32) -> DynamicImage {
+        let mut rng = ChaCha20Rng::seed_from_u64(11);
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(w, h, |_, _| {
+            image::Rgba([rng.gen(), rng.gen(), rng.gen(), 255])
+        }))
+    }
+
+    fn random_message(len: usize) -> Vec<u8> {
+        let mut rng = ChaCha20Rng::seed_from_u64(12);
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn test_roundtrip_at_every_fill() {
+        let img = noisy_image(80, 80);
+        let embedder = MatrixEmbedder::default();
+        let capacity = embedder.capacity(&img);
+        for len in [0, 1, 50, 300, capacity / 2, capacity] {
+            let message = random_message(len);
+            let encoded = embedder.embed(&img, &message).unwrap();
+            assert_eq!(embedder.extract(&encoded).unwrap(), message, "len {}", len);
+        }
+        assert!(matches!(
+            embedder.embed(&img, &random_message(capacity + 1)),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_choose_k() {
+        assert_eq!(MatrixEmbedder::choose_k(1000, 1000), Some(1));
+        // k = 3: 7 slots per 3 bits; k = 4 would need 15 per 4
+        assert_eq!(MatrixEmbedder::choose_k(2400, 1000), Some(3));
+        assert_eq!(MatrixEmbedder::choose_k(1000, 1001), None);
+    }
+
+    #[test]
+    fn test_changes_fewer_values_than_lsb() {
+        let img = noisy_image(100, 100);
+        let message = random_message(300);
+        let lsb = LsbEmbedder::default().embed(&img, &message).unwrap();
+        let matrix = MatrixEmbedder::default().embed(&img, &message).unwrap();
+        let lsb_changes = EmbedStats::between(&img, &lsb).changed_values;
+        let matrix_changes = EmbedStats::between(&img, &matrix).changed_values;
+        // 2400 payload bits: LSB flips about half, k = 6 flips at most one
+        // value per 6 bits
+        assert!(lsb_changes > 1000, "lsb changed {}", lsb_changes);
+        assert!(matrix_changes < 550, "matrix changed {}", matrix_changes);
+
+        let (header, _) = MatrixEmbedder::default().extract_payload(&matrix).unwrap();
+        assert_eq!(header.scheme_param, 6);
+    }
+
+    #[test]
+    fn test_keyed_matching_roundtrip() {
+        let img = noisy_image(60, 60);
+        let config = EmbedConfig::default()
+            .with_key(b"matrix key")
+            .with_strategy(EmbedStrategy::Match { seed: Some(4) });
+        let embedder = MatrixEmbedder::new(config).unwrap();
+        let encoded = embedder.embed(&img, b"fewer changes").unwrap();
+        assert_eq!(embedder.extract(&encoded).unwrap(), b"fewer changes");
+        assert!(MatrixEmbedder::default().extract(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_schemes_do_not_cross_decode() {
+        let img = noisy_image(60, 60);
+        let encoded = MatrixEmbedder::default().embed(&img, b"matrix").unwrap();
+        assert!(matches!(
+            LsbEmbedder::default().extract(&encoded),
+            Err(StegError::UnsupportedHeader(_))
+        ));
+        assert!(matches!(
+            MatrixEmbedder::new(EmbedConfig::default().with_bits_per_channel(2)),
+            Err(StegError::InvalidBitDepth(2))
+        ));
+    }
+}
@@ -0,0 +1,321 @@
+use image::DynamicImage;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::config::EmbedConfig;
+use crate::embedder::Embedder;
+use crate::error::StegError;
+use crate::header::Header;
+use crate::keyed::SlotPermutation;
+use crate::lsb::{body_capacity, embed, extract, match_low_bits, LsbEmbedder};
+use crate::samples::{slot_count, Samples};
+
+/// Most payloads one carrier can hold. Every carrier is split into this many
+/// slot sets whether or not they are all used.
+pub const MAX_PAYLOADS: usize = 4;
+
+/// Several independent payloads in one carrier, each under its own passphrase.
+///
+/// The colour sample slots are dealt into `MAX_PAYLOADS` disjoint sets (slot `s`
+/// belongs to set `s % MAX_PAYLOADS`), and each payload is encrypted and
+/// embedded with the LSB scheme over a passphrase-keyed permutation of one
+/// randomly chosen set. Before any payload is written the low bits of the
+/// whole image are randomised, so an unused set looks the same as one
+/// holding a payload under a passphrase the reader does not know. Revealing
+/// one passphrase, e.g. a decoy's, shows that set and nothing about the
+/// others.
+#[derive(Debug, Clone, Default)]
+pub struct MultiEmbedder {
+    config: EmbedConfig,
+}
+
+/// A carrier written by `MultiEmbedder::embed`.
+#[derive(Debug, Clone)]
+pub struct MultiEncoded {
+    pub image: DynamicImage,
+    /// Slot sets still free for further payloads.
+    pub free_sets: usize,
+    /// Message bytes the free slot sets hold between them.
+    pub remaining_capacity: usize,
+}
+
+impl MultiEmbedder {
+    /// `config` applies to every payload. The slot keys come from the
+    /// passphrases, so it must not carry a key of its own.
+    pub fn new(config: EmbedConfig) -> Result<Self, StegError> {
+        config.validate()?;
+        if config.key.is_some() {
+            return Err(StegError::IncompatibleOptions(
+                "payload slots are keyed by their passphrases".into(),
+            ));
+        }
+        Ok(Self { config })
+    }
+
+    /// Message bytes one payload can hold: the capacity of the smallest set.
+    pub fn capacity(&self, img: &DynamicImage) -> usize {
+        self.slot_set(MAX_PAYLOADS - 1, None)
+            .capacity_encrypted(img)
+    }
+
+    /// Embed each `(passphrase, message)` pair in its own slot set.
+    /// Passphrases must differ; at most `MAX_PAYLOADS` pairs fit.
+    pub fn embed(
+        &self,
+        img: &DynamicImage,
+        payloads: &[(&[u8], &[u8])],
+    ) -> Result<MultiEncoded, StegError> {
+        if payloads.len() > MAX_PAYLOADS {
+            return Err(StegError::IncompatibleOptions(format!(
+                "at most {} payloads per carrier",
+                MAX_PAYLOADS
+            )));
+        }
+        for (i, (passphrase, _)) in payloads.iter().enumerate() {
+            if payloads[..i].iter().any(|(other, _)| other == passphrase) {
+                return Err(StegError::IncompatibleOptions(
+                    "every payload needs its own passphrase".into(),
+                ));
+            }
+        }
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut sets: Vec<usize> = (0..MAX_PAYLOADS).collect();
+        sets.shuffle(&mut rng);
+
+        let mut image = self.randomize_low_bits(img, &mut rng)?;
+        for (&(passphrase, message), &set) in payloads.iter().zip(&sets) {
+            image = self
+                .slot_set(set, Some(passphrase))
+                .embed_encrypted(&image, message, passphrase)?;
+        }
+
+        let free = &sets[payloads.len()..];
+        Ok(MultiEncoded {
+            image,
+            free_sets: free.len(),
+            remaining_capacity: free
+                .iter()
+                .map(|&set| self.slot_set(set, None).capacity_encrypted(img))
+                .sum(),
+        })
+    }
+
+    /// Find and decrypt the payload embedded under `passphrase`.
+    /// Fails with `NoMessageFound` when no slot set holds one, exactly as
+    /// for a carrier without any payloads.
+    pub fn extract(&self, img: &DynamicImage, passphrase: &[u8]) -> Result<Vec<u8>, StegError> {
+        let mut failure = StegError::NoMessageFound;
+        for set in 0..MAX_PAYLOADS {
+            match self
+                .slot_set(set, Some(passphrase))
+                .extract_encrypted(img, passphrase)
+            {
+                Ok(message) => return Ok(message),
+                // A set that is not ours reads as noise
+                Err(
+                    StegError::NoMessageFound
+                    | StegError::CorruptHeader
+                    | StegError::UnsupportedHeader(_)
+                    | StegError::InvalidLength
+                    | StegError::InvalidBitDepth(_),
+                ) => {}
+                Err(e) => failure = e,
+            }
+        }
+        Err(failure)
+    }
+
+    fn slot_set(&self, index: usize, passphrase: Option<&[u8]>) -> SlotSet {
+        let mut config = self.config.clone();
+        config.key = passphrase.map(<[u8]>::to_vec);
+        SlotSet { config, index }
+    }
+
+    /// Overwrite the low bits of every colour sample with random ones.
+    fn randomize_low_bits(
+        &self,
+        img: &DynamicImage,
+        rng: &mut ChaCha20Rng,
+    ) -> Result<DynamicImage, StegError> {
+        let mut samples = Samples::from_image(img)?;
+        let mask = (1u16 << self.config.bits_per_channel) - 1;
+        let max = samples.max_value();
+        let mut matching = self.config.strategy.rng();
+        for slot in 0..samples.slot_count() {
+            let value = samples.get(slot);
+            let target = rng.gen::<u16>() & mask;
+            let value = match matching.as_mut() {
+                Some(matching) => match_low_bits(value, target, mask, max, matching),
+                None => (value & !mask) | target,
+            };
+            samples.set(slot, value);
+        }
+        Ok(samples.into_image())
+    }
+}
+
+/// The LSB scheme confined to the slots `s` with `s % MAX_PAYLOADS == index`.
+struct SlotSet {
+    config: EmbedConfig,
+    index: usize,
+}
+
+impl SlotSet {
+    fn len(&self, img: &DynamicImage) -> usize {
+        (slot_count(img) + MAX_PAYLOADS - 1 - self.index) / MAX_PAYLOADS
+    }
+
+    fn permutation(&self, img: &DynamicImage) -> Option<SlotPermutation> {
+        self.config
+            .key
+            .as_ref()
+            .map(|key| SlotPermutation::new(self.len(img), key))
+    }
+
+    fn image_slot(&self, perm: &Option<SlotPermutation>, slot: usize) -> usize {
+        let slot = match perm {
+            Some(perm) => perm.get(slot),
+            None => slot,
+        };
+        slot * MAX_PAYLOADS + self.index
+    }
+}
+
+impl Embedder for SlotSet {
+    fn name(&self) -> &'static str {
+        LsbEmbedder::NAME
+    }
+
+    fn config(&self) -> &EmbedConfig {
+        &self.config
+    }
+
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize {
+        body_capacity(self.len(img), header_len * 8, self.config.bits_per_channel)
+    }
+
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let mut header = header.clone();
+        header.scheme = LsbEmbedder::ID;
+        header.scheme_param = self.config.bits_per_channel;
+        let header = header.to_bytes()?;
+
+        let perm = self.permutation(img);
+        embed(img, &header, payload, &self.config, self.len(img), |slot| {
+            self.image_slot(&perm, slot)
+        })
+    }
+
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError> {
+        let perm = self.permutation(img);
+        extract(&Samples::from_image(img)?, self.len(img), |slot| {
+            self.image_slot(&perm, slot)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn grey(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            w,
+            h,
+            image::Rgba([128, 128, 128, 255]),
+        ))
+    }
+
+    #[test]
+    fn test_each_passphrase_reveals_its_payload() {
+        let img = grey(80, 80);
+        let multi = MultiEmbedder::default();
+        let encoded = multi
+            .embed(
+                &img,
+                &[
+                    (b"decoy", b"grocery list: eggs, milk"),
+                    (b"real", b"meet at the north gate at dawn"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            multi.extract(&encoded.image, b"decoy").unwrap(),
+            b"grocery list: eggs, milk"
+        );
+        assert_eq!(
+            multi.extract(&encoded.image, b"real").unwrap(),
+            b"meet at the north gate at dawn"
+        );
+        assert!(matches!(
+            multi.extract(&encoded.image, b"guess"),
+            Err(StegError::NoMessageFound)
+        ));
+
+        assert_eq!(encoded.free_sets, 2);
+        // 19200 slots: four sets of 4800, each 600 bytes less a 50-byte header
+        // and the 16-byte authentication tag
+        assert_eq!(multi.capacity(&img), 534);
+        assert_eq!(encoded.remaining_capacity, 2 * 534);
+    }
+
+    #[test]
+    fn test_unused_sets_look_used() {
+        // The grey carrier's LSBs are all 0; after embedding only a decoy
+        // every set must still show about as many 1s as 0s
+        let img = grey(100, 100);
+        let encoded = MultiEmbedder::default()
+            .embed(&img, &[(b"decoy", b"nothing to see")])
+            .unwrap();
+        assert_eq!(encoded.free_sets, MAX_PAYLOADS - 1);
+        let values: Vec<u8> = encoded
+            .image
+            .to_rgba8()
+            .pixels()
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect();
+        for set in 0..MAX_PAYLOADS {
+            let ones = values
+                .iter()
+                .skip(set)
+                .step_by(MAX_PAYLOADS)
+                .filter(|&&v| v & 1 == 1)
+                .count();
+            let fraction = ones as f64 / (values.len() / MAX_PAYLOADS) as f64;
+            assert!((fraction - 0.5).abs() < 0.03, "set {}: {}", set, fraction);
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_payload_lists() {
+        let img = grey(80, 80);
+        let multi = MultiEmbedder::default();
+        assert!(matches!(
+            multi.embed(&img, &[(b"same", b"one"), (b"same", b"two")]),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+        let keys: Vec<Vec<u8>> = (0..=MAX_PAYLOADS).map(|i| vec![i as u8]).collect();
+        let too_many: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (&k[..], &b"x"[..])).collect();
+        assert!(matches!(
+            multi.embed(&img, &too_many),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+        let large = vec![0u8; multi.capacity(&img) + 1];
+        assert!(matches!(
+            multi.embed(&img, &[(b"key", &large)]),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+        assert!(matches!(
+            MultiEmbedder::new(EmbedConfig::default().with_key(b"k")),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+use image::{DynamicImage, GrayImage};
+use rand_chacha::ChaCha20Rng;
+
+use crate::config::EmbedConfig;
+use crate::embedder::Embedder;
+use crate::error::StegError;
+use crate::header::{FormatVersion, Header};
+use crate::keyed::SlotPermutation;
+use crate::lsb::{match_low_bits, to_bits};
+
+/// EzStego-style embedding in the pixels of a palette image.
+///
+/// The opaque palette entries are sorted by luminance and each pixel in an
+/// opaque colour carries the parity of its colour's position in that order.
+/// Setting a bit moves a pixel at most one step along the order, to the
+/// colour closest in brightness, where flipping the low bit of the raw index
+/// could jump to any colour at all. Pixels in colours that are not fully
+/// opaque carry nothing and are never changed, so transparency survives
+/// untouched. The palette itself is never touched either.
+///
+/// The embedder works on the plane of palette indices of an
+/// `IndexedImage`, one slot per opaque pixel, and must be built with that
+/// image's palette (see `Carrier::embedder`). The header is written one bit per slot
+/// like the LSB scheme's. A key scatters the bits as in the other schemes,
+/// and LSB matching picks the step up or down the order at random instead of
+/// always pairing positions 2i and 2i + 1.
+#[derive(Debug, Clone)]
+pub struct PaletteEmbedder {
+    config: EmbedConfig,
+    /// Opaque palette index at each position of the luminance order.
+    order: Vec<u8>,
+    /// Position in the luminance order of each palette index, `None` for
+    /// entries that are not fully opaque.
+    rank: Vec<Option<u8>>,
+}
+
+impl PaletteEmbedder {
+    pub const NAME: &'static str = "palette";
+
+    /// Scheme id recorded in the header.
+    pub const ID: u8 = 3;
+
+    /// Build the embedder for images using `palette`, given as RGBA entries.
+    pub fn new(config: EmbedConfig, palette: &[[u8; 4]]) -> Result<Self, StegError> {
+        config.validate()?;
+        if config.bits_per_channel != 1 {
+            return Err(StegError::InvalidBitDepth(config.bits_per_channel));
+        }
+        if palette.len() > 256 {
+            return Err(StegError::UnsupportedCarrier(format!(
+                "palette of {} colours",
+                palette.len()
+            )));
+        }
+        let mut order: Vec<u8> = (0..palette.len())
+            .filter(|&i| palette[i][3] == 255)
+            .map(|i| i as u8)
+            .collect();
+        order.sort_by_key(|&i| {
+            let [r, g, b, _] = palette[i as usize];
+            299 * r as u32 + 587 * g as u32 + 114 * b as u32
+        });
+        let mut rank = vec![None; palette.len()];
+        for (position, &index) in order.iter().enumerate() {
+            rank[index as usize] = Some(position as u8);
+        }
+        Ok(Self {
+            config,
+            order,
+            rank,
+        })
+    }
+
+    /// Index plane of `img`, checked against the palette.
+    fn plane<'a>(&self, img: &'a DynamicImage) -> Result<&'a GrayImage, StegError> {
+        let plane = img.as_luma8().ok_or_else(|| {
+            StegError::UnsupportedCarrier(
+                "palette embedding needs the indices of an indexed image".into(),
+            )
+        })?;
+        if plane.iter().any(|&index| index as usize >= self.rank.len()) {
+            return Err(StegError::UnsupportedCarrier(
+                "pixel index outside the palette".into(),
+            ));
+        }
+        Ok(plane)
+    }
+}
+
+impl Embedder for PaletteEmbedder {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn config(&self) -> &EmbedConfig {
+        &self.config
+    }
+
+    fn payload_capacity(&self, img: &DynamicImage, header_len: usize) -> usize {
+        match self.plane(img) {
+            // A single opaque colour has nothing to step to
+            Ok(plane) if self.order.len() >= 2 => {
+                let slots = plane
+                    .iter()
+                    .filter(|&&index| self.rank[index as usize].is_some())
+                    .count();
+                slots.saturating_sub(header_len * 8) / 8
+            }
+            _ => 0,
+        }
+    }
+
+    fn embed_payload(
+        &self,
+        img: &DynamicImage,
+        header: &Header,
+        payload: &[u8],
+    ) -> Result<DynamicImage, StegError> {
+        let mut header = header.clone();
+        if header.version == FormatVersion::Legacy {
+            return Err(StegError::UnsupportedHeader(
+                "legacy headers only support the LSB scheme".into(),
+            ));
+        }
+        let mut carrier = Carrier::new(self, self.plane(img)?.clone());
+        let needed = header.encoded_len() + payload.len();
+        let capacity = self.payload_capacity(img, 0);
+        if needed > capacity {
+            return Err(StegError::MessageTooLarge {
+                needed: needed * 8,
+                capacity: capacity * 8,
+            });
+        }
+        header.scheme = Self::ID;
+        header.scheme_param = 1;
+        let header = header.to_bytes()?;
+
+        let mut rng = self.config.strategy.rng();
+        for (slot, bit) in to_bits(&header).chain(to_bits(payload)).enumerate() {
+            carrier.set_parity(slot, bit, rng.as_mut());
+        }
+        Ok(DynamicImage::ImageLuma8(carrier.plane))
+    }
+
+    fn extract_payload(&self, img: &DynamicImage) -> Result<(Header, Vec<u8>), StegError> {
+        let carrier = Carrier::new(self, self.plane(img)?.clone());
+        let total_slots = carrier.slots.len();
+        let read_bytes = |offset: usize, count: usize| -> Vec<u8> {
+            (0..count)
+                .map(|byte| {
+                    (0..8).fold(0u8, |acc, i| {
+                        (acc << 1) | carrier.parity((offset + byte) * 8 + i)
+                    })
+                })
+                .collect()
+        };
+        let header = Header::read(read_bytes, total_slots / 8)?;
+
+        if header.version == FormatVersion::Legacy || header.scheme != Self::ID {
+            return Err(StegError::UnsupportedHeader(format!(
+                "embedding scheme {}",
+                header.scheme
+            )));
+        }
+        if header.scheme_param != 1 {
+            return Err(StegError::UnsupportedHeader(format!(
+                "{} bits per palette index",
+                header.scheme_param
+            )));
+        }
+        let offset = header.encoded_len();
+        if (offset + header.payload_len) * 8 > total_slots {
+            return Err(StegError::InvalidLength);
+        }
+        let payload = read_bytes(offset, header.payload_len);
+        Ok((header, payload))
+    }
+}
+
+/// An index plane viewed as a sequence of parity slots, one per opaque
+/// pixel, optionally key-permuted.
+struct Carrier<'a> {
+    embedder: &'a PaletteEmbedder,
+    plane: GrayImage,
+    /// Positions of the opaque pixels in raster order. Embedding only ever
+    /// swaps one opaque colour for another, so they stay the same.
+    slots: Vec<usize>,
+    permutation: Option<SlotPermutation>,
+}
+
+impl<'a> Carrier<'a> {
+    fn new(embedder: &'a PaletteEmbedder, plane: GrayImage) -> Self {
+        let slots: Vec<usize> = plane
+            .iter()
+            .enumerate()
+            .filter(|&(_, &index)| embedder.rank[index as usize].is_some())
+            .map(|(pixel, _)| pixel)
+            .collect();
+        let permutation = embedder
+            .config
+            .key
+            .as_ref()
+            .map(|key| SlotPermutation::new(slots.len(), key));
+        Self {
+            embedder,
+            plane,
+            slots,
+            permutation,
+        }
+    }
+
+    fn position(&self, slot: usize) -> usize {
+        let slot = match &self.permutation {
+            Some(perm) => perm.get(slot),
+            None => slot,
+        };
+        self.slots[slot]
+    }
+
+    /// Rank of the colour of the pixel in `slot`, which is opaque.
+    fn rank(&self, slot: usize) -> u8 {
+        let index = self.plane.as_raw()[self.position(slot)];
+        self.embedder.rank[index as usize].expect("slots are opaque pixels")
+    }
+
+    fn parity(&self, slot: usize) -> u8 {
+        self.rank(slot) & 1
+    }
+
+    /// Move the pixel in `slot` to a neighbouring colour in the luminance
+    /// order if its parity is not `bit`.
+    fn set_parity(&mut self, slot: usize, bit: u8, rng: Option<&mut ChaCha20Rng>) {
+        let pixel = self.position(slot);
+        let rank = self.rank(slot);
+        let last = (self.embedder.order.len() - 1) as u8;
+        let rank = match rng {
+            Some(rng) => match_low_bits(rank as u16, bit as u16, 1, last as u16, rng) as u8,
+            // The last colour of an odd-sized palette has no partner above
+            None if (rank & !1) | bit > last => rank - 1,
+            None => (rank & !1) | bit,
+        };
+        let raw: &mut [u8] = &mut self.plane;
+        raw[pixel] = self.embedder.order[rank as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmbedStrategy;
+    use crate::format::{load_carrier, Carrier};
+    use image::{Luma, Rgb, RgbImage};
+    use rand::{Rng, SeedableRng};
+
+    /// A palette of `n` colours in scrambled brightness order.
+    fn palette(n: usize) -> Vec<[u8; 4]> {
+        (0..n)
+            .map(|i| {
+                let v = (i * 97 % n * 255 / n.max(2)) as u8;
+                [v, v / 2, 255 - v, 255]
+            })
+            .collect()
+    }
+
+    fn plane(w: u32, h: u32, colours: usize) -> DynamicImage {
+        let mut rng = ChaCha20Rng::seed_from_u64(21);
+        DynamicImage::ImageLuma8(GrayImage::from_fn(w, h, |_, _| {
+            Luma([rng.gen_range(0..colours) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_roundtrip_steps_one_colour_along_the_order() {
+        for (colours, config) in [
+            (16, EmbedConfig::default()),
+            (15, EmbedConfig::default().with_key(b"key")),
+            (
+                256,
+                EmbedConfig::default().with_strategy(EmbedStrategy::Match { seed: Some(3) }),
+            ),
+        ] {
+            let embedder = PaletteEmbedder::new(config, &palette(colours)).unwrap();
+            let img = plane(48, 40, colours);
+            assert_eq!(embedder.capacity(&img), 48 * 40 / 8 - 22);
+            let message = b"under the third lamp post".repeat(8);
+            let encoded = embedder.embed(&img, &message).unwrap();
+            assert_eq!(embedder.extract(&encoded).unwrap(), message);
+
+            let (before, after) = (img.as_luma8().unwrap(), encoded.as_luma8().unwrap());
+            for (a, b) in before.iter().zip(after.iter()) {
+                let rank = |index: &u8| embedder.rank[*index as usize].unwrap();
+                let (a, b) = (rank(a), rank(b));
+                assert!(a.abs_diff(b) <= 1, "{} colours: {} -> {}", colours, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_colour_palette_has_no_capacity() {
+        let embedder = PaletteEmbedder::new(EmbedConfig::default(), &palette(1)).unwrap();
+        let img = plane(32, 32, 1);
+        assert_eq!(embedder.capacity(&img), 0);
+        assert!(matches!(
+            embedder.embed(&img, b"x"),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+        let rgb = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([1, 2, 3])));
+        assert!(matches!(
+            embedder.extract(&rgb),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+    }
+
+    #[test]
+    fn test_indexed_png_carrier_keeps_its_palette() {
+        let colours = palette(8);
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 40, 30);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_palette(
+            colours
+                .iter()
+                .flat_map(|c| [c[0], c[1], c[2]])
+                .collect::<Vec<_>>(),
+        );
+        let mut writer = encoder.write_header().unwrap();
+        let indices = plane(40, 30, 8).into_luma8().into_raw();
+        writer.write_image_data(&indices).unwrap();
+        writer.finish().unwrap();
+
+        let carrier = load_carrier(&bytes).unwrap();
+        let embedder = carrier.embedder("lsb", EmbedConfig::default()).unwrap();
+        assert_eq!(embedder.name(), PaletteEmbedder::NAME);
+        let encoded = embedder
+            .embed_encrypted(carrier.image(), b"sealed in a palette", b"pw")
+            .unwrap();
+        let out = carrier.with_image(encoded).unwrap().to_bytes().unwrap();
+
+        let Carrier::Indexed(reread) = load_carrier(&out).unwrap() else {
+            panic!("output is no longer indexed");
+        };
+        assert_eq!(reread.palette(), &colours[..]);
+        assert_eq!(
+            embedder.extract_encrypted(reread.indices(), b"pw").unwrap(),
+            b"sealed in a palette"
+        );
+    }
+
+    #[test]
+    fn test_transparent_pixels_stay_transparent() {
+        // Entry 0 transparent and 1 half-transparent, as in a tRNS chunk, with
+        // the darkest opaque colour right after them
+        let rgb: Vec<u8> = (0..6u8).flat_map(|i| [i * 40, i * 40, i * 40]).collect();
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 40, 40);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_palette(rgb);
+        encoder.set_trns(vec![0u8, 128]);
+        let mut writer = encoder.write_header().unwrap();
+        // A transparent background around an opaque square
+        let indices: Vec<u8> = (0..40 * 40)
+            .map(|i| match (i % 40, i / 40) {
+                (10..30, 10..30) => 2 + (i * 7 % 4) as u8,
+                _ => (i % 2) as u8,
+            })
+            .collect();
+        writer.write_image_data(&indices).unwrap();
+        writer.finish().unwrap();
+
+        let carrier = load_carrier(&bytes).unwrap();
+        let opaque = |index: u8| index >= 2;
+        for config in [
+            EmbedConfig::default(),
+            EmbedConfig::default().with_strategy(EmbedStrategy::Match { seed: Some(5) }),
+        ] {
+            let embedder = carrier.embedder("lsb", config).unwrap();
+            // One slot per opaque pixel only
+            assert_eq!(embedder.capacity(carrier.image()), 20 * 20 / 8 - 22);
+            let message = vec![0xFF; embedder.capacity(carrier.image())];
+            let encoded = embedder.embed(carrier.image(), &message).unwrap();
+            assert_eq!(embedder.extract(&encoded).unwrap(), message);
+
+            let before = carrier.image().as_luma8().unwrap();
+            let after = encoded.as_luma8().unwrap();
+            assert_ne!(before, after);
+            for (&a, &b) in before.iter().zip(after.iter()) {
+                if opaque(a) {
+                    assert!(opaque(b), "opaque colour {} became {}", a, b);
+                } else {
+                    assert_eq!(a, b, "transparent pixel changed");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::error::StegError;
+
+/// X25519 key length in bytes, public or secret.
+pub const KEY_LEN: usize = 32;
+
+/// Prefix of the text form of a public key.
+pub const PUBLIC_KEY_PREFIX: &str = "steg-pk-";
+
+/// Prefix of the text form of a secret key.
+pub const SECRET_KEY_PREFIX: &str = "steg-sk-";
+
+/// Domain separator for the sealed-box key derivation.
+const DOMAIN: &[u8] = b"steg-core/sealed-box/v1";
+
+/// Public half of a recipient key pair: messages sealed to it can only be
+/// opened with the matching `RecipientSecret`. Its text form is
+/// `steg-pk-` followed by the base64 key bytes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RecipientKey(PublicKey);
+
+/// Secret half of a recipient key pair. Never shown by `Debug`; its text
+/// form is `steg-sk-` followed by the base64 key bytes.
+#[derive(Clone)]
+pub struct RecipientSecret(StaticSecret);
+
+/// Ephemeral public key of a sealed payload, carried in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SealedParams {
+    pub ephemeral: [u8; KEY_LEN],
+}
+
+impl RecipientKey {
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(PublicKey::from(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+}
+
+impl RecipientSecret {
+    /// A fresh key pair from the operating system's RNG.
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(StaticSecret::from(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    pub fn public_key(&self) -> RecipientKey {
+        RecipientKey(PublicKey::from(&self.0))
+    }
+
+    /// Text form for storing the key in a file. Keep it private.
+    pub fn to_text(&self) -> String {
+        format!("{}{}", SECRET_KEY_PREFIX, BASE64.encode(self.to_bytes()))
+    }
+}
+
+impl fmt::Display for RecipientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, BASE64.encode(self.to_bytes()))
+    }
+}
+
+impl fmt::Debug for RecipientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RecipientKey({})", self)
+    }
+}
+
+impl FromStr for RecipientKey {
+    type Err = StegError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key(s, PUBLIC_KEY_PREFIX).map(Self::from_bytes)
+    }
+}
+
+impl fmt::Debug for RecipientSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RecipientSecret")
+            .field(&"<redacted>")
+            .finish()
+    }
+}
+
+impl FromStr for RecipientSecret {
+    type Err = StegError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key(s, SECRET_KEY_PREFIX).map(Self::from_bytes)
+    }
+}
+
+/// Decode the text form of a 32-byte key: `prefix` followed by base64.
+pub(crate) fn parse_key(text: &str, prefix: &str) -> Result<[u8; KEY_LEN], StegError> {
+    let encoded = text
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| StegError::InvalidKey(format!("expected a key starting with {}", prefix)))?;
+    let bytes = BASE64
+        .decode(encoded)
+        .map_err(|e| StegError::InvalidKey(e.to_string()))?;
+    bytes
+        .try_into()
+        .map_err(|_| StegError::InvalidKey(format!("expected {} key bytes", KEY_LEN)))
+}
+
+/// Encrypt `plaintext` so that only the holder of `recipient`'s secret key
+/// can read it (a sealed box): a fresh ephemeral X25519 key agrees a shared
+/// secret with the recipient, HKDF-SHA256 turns it into a ChaCha20-Poly1305
+/// key and nonce, and the ephemeral public key travels with the ciphertext.
+/// The sender keeps no means of decrypting the result. `aad` is
+/// authenticated but not encrypted.
+pub fn seal(
+    plaintext: &[u8],
+    recipient: &RecipientKey,
+    aad: &[u8],
+) -> Result<(SealedParams, Vec<u8>), StegError> {
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&recipient.0);
+    if !shared.was_contributory() {
+        return Err(StegError::InvalidKey(
+            "recipient key is a low-order point".into(),
+        ));
+    }
+    let (key, nonce) = derive(
+        shared.as_bytes(),
+        ephemeral_public.as_bytes(),
+        recipient.0.as_bytes(),
+    );
+    let ciphertext = ChaCha20Poly1305::new(&key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("ChaCha20-Poly1305 encryption of an in-memory buffer cannot fail");
+    Ok((
+        SealedParams {
+            ephemeral: ephemeral_public.to_bytes(),
+        },
+        ciphertext,
+    ))
+}
+
+/// Decrypt and authenticate a sealed payload along with the `aad` it was
+/// sealed with. The wrong secret key and a tampered payload or `aad` all
+/// yield `DecryptionFailed`.
+pub fn open(
+    params: &SealedParams,
+    ciphertext: &[u8],
+    secret: &RecipientSecret,
+    aad: &[u8],
+) -> Result<Vec<u8>, StegError> {
+    let ephemeral = PublicKey::from(params.ephemeral);
+    let shared = secret.0.diffie_hellman(&ephemeral);
+    if !shared.was_contributory() {
+        return Err(StegError::DecryptionFailed);
+    }
+    let recipient = PublicKey::from(&secret.0);
+    let (key, nonce) = derive(
+        shared.as_bytes(),
+        ephemeral.as_bytes(),
+        recipient.as_bytes(),
+    );
+    ChaCha20Poly1305::new(&key)
+        .decrypt(
+            &nonce,
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| StegError::DecryptionFailed)
+}
+
+/// Key and nonce for one sealed box. Both public keys are bound in, and as
+/// the ephemeral key is never reused the nonce need not be random.
+fn derive(
+    shared: &[u8; KEY_LEN],
+    ephemeral: &[u8; KEY_LEN],
+    recipient: &[u8; KEY_LEN],
+) -> (Key, Nonce) {
+    let mut salt = [0u8; 2 * KEY_LEN];
+    salt[..KEY_LEN].copy_from_slice(ephemeral);
+    salt[KEY_LEN..].copy_from_slice(recipient);
+    let mut okm = [0u8; 32 + 12];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(DOMAIN, &mut okm)
+        .expect("44 bytes is a valid HKDF-SHA256 output length");
+    (*Key::from_slice(&okm[..32]), *Nonce::from_slice(&okm[32..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let secret = RecipientSecret::generate();
+        let (params, ciphertext) = seal(b"for your eyes only", &secret.public_key(), b"").unwrap();
+        assert_eq!(
+            open(&params, &ciphertext, &secret, b"").unwrap(),
+            b"for your eyes only"
+        );
+
+        let other = RecipientSecret::generate();
+        assert!(matches!(
+            open(&params, &ciphertext, &other, b""),
+            Err(StegError::DecryptionFailed)
+        ));
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        assert!(matches!(
+            open(&params, &tampered, &secret, b""),
+            Err(StegError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            open(&params, &ciphertext, &secret, b"extra"),
+            Err(StegError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_text_form_roundtrip() {
+        let secret = RecipientSecret::generate();
+        let public = secret.public_key();
+        let text = public.to_string();
+        assert!(text.starts_with(PUBLIC_KEY_PREFIX));
+        assert_eq!(text.parse::<RecipientKey>().unwrap(), public);
+        let restored: RecipientSecret = format!("{}\n", secret.to_text()).parse().unwrap();
+        assert_eq!(restored.public_key(), public);
+
+        assert!(!format!("{:?}", secret).contains(&BASE64.encode(secret.to_bytes())));
+        assert!(matches!(
+            secret.to_text().parse::<RecipientKey>(),
+            Err(StegError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            "steg-pk-AAAA".parse::<RecipientKey>(),
+            Err(StegError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_low_order_recipient_is_rejected() {
+        let zero = RecipientKey::from_bytes([0; KEY_LEN]);
+        assert!(matches!(seal(b"x", &zero, b""), Err(StegError::InvalidKey(_))));
+    }
+}
@@ -0,0 +1,226 @@
+use image::{DynamicImage, ImageBuffer, Luma, LumaA, Pixel, Rgb, Rgba};
+
+use crate::error::StegError;
+
+/// The colour samples of a carrier in the image's own colour type and depth.
+///
+/// Slots are the colour samples in raster order: one per pixel in a
+/// greyscale image, R, G and B in a colour one. Alpha is carried along but
+/// never used. 8- and 16-bit samples are both held as `u16` and written back
+/// at their original depth by `into_image`, so embedding never converts the
+/// carrier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Samples {
+    layout: Layout,
+    width: u32,
+    height: u32,
+    data: Vec<u16>,
+}
+
+/// Colour types an embedder can work in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    L8,
+    La8,
+    Rgb8,
+    Rgba8,
+    L16,
+    La16,
+    Rgb16,
+    Rgba16,
+}
+
+impl Layout {
+    fn of(img: &DynamicImage) -> Option<Self> {
+        Some(match img {
+            DynamicImage::ImageLuma8(_) => Layout::L8,
+            DynamicImage::ImageLumaA8(_) => Layout::La8,
+            DynamicImage::ImageRgb8(_) => Layout::Rgb8,
+            DynamicImage::ImageRgba8(_) => Layout::Rgba8,
+            DynamicImage::ImageLuma16(_) => Layout::L16,
+            DynamicImage::ImageLumaA16(_) => Layout::La16,
+            DynamicImage::ImageRgb16(_) => Layout::Rgb16,
+            DynamicImage::ImageRgba16(_) => Layout::Rgba16,
+            _ => return None,
+        })
+    }
+
+    /// Samples per pixel, alpha included.
+    fn channels(self) -> usize {
+        match self {
+            Layout::L8 | Layout::L16 => 1,
+            Layout::La8 | Layout::La16 => 2,
+            Layout::Rgb8 | Layout::Rgb16 => 3,
+            Layout::Rgba8 | Layout::Rgba16 => 4,
+        }
+    }
+
+    /// Samples per pixel that carry data.
+    fn color_channels(self) -> usize {
+        match self {
+            Layout::L8 | Layout::La8 | Layout::L16 | Layout::La16 => 1,
+            _ => 3,
+        }
+    }
+
+    fn depth(self) -> u8 {
+        match self {
+            Layout::L8 | Layout::La8 | Layout::Rgb8 | Layout::Rgba8 => 8,
+            _ => 16,
+        }
+    }
+}
+
+impl Samples {
+    /// Take the samples of `img`. Fails with `UnsupportedCarrier` for
+    /// floating-point images.
+    pub fn from_image(img: &DynamicImage) -> Result<Self, StegError> {
+        let layout = Layout::of(img)
+            .ok_or_else(|| StegError::UnsupportedCarrier(format!("{:?} samples", img.color())))?;
+        let data = match img {
+            DynamicImage::ImageLuma8(buf) => widen(buf.as_raw()),
+            DynamicImage::ImageLumaA8(buf) => widen(buf.as_raw()),
+            DynamicImage::ImageRgb8(buf) => widen(buf.as_raw()),
+            DynamicImage::ImageRgba8(buf) => widen(buf.as_raw()),
+            DynamicImage::ImageLuma16(buf) => buf.as_raw().clone(),
+            DynamicImage::ImageLumaA16(buf) => buf.as_raw().clone(),
+            DynamicImage::ImageRgb16(buf) => buf.as_raw().clone(),
+            DynamicImage::ImageRgba16(buf) => buf.as_raw().clone(),
+            _ => unreachable!("layout checked above"),
+        };
+        Ok(Self {
+            layout,
+            width: img.width(),
+            height: img.height(),
+            data,
+        })
+    }
+
+    /// The image again, in the colour type and depth it was taken from.
+    pub fn into_image(self) -> DynamicImage {
+        let (w, h) = (self.width, self.height);
+        match self.layout {
+            Layout::L8 => DynamicImage::ImageLuma8(buffer::<Luma<u8>>(w, h, narrow(self.data))),
+            Layout::La8 => DynamicImage::ImageLumaA8(buffer::<LumaA<u8>>(w, h, narrow(self.data))),
+            Layout::Rgb8 => DynamicImage::ImageRgb8(buffer::<Rgb<u8>>(w, h, narrow(self.data))),
+            Layout::Rgba8 => DynamicImage::ImageRgba8(buffer::<Rgba<u8>>(w, h, narrow(self.data))),
+            Layout::L16 => DynamicImage::ImageLuma16(buffer::<Luma<u16>>(w, h, self.data)),
+            Layout::La16 => DynamicImage::ImageLumaA16(buffer::<LumaA<u16>>(w, h, self.data)),
+            Layout::Rgb16 => DynamicImage::ImageRgb16(buffer::<Rgb<u16>>(w, h, self.data)),
+            Layout::Rgba16 => DynamicImage::ImageRgba16(buffer::<Rgba<u16>>(w, h, self.data)),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Slots per pixel: 1 for greyscale, 3 for colour.
+    pub fn color_channels(&self) -> usize {
+        self.layout.color_channels()
+    }
+
+    /// Bits per sample, 8 or 16.
+    pub fn depth(&self) -> u8 {
+        self.layout.depth()
+    }
+
+    /// Largest sample value.
+    pub fn max_value(&self) -> u16 {
+        (u32::MAX >> (32 - self.depth())) as u16
+    }
+
+    /// Number of embedding slots.
+    pub fn slot_count(&self) -> usize {
+        self.width as usize * self.height as usize * self.color_channels()
+    }
+
+    /// Value of slot `slot`, counting in raster order.
+    pub fn get(&self, slot: usize) -> u16 {
+        self.data[self.index(slot)]
+    }
+
+    pub fn set(&mut self, slot: usize, value: u16) {
+        let index = self.index(slot);
+        self.data[index] = value;
+    }
+
+    /// Colour channel `ch` of the pixel at (`x`, `y`).
+    pub fn sample(&self, x: u32, y: u32, ch: usize) -> u16 {
+        let pixel = y as usize * self.width as usize + x as usize;
+        self.data[pixel * self.layout.channels() + ch]
+    }
+
+    fn index(&self, slot: usize) -> usize {
+        let colors = self.color_channels();
+        slot / colors * self.layout.channels() + slot % colors
+    }
+}
+
+/// Number of embedding slots in `img`, without copying its samples; 0 for
+/// colour types `Samples` does not support.
+pub fn slot_count(img: &DynamicImage) -> usize {
+    Layout::of(img).map_or(0, |layout| {
+        img.width() as usize * img.height() as usize * layout.color_channels()
+    })
+}
+
+fn widen(raw: &[u8]) -> Vec<u16> {
+    raw.iter().map(|&v| v as u16).collect()
+}
+
+fn narrow(data: Vec<u16>) -> Vec<u8> {
+    data.into_iter().map(|v| v as u8).collect()
+}
+
+fn buffer<P: Pixel>(
+    width: u32,
+    height: u32,
+    raw: Vec<P::Subpixel>,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    ImageBuffer::from_raw(width, height, raw).expect("sample count matches the dimensions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Rgb32FImage, RgbImage};
+
+    #[test]
+    fn test_slots_skip_alpha_and_keep_the_colour_type() {
+        let img = DynamicImage::ImageLumaA16(ImageBuffer::from_fn(3, 2, |x, y| {
+            LumaA([1000 * (x + 3 * y) as u16, 65535])
+        }));
+        let mut samples = Samples::from_image(&img).unwrap();
+        assert_eq!(samples.slot_count(), 6);
+        assert_eq!(slot_count(&img), 6);
+        assert_eq!((samples.depth(), samples.max_value()), (16, 65535));
+        assert_eq!(samples.get(4), 4000);
+        samples.set(4, 4001);
+        let out = samples.into_image();
+        let out = out.as_luma_alpha16().unwrap();
+        assert_eq!(out.get_pixel(1, 1).0, [4001, 65535]);
+
+        let rgb = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([1, 2, 3])));
+        let samples = Samples::from_image(&rgb).unwrap();
+        assert_eq!(
+            (samples.slot_count(), samples.get(5), samples.max_value()),
+            (12, 3, 255)
+        );
+        assert_eq!(samples.sample(1, 1, 2), 3);
+        assert_eq!(samples.into_image(), rgb);
+
+        let grey = DynamicImage::ImageLuma8(GrayImage::new(4, 4));
+        assert_eq!(Samples::from_image(&grey).unwrap().color_channels(), 1);
+        let float = DynamicImage::ImageRgb32F(Rgb32FImage::new(2, 2));
+        assert!(matches!(
+            Samples::from_image(&float),
+            Err(StegError::UnsupportedCarrier(_))
+        ));
+        assert_eq!(slot_count(&float), 0);
+    }
+}
@@ -0,0 +1,306 @@
+use image::DynamicImage;
+
+use crate::crypto::{EncryptionParams, NONCE_LEN, SALT_LEN, TAG_LEN};
+use crate::embedder::{data_capacity, prepare, Decoded, Embedder, Lock, Part};
+use crate::error::StegError;
+use crate::header::{Header, ShardInfo};
+
+/// Most carriers one message can be split across.
+pub const MAX_SHARDS: usize = u16::MAX as usize;
+
+/// Largest part of a message in bytes that fits in `img` as one shard,
+/// encrypted or not.
+pub fn shard_capacity<E: Embedder + ?Sized>(
+    embedder: &E,
+    img: &DynamicImage,
+    encrypted: bool,
+) -> usize {
+    let header = Header {
+        encryption: encrypted.then_some(EncryptionParams {
+            salt: [0; SALT_LEN],
+            nonce: [0; NONCE_LEN],
+        }),
+        shard: Some(ShardInfo {
+            message_id: 0,
+            sequence: 1,
+            total: 1,
+        }),
+        ..Header::for_payload(&[])
+    };
+    let capacity = data_capacity(embedder, img, header);
+    if encrypted {
+        capacity.saturating_sub(TAG_LEN)
+    } else {
+        capacity
+    }
+}
+
+/// Split `message` across `carriers`, one shard per image, and hide each
+/// shard with `embedder`, encrypted under `passphrase` if given.
+///
+/// Every shard is a complete payload with its own header, recording a random
+/// message id shared by all shards, its sequence number and the shard count,
+/// so `reassemble` accepts the images in any order. Shards are sized in
+/// proportion to the carriers' capacities. Returns the encoded images in the
+/// order of `carriers`.
+pub fn split<E: Embedder + ?Sized>(
+    embedder: &E,
+    carriers: &[DynamicImage],
+    message: &[u8],
+    passphrase: Option<&[u8]>,
+) -> Result<Vec<DynamicImage>, StegError> {
+    if carriers.is_empty() || carriers.len() > MAX_SHARDS {
+        return Err(StegError::IncompatibleOptions(format!(
+            "a message is split across 1 to {} carriers",
+            MAX_SHARDS
+        )));
+    }
+    let capacities: Vec<usize> = carriers
+        .iter()
+        .map(|img| shard_capacity(embedder, img, passphrase.is_some()))
+        .collect();
+    let total_capacity: usize = capacities.iter().sum();
+    if message.len() > total_capacity {
+        return Err(StegError::MessageTooLarge {
+            needed: message.len() * 8,
+            capacity: total_capacity * 8,
+        });
+    }
+
+    let message_id = rand::random::<u64>();
+    let total = carriers.len() as u16;
+    let mut rest = message;
+    let mut encoded = Vec::with_capacity(carriers.len());
+    for (i, (img, len)) in carriers
+        .iter()
+        .zip(shard_lengths(message.len(), &capacities))
+        .enumerate()
+    {
+        let (part, tail) = rest.split_at(len);
+        rest = tail;
+        let shard = ShardInfo {
+            message_id,
+            sequence: i as u16 + 1,
+            total,
+        };
+        let (header, payload) = prepare(
+            embedder.config(),
+            part,
+            passphrase.map(Lock::Passphrase),
+            Part::Shard(shard),
+        )?;
+        encoded.push(embedder.embed_payload(img, &header, &payload)?);
+    }
+    Ok(encoded)
+}
+
+/// Put a message back together from decoded shards given in any order.
+/// Fails with `DuplicateShards` or `MissingShards` naming the sequence
+/// numbers concerned, and with `ShardMismatch` when the decoded payloads are
+/// not all shards of the same message.
+pub fn reassemble(shards: &[Decoded]) -> Result<Vec<u8>, StegError> {
+    let infos = shards
+        .iter()
+        .enumerate()
+        .map(|(i, decoded)| {
+            decoded.header.shard.ok_or_else(|| {
+                StegError::ShardMismatch(format!(
+                    "image {} carries a complete message, not a shard",
+                    i + 1
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = infos
+        .first()
+        .ok_or_else(|| StegError::ShardMismatch("no shards given".into()))?;
+    if infos
+        .iter()
+        .any(|info| info.message_id != first.message_id || info.total != first.total)
+    {
+        return Err(StegError::ShardMismatch(
+            "shards of different messages".into(),
+        ));
+    }
+
+    let mut parts: Vec<Option<&[u8]>> = vec![None; first.total as usize];
+    let mut duplicates = Vec::new();
+    for (info, decoded) in infos.iter().zip(shards) {
+        let part = &mut parts[info.sequence as usize - 1];
+        if part.is_some() {
+            duplicates.push(info.sequence);
+        }
+        *part = Some(&decoded.message);
+    }
+    if !duplicates.is_empty() {
+        duplicates.sort_unstable();
+        duplicates.dedup();
+        return Err(StegError::DuplicateShards(duplicates));
+    }
+    let missing: Vec<u16> = (1..=first.total)
+        .filter(|&sequence| parts[sequence as usize - 1].is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Err(StegError::MissingShards {
+            missing,
+            total: first.total,
+        });
+    }
+    Ok(parts.into_iter().flatten().flatten().copied().collect())
+}
+
+/// Decode every image with `embedder` and reassemble the sharded message.
+pub fn extract<E: Embedder + ?Sized>(
+    embedder: &E,
+    images: &[DynamicImage],
+    passphrase: Option<&[u8]>,
+) -> Result<Vec<u8>, StegError> {
+    let shards = images
+        .iter()
+        .map(|img| embedder.extract_message(img, passphrase))
+        .collect::<Result<Vec<_>, _>>()?;
+    reassemble(&shards)
+}
+
+/// Split `len` bytes over carriers in proportion to their `capacities`,
+/// topping up in carrier order what rounding down left over.
+fn shard_lengths(len: usize, capacities: &[usize]) -> Vec<usize> {
+    let total: usize = capacities.iter().sum();
+    if total == 0 {
+        return vec![0; capacities.len()];
+    }
+    let mut lengths: Vec<usize> = capacities
+        .iter()
+        .map(|&capacity| (len as u128 * capacity as u128 / total as u128) as usize)
+        .collect();
+    let mut rest = len - lengths.iter().sum::<usize>();
+    for (length, &capacity) in lengths.iter_mut().zip(capacities) {
+        let extra = rest.min(capacity - *length);
+        *length += extra;
+        rest -= extra;
+    }
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsb::LsbEmbedder;
+    use crate::matrix::MatrixEmbedder;
+    use image::RgbaImage;
+
+    fn carrier(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            w,
+            h,
+            image::Rgba([90, 140, 200, 255]),
+        ))
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_split_and_reassemble_in_any_order() {
+        let carriers = [carrier(40, 40), carrier(60, 60), carrier(30, 30)];
+        let embedder = LsbEmbedder::default();
+        let capacity: usize = carriers
+            .iter()
+            .map(|img| shard_capacity(&embedder, img, true))
+            .sum();
+        let message = message(capacity);
+        let mut shards = split(&embedder, &carriers, &message, Some(b"spread")).unwrap();
+        shards.rotate_left(1);
+        assert_eq!(
+            extract(&embedder, &shards, Some(b"spread")).unwrap(),
+            message
+        );
+
+        // One byte more than fits
+        let mut longer = message.clone();
+        longer.push(0);
+        assert!(matches!(
+            split(&embedder, &carriers, &longer, Some(b"spread")),
+            Err(StegError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_and_duplicate_shards_are_flagged() {
+        let carriers = vec![carrier(40, 40); 4];
+        let embedder = MatrixEmbedder::default();
+        let shards = split(&embedder, &carriers, &message(500), None).unwrap();
+        let decoded: Vec<Decoded> = shards
+            .iter()
+            .map(|img| embedder.extract_message(img, None).unwrap())
+            .collect();
+
+        match reassemble(&[decoded[3].clone(), decoded[0].clone()]) {
+            Err(StegError::MissingShards { missing, total }) => {
+                assert_eq!(missing, vec![2, 3]);
+                assert_eq!(total, 4);
+            }
+            other => panic!("expected missing shards, got {:?}", other),
+        }
+        let mut doubled = decoded.clone();
+        doubled.push(decoded[1].clone());
+        assert!(matches!(
+            reassemble(&doubled),
+            Err(StegError::DuplicateShards(d)) if d == vec![2]
+        ));
+        assert_eq!(reassemble(&decoded).unwrap(), message(500));
+    }
+
+    #[test]
+    fn test_shards_of_other_messages_are_rejected() {
+        let carriers = vec![carrier(40, 40); 2];
+        let embedder = LsbEmbedder::default();
+        let first = split(&embedder, &carriers, b"first message", None).unwrap();
+        let second = split(&embedder, &carriers, b"second message", None).unwrap();
+        assert!(matches!(
+            extract(&embedder, &[first[0].clone(), second[1].clone()], None),
+            Err(StegError::ShardMismatch(_))
+        ));
+
+        let whole = embedder.embed(&carriers[0], b"not sharded").unwrap();
+        assert!(matches!(
+            extract(&embedder, &[whole], None),
+            Err(StegError::ShardMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_renumbered_encrypted_shards_fail_to_decrypt() {
+        let carriers = vec![carrier(40, 40); 2];
+        let embedder = LsbEmbedder::default();
+        let shards = split(&embedder, &carriers, &message(200), Some(b"pw")).unwrap();
+        // Swap the sequence numbers of the two shards, rewriting the headers
+        // with valid checksums
+        let swapped: Vec<DynamicImage> = shards
+            .iter()
+            .map(|img| {
+                let (mut header, payload) = embedder.extract_payload(img).unwrap();
+                let shard = header.shard.as_mut().unwrap();
+                shard.sequence = 3 - shard.sequence;
+                embedder.embed_payload(img, &header, &payload).unwrap()
+            })
+            .collect();
+        assert!(matches!(
+            extract(&embedder, &swapped, Some(b"pw")),
+            Err(StegError::DecryptionFailed)
+        ));
+        assert_eq!(
+            extract(&embedder, &shards, Some(b"pw")).unwrap(),
+            message(200)
+        );
+    }
+
+    #[test]
+    fn test_shard_lengths_follow_capacity() {
+        assert_eq!(shard_lengths(20, &[10, 30]), vec![5, 15]);
+        assert_eq!(shard_lengths(10, &[10, 30]), vec![3, 7]);
+        assert_eq!(shard_lengths(7, &[3, 3, 3]), vec![3, 2, 2]);
+        assert_eq!(shard_lengths(0, &[0, 0]), vec![0, 0]);
+    }
+}
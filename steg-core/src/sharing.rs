@@ -0,0 +1,366 @@
+use image::DynamicImage;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::crypto::{EncryptionParams, NONCE_LEN, SALT_LEN, TAG_LEN};
+use crate::embedder::{data_capacity, prepare, Decoded, Embedder, Lock, Part};
+use crate::error::StegError;
+use crate::fec::{div, mul};
+use crate::header::{Header, ShareInfo};
+
+/// Most shares one message can be split into: the non-zero points of GF(256).
+pub const MAX_SHARES: u8 = 255;
+
+/// Bytes of the message's SHA-256 digest shared along with it, so that a
+/// corrupt or forged share is caught when the message is recovered.
+pub const DIGEST_LEN: usize = 16;
+
+/// Shamir secret sharing of `secret` over GF(256), byte by byte.
+///
+/// Each byte is the constant term of a random polynomial of degree
+/// `threshold - 1`; share `x` (1 to `total`) holds the polynomials evaluated at
+/// `x`. Any `threshold` shares determine the polynomials, while fewer are
+/// consistent with every possible secret and so reveal nothing but its length.
+/// Returns the shares in index order.
+pub fn split_secret(secret: &[u8], threshold: u8, total: u8) -> Result<Vec<Vec<u8>>, StegError> {
+    if threshold == 0 || threshold > total {
+        return Err(StegError::IncompatibleOptions(format!(
+            "threshold {} must be between 1 and the share count {}",
+            threshold, total
+        )));
+    }
+    // coefficients[j] holds the degree j+1 coefficient for every byte
+    let coefficients: Vec<Vec<u8>> = (1..threshold)
+        .map(|_| {
+            let mut c = vec![0u8; secret.len()];
+            OsRng.fill_bytes(&mut c);
+            c
+        })
+        .collect();
+    Ok((1..=total)
+        .map(|x| {
+            (0..secret.len())
+                .map(|i| {
+                    // Horner's rule, highest degree first, secret last
+                    let high = coefficients
+                        .iter()
+                        .rev()
+                        .fold(0u8, |acc, c| mul(acc, x) ^ c[i]);
+                    mul(high, x) ^ secret[i]
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// Recover a secret from `(index, share)` pairs by Lagrange interpolation at
+/// zero. Indices must be distinct and non-zero, and the shares of equal length;
+/// with fewer pairs than the threshold the result is meaningless.
+pub fn combine_shares(shares: &[(u8, &[u8])]) -> Vec<u8> {
+    let len = shares.first().map_or(0, |(_, s)| s.len());
+    let weights: Vec<u8> = shares
+        .iter()
+        .map(|&(xi, _)| {
+            shares
+                .iter()
+                .filter(|&&(xj, _)| xj != xi)
+                .fold(1u8, |acc, &(xj, _)| mul(acc, div(xj, xj ^ xi)))
+        })
+        .collect();
+    (0..len)
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&weights)
+                .fold(0u8, |acc, (&(_, share), &w)| acc ^ mul(w, share[i]))
+        })
+        .collect()
+}
+
+/// Longest message in bytes whose share fits in `img`, encrypted or not.
+pub fn share_capacity<E: Embedder + ?Sized>(
+    embedder: &E,
+    img: &DynamicImage,
+    encrypted: bool,
+) -> usize {
+    let header = Header {
+        encryption: encrypted.then_some(EncryptionParams {
+            salt: [0; SALT_LEN],
+            nonce: [0; NONCE_LEN],
+        }),
+        share: Some(ShareInfo {
+            secret_id: 0,
+            index: 1,
+            threshold: 1,
+            total: 1,
+        }),
+        ..Header::for_payload(&[])
+    };
+    let capacity = data_capacity(embedder, img, header).saturating_sub(DIGEST_LEN);
+    if encrypted {
+        capacity.saturating_sub(TAG_LEN)
+    } else {
+        capacity
+    }
+}
+
+/// Split `message` into one share per carrier, any `threshold` of which
+/// recover it, and hide each share with `embedder`, encrypted under
+/// `passphrase` if given. Unlike sharding, every carrier holds a share as long
+/// as the whole message plus its digest. Returns the encoded images in the order of `carriers`.
+/// Shares look random and do not compress, so an embedder configured to
+/// compress is refused.
+pub fn embed_shares<E: Embedder + ?Sized>(
+    embedder: &E,
+    carriers: &[DynamicImage],
+    message: &[u8],
+    threshold: u8,
+    passphrase: Option<&[u8]>,
+) -> Result<Vec<DynamicImage>, StegError> {
+    if carriers.len() > MAX_SHARES as usize {
+        return Err(StegError::IncompatibleOptions(format!(
+            "at most {} shares per message",
+            MAX_SHARES
+        )));
+    }
+    if embedder.config().compress {
+        return Err(StegError::IncompatibleOptions(
+            "secret shares do not compress".into(),
+        ));
+    }
+    let total = carriers.len() as u8;
+    let shares = split_secret(&[message, &digest(message)].concat(), threshold, total)?;
+    let secret_id = rand::random::<u64>();
+    carriers
+        .iter()
+        .zip(shares)
+        .zip(1..=total)
+        .map(|((img, share), index)| {
+            let info = ShareInfo {
+                secret_id,
+                index,
+                threshold,
+                total,
+            };
+            let (header, payload) = prepare(
+                embedder.config(),
+                &share,
+                passphrase.map(Lock::Passphrase),
+                Part::Share(info),
+            )?;
+            embedder.embed_payload(img, &header, &payload)
+        })
+        .collect()
+}
+
+/// Recover a message from decoded shares given in any order. Fails with
+/// `NotEnoughShares` below the threshold and with `ShareMismatch` when the
+/// payloads are not shares of one message, or when the recovered message
+/// does not match its digest.
+pub fn recover(shares: &[Decoded]) -> Result<Vec<u8>, StegError> {
+    let mut points: Vec<(ShareInfo, &[u8])> = Vec::with_capacity(shares.len());
+    for (i, decoded) in shares.iter().enumerate() {
+        let info = decoded.header.share.ok_or_else(|| {
+            StegError::ShareMismatch(format!("image {} does not carry a share", i + 1))
+        })?;
+        if let Some((first, _)) = points.first() {
+            if info.secret_id != first.secret_id
+                || info.threshold != first.threshold
+                || info.total != first.total
+            {
+                return Err(StegError::ShareMismatch(
+                    "shares of different messages".into(),
+                ));
+            }
+        }
+        match points.iter().find(|(other, _)| other.index == info.index) {
+            Some((_, data)) if *data != decoded.message.as_slice() => {
+                return Err(StegError::ShareMismatch(format!(
+                    "two different shares numbered {}",
+                    info.index
+                )));
+            }
+            Some(_) => {}
+            None => points.push((info, &decoded.message)),
+        }
+    }
+
+    let threshold = points.first().map_or(1, |(info, _)| info.threshold);
+    if points.len() < threshold as usize {
+        return Err(StegError::NotEnoughShares {
+            found: points.len(),
+            threshold,
+        });
+    }
+    let len = points[0].1.len();
+    if points.iter().any(|(_, data)| data.len() != len) {
+        return Err(StegError::ShareMismatch(
+            "shares of different lengths".into(),
+        ));
+    }
+    let selected: Vec<(u8, &[u8])> = points
+        .iter()
+        .take(threshold as usize)
+        .map(|(info, data)| (info.index, *data))
+        .collect();
+    let secret = combine_shares(&selected);
+    let (message, check) = secret.split_at(secret.len().saturating_sub(DIGEST_LEN));
+    if check != digest(message) {
+        return Err(StegError::ShareMismatch(
+            "the recovered message fails its digest; a share is corrupt".into(),
+        ));
+    }
+    Ok(message.to_vec())
+}
+
+/// The first `DIGEST_LEN` bytes of the SHA-256 digest of `message`.
+fn digest(message: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut digest = [0u8; DIGEST_LEN];
+    digest.copy_from_slice(&Sha256::digest(message)[..DIGEST_LEN]);
+    digest
+}
+
+/// Decode every image with `embedder` and recover the shared message.
+pub fn extract_shares<E: Embedder + ?Sized>(
+    embedder: &E,
+    images: &[DynamicImage],
+    passphrase: Option<&[u8]>,
+) -> Result<Vec<u8>, StegError> {
+    let shares = images
+        .iter()
+        .map(|img| embedder.extract_message(img, passphrase))
+        .collect::<Result<Vec<_>, _>>()?;
+    recover(&shares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmbedConfig;
+    use crate::lsb::LsbEmbedder;
+    use image::RgbaImage;
+
+    fn carrier(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            w,
+            h,
+            image::Rgba([90, 140, 200, 255]),
+        ))
+    }
+
+    #[test]
+    fn test_any_threshold_subset_recovers() {
+        let secret = b"the package is under the third bench";
+        let shares = split_secret(secret, 3, 5).unwrap();
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let picked: Vec<(u8, &[u8])> = [a, b, c]
+                        .iter()
+                        .map(|&i| (i as u8 + 1, shares[i].as_slice()))
+                        .collect();
+                    assert_eq!(combine_shares(&picked), secret);
+                }
+            }
+        }
+        let two: Vec<(u8, &[u8])> = vec![(1, &shares[0]), (2, &shares[1])];
+        assert_ne!(combine_shares(&two), secret);
+    }
+
+    #[test]
+    fn test_single_share_looks_uniform() {
+        // Below the threshold a share is a uniformly random pad, whatever
+        // the secret; a constant secret must not show through
+        let shares = split_secret(&[0u8; 4096], 2, 3).unwrap();
+        for share in &shares {
+            let mut counts = [0usize; 256];
+            for &byte in share {
+                counts[byte as usize] += 1;
+            }
+            assert!(counts.iter().all(|&c| c < 40), "{:?}", counts);
+            assert!(counts.iter().filter(|&&c| c > 0).count() > 240);
+        }
+    }
+
+    #[test]
+    fn test_embed_and_recover_from_carriers() {
+        let carriers = vec![carrier(40, 40); 4];
+        let embedder = LsbEmbedder::default();
+        let message = b"two of four paintings are enough";
+        let encoded = embed_shares(&embedder, &carriers, message, 2, Some(b"pw")).unwrap();
+
+        let picked = [encoded[3].clone(), encoded[1].clone()];
+        assert_eq!(
+            extract_shares(&embedder, &picked, Some(b"pw")).unwrap(),
+            message
+        );
+        assert!(matches!(
+            extract_shares(&embedder, &encoded[2..3], Some(b"pw")),
+            Err(StegError::NotEnoughShares {
+                found: 1,
+                threshold: 2
+            })
+        ));
+        // A repeated image counts once
+        assert!(matches!(
+            extract_shares(
+                &embedder,
+                &[encoded[0].clone(), encoded[0].clone()],
+                Some(b"pw")
+            ),
+            Err(StegError::NotEnoughShares { found: 1, .. })
+        ));
+
+        let other = embed_shares(&embedder, &carriers, message, 2, Some(b"pw")).unwrap();
+        assert!(matches!(
+            extract_shares(
+                &embedder,
+                &[encoded[0].clone(), other[1].clone()],
+                Some(b"pw")
+            ),
+            Err(StegError::ShareMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_share_is_caught() {
+        let carriers = vec![carrier(40, 40); 3];
+        let embedder = LsbEmbedder::default();
+        let message = b"any two of three, unencrypted";
+        let encoded = embed_shares(&embedder, &carriers, message, 2, None).unwrap();
+        let mut decoded: Vec<Decoded> = encoded
+            .iter()
+            .map(|img| embedder.extract_message(img, None).unwrap())
+            .collect();
+        assert_eq!(recover(&decoded).unwrap(), message);
+        decoded[0].message[3] ^= 0x40;
+        assert!(matches!(
+            recover(&decoded),
+            Err(StegError::ShareMismatch(_))
+        ));
+        assert_eq!(recover(&decoded[1..]).unwrap(), message);
+    }
+
+    #[test]
+    fn test_rejects_bad_threshold() {
+        assert!(matches!(
+            split_secret(b"x", 4, 3),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+        assert!(matches!(
+            split_secret(b"x", 0, 3),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_compression() {
+        let embedder = LsbEmbedder::new(EmbedConfig::default().with_compression(true)).unwrap();
+        let message = [b'a'; 64];
+        assert!(matches!(
+            embed_shares(&embedder, &vec![carrier(40, 40); 2], &message, 2, None),
+            Err(StegError::IncompatibleOptions(_))
+        ));
+    }
+}
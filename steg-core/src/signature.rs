@@ -0,0 +1,316 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, SIGNATURE_LENGTH};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::error::StegError;
+use crate::recipient::{parse_key, KEY_LEN};
+
+/// Prefix of the text form of a signing public key.
+pub const SIGNER_KEY_PREFIX: &str = "steg-sign-pk-";
+
+/// Prefix of the text form of a signing secret key.
+pub const SIGNER_SECRET_PREFIX: &str = "steg-sign-sk-";
+
+/// Bytes a signature adds to a message: the signer's public key and the
+/// signature itself.
+pub const SIGNATURE_BLOCK_LEN: usize = KEY_LEN + SIGNATURE_LENGTH;
+
+/// Domain separator prepended to the message before signing, so a signature
+/// made here is never valid for anything else.
+const DOMAIN: &[u8] = b"steg-core/signature/v1";
+
+/// Public half of a sender's Ed25519 signing key. Its text form is
+/// `steg-sign-pk-` followed by the base64 key bytes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SignerKey(VerifyingKey);
+
+/// Secret half of a sender's Ed25519 signing key. Never shown by `Debug`; its
+/// text form is `steg-sign-sk-` followed by the base64 key bytes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SignerSecret(SigningKey);
+
+impl SignerKey {
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Result<Self, StegError> {
+        VerifyingKey::from_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| StegError::InvalidKey("not a valid Ed25519 public key".into()))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+}
+
+impl SignerSecret {
+    /// A fresh signing key from the operating system's RNG.
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut bytes);
+        Self::from_bytes(bytes)
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(SigningKey::from_bytes(&bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; KEY_LEN] {
+        self.0.to_bytes()
+    }
+
+    pub fn public_key(&self) -> SignerKey {
+        SignerKey(self.0.verifying_key())
+    }
+
+    /// Text form for storing the key in a file. Keep it private.
+    pub fn to_text(&self) -> String {
+        format!("{}{}", SIGNER_SECRET_PREFIX, BASE64.encode(self.to_bytes()))
+    }
+}
+
+impl fmt::Display for SignerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SIGNER_KEY_PREFIX, BASE64.encode(self.to_bytes()))
+    }
+}
+
+impl fmt::Debug for SignerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SignerKey({})", self)
+    }
+}
+
+impl FromStr for SignerKey {
+    type Err = StegError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(parse_key(s, SIGNER_KEY_PREFIX)?)
+    }
+}
+
+impl fmt::Debug for SignerSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SignerSecret").field(&"<redacted>").finish()
+    }
+}
+
+impl FromStr for SignerSecret {
+    type Err = StegError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key(s, SIGNER_SECRET_PREFIX).map(Self::from_bytes)
+    }
+}
+
+/// What a decoded message says about its sender, before any trust decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// The message carries no signature.
+    Unsigned,
+    /// A signature is present but does not match the message.
+    Invalid,
+    /// The message was signed by this key.
+    Valid(SignerKey),
+}
+
+/// Result of checking a decoded message against a set of trusted signers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// The message carries no signature.
+    Unsigned,
+    /// A signature is present but does not match the message: it was altered
+    /// or the signature forged.
+    Invalid,
+    /// Validly signed, but by a key that is not trusted.
+    Untrusted(SignerKey),
+    /// Validly signed by the trusted key called `name`.
+    Trusted { name: String, key: SignerKey },
+}
+
+impl Verification {
+    /// Whether the sender is a trusted signer.
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, Verification::Trusted { .. })
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verification::Trusted { name, .. } => write!(f, "signed by {}", name),
+            Verification::Untrusted(key) => write!(f, "UNVERIFIED (signed by unknown key {})", key),
+            Verification::Invalid => write!(f, "UNVERIFIED (bad signature)"),
+            Verification::Unsigned => write!(f, "UNVERIFIED (not signed)"),
+        }
+    }
+}
+
+/// Named public keys whose signatures are trusted.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedSigners {
+    signers: Vec<(String, SignerKey)>,
+}
+
+impl TrustedSigners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust `key` under `name`.
+    pub fn add(&mut self, name: impl Into<String>, key: SignerKey) {
+        self.signers.push((name.into(), key));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signers.is_empty()
+    }
+
+    /// Name a trusted key goes by.
+    pub fn name_of(&self, key: &SignerKey) -> Option<&str> {
+        self.signers
+            .iter()
+            .find(|(_, trusted)| trusted == key)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Decide whether a decoded signature comes from a trusted signer.
+    pub fn verify(&self, status: &SignatureStatus) -> Verification {
+        match status {
+            SignatureStatus::Unsigned => Verification::Unsigned,
+            SignatureStatus::Invalid => Verification::Invalid,
+            SignatureStatus::Valid(key) => match self.name_of(key) {
+                Some(name) => Verification::Trusted {
+                    name: name.to_string(),
+                    key: *key,
+                },
+                None => Verification::Untrusted(*key),
+            },
+        }
+    }
+}
+
+/// Parses a trusted-keys file: one `name steg-sign-pk-...` pair per line.
+/// Blank lines and lines starting with `#` are skipped.
+impl FromStr for TrustedSigners {
+    type Err = StegError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut trusted = Self::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, key) = line.rsplit_once(char::is_whitespace).ok_or_else(|| {
+                StegError::InvalidKey(format!("line {}: expected a name and a key", number + 1))
+            })?;
+            let key = key
+                .parse()
+                .map_err(|e| StegError::InvalidKey(format!("line {}: {}", number + 1, e)))?;
+            trusted.add(name.trim(), key);
+        }
+        Ok(trusted)
+    }
+}
+
+/// Append `secret`'s public key and its signature over `message`.
+pub(crate) fn sign(message: &[u8], secret: &SignerSecret) -> Vec<u8> {
+    let signature = secret.0.sign(&signed_data(message));
+    let mut signed = Vec::with_capacity(message.len() + SIGNATURE_BLOCK_LEN);
+    signed.extend_from_slice(message);
+    signed.extend_from_slice(&secret.0.verifying_key().to_bytes());
+    signed.extend_from_slice(&signature.to_bytes());
+    signed
+}
+
+/// Split a message written by `sign` into the message and the outcome of
+/// checking its signature. Fails with `ChecksumMismatch` when it is too
+/// short to hold a signature at all: the header promised one, so the payload
+/// is damaged.
+pub(crate) fn open(mut signed: Vec<u8>) -> Result<(Vec<u8>, SignatureStatus), StegError> {
+    if signed.len() < SIGNATURE_BLOCK_LEN {
+        return Err(StegError::ChecksumMismatch);
+    }
+    let block = signed.split_off(signed.len() - SIGNATURE_BLOCK_LEN);
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&block[..KEY_LEN]);
+    let mut signature = [0u8; SIGNATURE_LENGTH];
+    signature.copy_from_slice(&block[KEY_LEN..]);
+    let status = match VerifyingKey::from_bytes(&key) {
+        Ok(key)
+            if key
+                .verify_strict(&signed_data(&signed), &Signature::from_bytes(&signature))
+                .is_ok() =>
+        {
+            SignatureStatus::Valid(SignerKey(key))
+        }
+        _ => SignatureStatus::Invalid,
+    };
+    Ok((signed, status))
+}
+
+fn signed_data(message: &[u8]) -> Vec<u8> {
+    [DOMAIN, message].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_open() {
+        let secret = SignerSecret::generate();
+        let signed = sign(b"meet at noon", &secret);
+        assert_eq!(signed.len(), 12 + SIGNATURE_BLOCK_LEN);
+        let (message, status) = open(signed.clone()).unwrap();
+        assert_eq!(message, b"meet at noon");
+        assert_eq!(status, SignatureStatus::Valid(secret.public_key()));
+
+        let mut altered = signed.clone();
+        altered[0] ^= 1;
+        assert_eq!(open(altered).unwrap().1, SignatureStatus::Invalid);
+        // Swapping in another key does not make the signature someone else's
+        let mut reattributed = signed;
+        reattributed[12..12 + KEY_LEN]
+            .copy_from_slice(&SignerSecret::generate().public_key().to_bytes());
+        assert_eq!(open(reattributed).unwrap().1, SignatureStatus::Invalid);
+        assert!(matches!(
+            open(vec![0; 10]),
+            Err(StegError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_trusted_signers() {
+        let alice = SignerSecret::generate();
+        let mallory = SignerSecret::generate();
+        let text = format!("# team keys\n\nalice {}\n", alice.public_key());
+        let trusted: TrustedSigners = text.parse().unwrap();
+
+        let status = open(sign(b"x", &alice)).unwrap().1;
+        let verification = trusted.verify(&status);
+        assert!(verification.is_trusted());
+        assert_eq!(verification.to_string(), "signed by alice");
+
+        let status = open(sign(b"x", &mallory)).unwrap().1;
+        assert_eq!(
+            trusted.verify(&status),
+            Verification::Untrusted(mallory.public_key())
+        );
+        assert!(trusted
+            .verify(&SignatureStatus::Unsigned)
+            .to_string()
+            .starts_with("UNVERIFIED"));
+
+        assert!(matches!(
+            "alice".parse::<TrustedSigners>(),
+            Err(StegError::InvalidKey(_))
+        ));
+        let restored: SignerSecret = alice.to_text().parse().unwrap();
+        assert_eq!(restored.public_key(), alice.public_key());
+    }
+}
@@ -1,43 +1,259 @@
+use clap::builder::PossibleValuesParser;
 use clap::Parser;
+use std::io::Write;
 use std::path::PathBuf;
 
+/// Name an embedded file is restored under, in an `--output` directory, when
+/// it carries no safe filename of its own.
+const FALLBACK_FILENAME: &str = "decoded-message";
+
 #[derive(Parser, Debug)]
-#[command(name = "steg-decode", about = "Decode a hidden message from a PNG image")]
+#[command(name = "steg-decode", about = "Decode a hidden message from an image")]
 struct Args {
-    /// Input PNG image with hidden message
-    #[arg(short, long)]
-    input: PathBuf,
+    /// Input image with hidden message (PNG, GIF, JPEG, BMP, TIFF, WebP or
+    /// QOI); give every shard of a message split across several images, in
+    /// any order
+    #[arg(short, long, num_args = 1.., required = true)]
+    input: Vec<PathBuf>,
 
-    /// Optional output file to write the decoded message to
+    /// Optional output file to write the decoded message to, or a directory
+    /// to restore an embedded file into under its original name (or under
+    /// "decoded-message" when it has no usable one)
     #[arg(short, long)]
     output: Option<PathBuf>,
+
+    /// Output the extracted bytes as they are, without unpacking a file
+    /// container
+    #[arg(long)]
+    raw: bool,
+
+    /// Overwrite an existing file when restoring one under its original name
+    #[arg(long)]
+    force: bool,
+
+    /// Passphrase for an encrypted message
+    #[arg(short, long, conflicts_with = "secret_key")]
+    passphrase: Option<String>,
+
+    /// Secret key file for a message sealed to your public key
+    #[arg(short, long)]
+    secret_key: Option<PathBuf>,
+
+    /// File of trusted signers, one "name steg-sign-pk-..." per line; signed
+    /// messages from anyone else are reported as UNVERIFIED
+    #[arg(short, long)]
+    trusted_keys: Option<PathBuf>,
+
+    /// Embedding scheme the message was hidden with; palette images always
+    /// use the palette scheme and JPEG images the F5 scheme
+    #[arg(
+        long,
+        default_value = "lsb",
+        value_parser = PossibleValuesParser::new(steg_core::embedder::SCHEMES.iter().copied())
+    )]
+    scheme: String,
+
+    /// Key the message was scattered with at encode time
+    #[arg(short, long)]
+    key: Option<String>,
+
+    /// Refuse compressed messages that expand beyond this many bytes
+    #[arg(long, default_value_t = steg_core::compress::DEFAULT_DECOMPRESSION_LIMIT)]
+    max_decompressed_size: usize,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let img = image::open(&args.input)?;
-    let message_bytes = steg_core::decode(&img)?;
+    let mut config =
+        steg_core::EmbedConfig::default().with_decompression_limit(args.max_decompressed_size);
+    if let Some(key) = &args.key {
+        config = config.with_key(key.as_bytes());
+    }
+    let secret_key: Option<steg_core::RecipientSecret> = match &args.secret_key {
+        Some(path) => Some(std::fs::read_to_string(path)?.parse()?),
+        None => None,
+    };
+    let trusted: steg_core::TrustedSigners = match &args.trusted_keys {
+        Some(path) => std::fs::read_to_string(path)?.parse()?,
+        None => steg_core::TrustedSigners::new(),
+    };
+    let mut shards = Vec::with_capacity(args.input.len());
+    for input in &args.input {
+        let carrier = steg_core::format::open_carrier(input)?;
+        let embedder = carrier.embedder(&args.scheme, config.clone())?;
+        let img = carrier.image();
+        let result = match &secret_key {
+            Some(secret) => embedder.extract_sealed(img, secret),
+            None => embedder.extract_message(img, args.passphrase.as_deref().map(str::as_bytes)),
+        };
+        let decoded = match result {
+            Err(steg_core::StegError::ChecksumMismatch | steg_core::StegError::Uncorrectable) => {
+                eprintln!("Error: message in {:?} present but corrupted", input);
+                std::process::exit(1);
+            }
+            Err(
+                e @ (steg_core::StegError::Expired { .. }
+                | steg_core::StegError::NotYetValid { .. }),
+            ) => {
+                eprintln!("Error: {} ({:?})", e, input);
+                std::process::exit(1);
+            }
+            result => result?,
+        };
+        eprintln!("Found {} header in {:?}", decoded.header.version, input);
+        if let Some(shard) = &decoded.header.shard {
+            eprintln!("Shard {} of {}", shard.sequence, shard.total);
+        }
+        if let Some(share) = &decoded.header.share {
+            eprintln!(
+                "Share {} of {} ({} needed)",
+                share.index, share.total, share.threshold
+            );
+        }
+        if let Some(validity) = &decoded.header.validity {
+            if let Some(not_before) = validity.not_before {
+                eprintln!(
+                    "Readable from: {}",
+                    steg_core::clock::format_unix_time(not_before)
+                );
+            }
+            if let Some(expires_at) = validity.expires_at {
+                eprintln!(
+                    "Expires: {}",
+                    steg_core::clock::format_unix_time(expires_at)
+                );
+            }
+        }
+        if decoded.header.fec.is_some() {
+            eprintln!(
+                "Error correction repaired {} byte(s)",
+                decoded.errors_corrected
+            );
+        }
+        match trusted.verify(&decoded.signature) {
+            steg_core::Verification::Trusted { name, key } => {
+                eprintln!("Signer: {} ({})", name, key)
+            }
+            verification => eprintln!("Signer: {}", verification),
+        }
+        shards.push(decoded);
+    }
+    let is_container = shards.iter().any(|d| d.header.container);
+    let message_bytes = if shards.iter().any(|d| d.header.share.is_some()) {
+        match steg_core::sharing::recover(&shards) {
+            Err(
+                e @ (steg_core::StegError::NotEnoughShares { .. }
+                | steg_core::StegError::ShareMismatch(_)),
+            ) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            result => result?,
+        }
+    } else if shards.len() == 1 && shards[0].header.shard.is_none() {
+        shards.remove(0).message
+    } else {
+        match steg_core::shard::reassemble(&shards) {
+            Err(
+                e @ (steg_core::StegError::MissingShards { .. }
+                | steg_core::StegError::DuplicateShards(_)
+                | steg_core::StegError::ShardMismatch(_)),
+            ) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            result => result?,
+        }
+    };
 
-    match args.output {
-        Some(path) => {
-            std::fs::write(&path, &message_bytes)?;
-            eprintln!("Decoded message written to {:?}", path);
+    if is_container && !args.raw {
+        let container = steg_core::Container::from_bytes(&message_bytes)?;
+        if let Some(name) = &container.filename {
+            eprintln!("Filename: {}", name);
         }
-        None => {
-            // Try to print as UTF-8, fall back to hex dump
-            match String::from_utf8(message_bytes.clone()) {
-                Ok(text) => println!("{}", text),
-                Err(_) => {
-                    eprintln!("Message is not valid UTF-8, printing hex:");
-                    for byte in &message_bytes {
-                        print!("{:02x}", byte);
+        if let Some(mime_type) = &container.mime_type {
+            eprintln!("Type: {}", mime_type);
+        }
+        if let Some(created_at) = container.created_at {
+            eprintln!(
+                "Created: {}",
+                steg_core::clock::format_unix_time(created_at)
+            );
+        }
+        for (key, value) in &container.fields {
+            eprintln!("{}: {}", key, value);
+        }
+
+        let path = match (&args.output, container.safe_filename()) {
+            (Some(dir), name) if dir.is_dir() => Some(dir.join(name.unwrap_or(FALLBACK_FILENAME))),
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(name)) => Some(PathBuf::from(name)),
+            (None, None) => None,
+        };
+        match path {
+            Some(path) if args.output.as_ref() != Some(&path) => {
+                // The sender chose this name: never follow a link planted
+                // under it, and replace an existing file only with --force
+                let existing = std::fs::symlink_metadata(&path).ok();
+                if existing
+                    .as_ref()
+                    .is_some_and(|m| m.file_type().is_symlink())
+                {
+                    eprintln!(
+                        "Error: {:?} is a symbolic link; pass --output to choose another path",
+                        path
+                    );
+                    std::process::exit(1);
+                }
+                if existing.is_some() {
+                    if !args.force {
+                        eprintln!(
+                            "Error: {:?} already exists; pass --force to overwrite or --output to choose another path",
+                            path
+                        );
+                        std::process::exit(1);
                     }
-                    println!();
+                    std::fs::remove_file(&path)?;
                 }
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?
+                    .write_all(&container.data)?;
+                eprintln!("Decoded file written to {:?}", path);
+            }
+            Some(path) => {
+                std::fs::write(&path, &container.data)?;
+                eprintln!("Decoded file written to {:?}", path);
             }
+            None => print_message(&container.data),
         }
+        return Ok(());
+    }
+
+    match args.output {
+        Some(path) => {
+            std::fs::write(&path, &message_bytes)?;
+            eprintln!("Decoded message written to {:?}", path);
+        }
+        None => print_message(&message_bytes),
     }
 
     Ok(())
 }
+
+/// Print `message_bytes` as UTF-8 text if it is, otherwise as hex.
+fn print_message(message_bytes: &[u8]) {
+    // Try to print as UTF-8, fall back to hex dump
+    match std::str::from_utf8(message_bytes) {
+        Ok(text) => println!("{}", text),
+        Err(_) => {
+            eprintln!("Message is not valid UTF-8, printing hex:");
+            for byte in message_bytes {
+                print!("{:02x}", byte);
+            }
+            println!();
+        }
+    }
+}
@@ -1,25 +1,109 @@
+use clap::builder::PossibleValuesParser;
 use clap::Parser;
-use image::ImageFormat;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
-#[command(name = "steg-encode", about = "Encode a hidden message into a PNG image")]
+#[command(name = "steg-encode", about = "Encode a hidden message into an image")]
 struct Args {
-    /// Input PNG image path
-    #[arg(short, long)]
-    input: PathBuf,
+    /// Input image path (PNG, GIF, JPEG, BMP, TIFF, WebP or QOI, told apart
+    /// by content); several images split the message between them. Palette
+    /// images always use the palette scheme and JPEG images the F5 scheme
+    #[arg(short, long, num_args = 1.., required = true)]
+    input: Vec<PathBuf>,
 
-    /// Output PNG image path
-    #[arg(short, long)]
-    output: PathBuf,
+    /// Output image path, one per input; written in the input's format and
+    /// metadata, or as PNG for formats that cannot be written losslessly.
+    /// The extension must match, and each file is read back to check the
+    /// message survived
+    #[arg(short, long, num_args = 1.., required = true)]
+    output: Vec<PathBuf>,
 
     /// Message to encode (text)
     #[arg(short, long, conflicts_with = "message_file")]
     message: Option<String>,
 
-    /// File containing the message to encode
+    /// File containing the message to encode; its name and type are
+    /// embedded with it so steg-decode can restore the file
     #[arg(long, conflicts_with = "message")]
     message_file: Option<PathBuf>,
+
+    /// Embed the message bytes alone, without filename, type or metadata
+    #[arg(long, conflicts_with_all = ["mime_type", "meta"])]
+    raw: bool,
+
+    /// MIME type to record instead of the one guessed from the file name
+    #[arg(long)]
+    mime_type: Option<String>,
+
+    /// Extra metadata to embed with the message, as key=value (repeatable)
+    #[arg(long, value_parser = parse_meta)]
+    meta: Vec<(String, String)>,
+
+    /// Encrypt the message with this passphrase before embedding
+    #[arg(short, long, conflicts_with = "recipient")]
+    passphrase: Option<String>,
+
+    /// Seal the message to this public key (a steg-pk- key or a file holding
+    /// one) so that only the holder of the secret key can read it
+    #[arg(short, long)]
+    recipient: Option<String>,
+
+    /// Sign the message with the Ed25519 secret key in this file, so the
+    /// recipient can tell who wrote it
+    #[arg(long)]
+    sign: Option<PathBuf>,
+
+    /// Low-order bits to use in each R, G, B channel (1-4)
+    #[arg(short, long, default_value_t = 1)]
+    bits_per_channel: u8,
+
+    /// Use LSB matching (+-1 embedding) instead of LSB replacement
+    #[arg(long)]
+    lsb_matching: bool,
+
+    /// Embedding scheme to use
+    #[arg(
+        long,
+        default_value = "lsb",
+        value_parser = PossibleValuesParser::new(steg_core::embedder::SCHEMES.iter().copied())
+    )]
+    scheme: String,
+
+    /// Scatter the message over a key-driven ordering of the pixels
+    #[arg(short, long)]
+    key: Option<String>,
+
+    /// Add Reed-Solomon error correction with this much redundancy
+    #[arg(
+        long,
+        value_parser = PossibleValuesParser::new(steg_core::Redundancy::NAMES.iter().copied())
+    )]
+    fec: Option<String>,
+
+    /// Compress the message first when that makes it smaller
+    #[arg(long, conflicts_with = "threshold")]
+    compress: bool,
+
+    /// With several inputs, give every image a secret share instead of a
+    /// shard, so that any this many of them recover the message
+    #[arg(long)]
+    threshold: Option<u8>,
+
+    /// Refuse to decode the message before this time: a UTC date
+    /// (2026-10-18), RFC 3339 timestamp (2026-10-18T06:00:00Z) or Unix
+    /// seconds
+    #[arg(long, value_parser = parse_time)]
+    not_before: Option<u64>,
+
+    /// Refuse to decode the message from this time on, given like
+    /// --not-before
+    #[arg(long, value_parser = parse_time, conflicts_with = "expires_in")]
+    expires_at: Option<u64>,
+
+    /// Refuse to decode the message once this long has passed, e.g. 90m,
+    /// 12h or 7d
+    #[arg(long, value_parser = parse_expires_in)]
+    expires_in: Option<u64>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -33,18 +117,225 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             std::process::exit(1);
         }
     };
+    // Files travel in a container with their name and type; text messages
+    // only when metadata is given
+    let use_container = !args.raw
+        && (args.message_file.is_some() || args.mime_type.is_some() || !args.meta.is_empty());
+    let message_bytes = if use_container {
+        let mut container = steg_core::Container::new(message_bytes);
+        if let Some(name) = args
+            .message_file
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .and_then(|name| name.to_str())
+        {
+            container = container.with_filename(name);
+        }
+        if let Some(mime_type) = &args.mime_type {
+            container = container.with_mime_type(mime_type);
+        }
+        for (key, value) in &args.meta {
+            container = container.with_field(key, value);
+        }
+        container.to_bytes()
+    } else {
+        message_bytes
+    };
+
+    if args.input.len() != args.output.len() {
+        eprintln!(
+            "Error: {} input image(s) but {} output path(s)",
+            args.input.len(),
+            args.output.len()
+        );
+        std::process::exit(1);
+    }
+    if let Some(threshold) = args.threshold {
+        if args.input.len() < 2 {
+            eprintln!("Error: --threshold shares a message between at least two input images");
+            std::process::exit(1);
+        }
+        if threshold == 0 || threshold as usize > args.input.len() {
+            eprintln!(
+                "Error: --threshold must be between 1 and the number of input images ({})",
+                args.input.len()
+            );
+            std::process::exit(1);
+        }
+    }
+    let carriers = args
+        .input
+        .iter()
+        .map(steg_core::format::open_carrier)
+        .collect::<Result<Vec<_>, _>>()?;
+    for (carrier, output) in carriers.iter().zip(&args.output) {
+        carrier.check_output_path(output)?;
+    }
+    let mut config = steg_core::EmbedConfig::default().with_bits_per_channel(args.bits_per_channel);
+    if args.lsb_matching {
+        config = config.with_strategy(steg_core::EmbedStrategy::Match { seed: None });
+    }
+    if let Some(key) = &args.key {
+        config = config.with_key(key.as_bytes());
+    }
+    if let Some(fec) = args.fec.as_deref().and_then(steg_core::Redundancy::by_name) {
+        config = config.with_fec(fec);
+    }
+    config = config
+        .with_compression(args.compress)
+        .with_container(use_container);
+    if let Some(not_before) = args.not_before {
+        config = config.with_not_before(not_before);
+    }
+    let expires_at = args.expires_at.or(args.expires_in);
+    if let Some(expires_at) = expires_at {
+        eprintln!(
+            "Message expires at {}",
+            steg_core::clock::format_unix_time(expires_at)
+        );
+        config = config.with_expires_at(expires_at);
+    }
+    if let Some(path) = &args.sign {
+        let signer: steg_core::SignerSecret = std::fs::read_to_string(path)?.parse()?;
+        eprintln!("Signing as {}", signer.public_key());
+        config = config.with_signer(signer);
+    }
+    let any_native = carriers
+        .iter()
+        .any(|c| !matches!(c, steg_core::Carrier::Image(_)));
+    if carriers.len() > 1 && any_native {
+        eprintln!("Error: palette and JPEG images can only carry a message on their own");
+        std::process::exit(1);
+    }
+    let embedder = carriers[0].embedder(&args.scheme, config)?;
+    if embedder.name() != args.scheme {
+        let kind = match carriers[0] {
+            steg_core::Carrier::Jpeg(_) => "JPEG",
+            _ => "palette",
+        };
+        eprintln!(
+            "{:?} is a {} image; embedding with the {} scheme",
+            args.input[0],
+            kind,
+            embedder.name()
+        );
+    }
+    let images: Vec<_> = carriers.iter().map(|c| c.image().clone()).collect();
+    let passphrase = args.passphrase.as_deref().map(str::as_bytes);
+    let recipient: Option<steg_core::RecipientKey> = match &args.recipient {
+        Some(key) if key.starts_with(steg_core::recipient::PUBLIC_KEY_PREFIX) => Some(key.parse()?),
+        Some(path) => Some(std::fs::read_to_string(path)?.parse()?),
+        None => None,
+    };
+    if recipient.is_some() && carriers.len() > 1 {
+        eprintln!("Error: --recipient seals a message to a single image");
+        std::process::exit(1);
+    }
 
-    let img = image::open(&args.input)?;
-    let cap = steg_core::capacity(&img);
-    eprintln!(
-        "Image capacity: {} bytes, message size: {} bytes",
-        cap,
-        message_bytes.len()
-    );
+    let encoded = if images.len() == 1 {
+        let img = &images[0];
+        let cap = match (passphrase, &recipient) {
+            (Some(_), _) => embedder.capacity_encrypted(img),
+            (None, Some(_)) => embedder.capacity_sealed(img),
+            (None, None) => embedder.capacity(img),
+        };
+        eprintln!(
+            "Image capacity: {} bytes, message size: {} bytes",
+            cap,
+            message_bytes.len()
+        );
+        vec![match (passphrase, &recipient) {
+            (Some(passphrase), _) => embedder.embed_encrypted(img, &message_bytes, passphrase)?,
+            (None, Some(recipient)) => embedder.embed_sealed(img, &message_bytes, recipient)?,
+            (None, None) => embedder.embed(img, &message_bytes)?,
+        }]
+    } else if let Some(threshold) = args.threshold {
+        let cap = images
+            .iter()
+            .map(|img| {
+                steg_core::sharing::share_capacity(embedder.as_ref(), img, passphrase.is_some())
+            })
+            .min()
+            .unwrap_or(0);
+        eprintln!(
+            "Share capacity: {} bytes, message size: {} bytes, any {} of {} images recover it",
+            cap,
+            message_bytes.len(),
+            threshold,
+            images.len()
+        );
+        steg_core::sharing::embed_shares(
+            embedder.as_ref(),
+            &images,
+            &message_bytes,
+            threshold,
+            passphrase,
+        )?
+    } else {
+        let cap: usize = images
+            .iter()
+            .map(|img| {
+                steg_core::shard::shard_capacity(embedder.as_ref(), img, passphrase.is_some())
+            })
+            .sum();
+        eprintln!(
+            "Combined capacity of {} images: {} bytes, message size: {} bytes",
+            images.len(),
+            cap,
+            message_bytes.len()
+        );
+        steg_core::shard::split(embedder.as_ref(), &images, &message_bytes, passphrase)?
+    };
 
-    let encoded = steg_core::encode(&img, &message_bytes)?;
-    encoded.save_with_format(&args.output, ImageFormat::Png)?;
-    eprintln!("Message encoded successfully into {:?}", args.output);
+    for ((carrier, encoded), output) in carriers.iter().zip(encoded).zip(&args.output) {
+        let stats = steg_core::EmbedStats::between(carrier.image(), &encoded);
+        eprintln!(
+            "Changed {} of {} channel values ({:.2}%)",
+            stats.changed_values,
+            stats.total_values,
+            stats.change_rate() * 100.0
+        );
+        carrier.with_image(encoded)?.save(output)?;
+        eprintln!("Message encoded successfully into {:?}", output);
+    }
 
     Ok(())
 }
+
+fn parse_time(text: &str) -> Result<u64, String> {
+    steg_core::clock::parse_unix_time(text)
+        .ok_or_else(|| format!("expected a UTC date or timestamp, got {:?}", text))
+}
+
+/// The time, in Unix seconds, a duration such as `12h` from now.
+fn parse_expires_in(text: &str) -> Result<u64, String> {
+    parse_duration(text)?
+        .checked_add(steg_core::clock::unix_now())
+        .ok_or_else(|| format!("{:?} is too far in the future", text))
+}
+
+/// Seconds in a duration such as `45s`, `90m`, `12h` or `7d`.
+fn parse_duration(text: &str) -> Result<u64, String> {
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(digits);
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => return Err(format!("unknown unit {:?}; use s, m, h or d", unit)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("expected a duration such as 12h, got {:?}", text))
+}
+
+fn parse_meta(text: &str) -> Result<(String, String), String> {
+    text.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got {:?}", text))
+}
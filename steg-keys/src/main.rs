@@ -0,0 +1,256 @@
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use steg_core::recipient::{KEY_LEN, PUBLIC_KEY_PREFIX, SECRET_KEY_PREFIX};
+use steg_core::signature::{SIGNER_KEY_PREFIX, SIGNER_SECRET_PREFIX};
+use steg_core::{RecipientKey, RecipientSecret, SignerKey, SignerSecret};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "steg-keys",
+    about = "Manage X25519 recipient keys and Ed25519 signing keys"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a key pair, write the secret key and print the public key
+    Generate {
+        /// File to write the secret key to; keep it private
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Also write the public key to this file
+        #[arg(long)]
+        public_output: Option<PathBuf>,
+
+        /// Generate an Ed25519 key for signing messages instead of an X25519
+        /// key for receiving sealed ones
+        #[arg(long)]
+        signing: bool,
+
+        /// Overwrite an existing secret key file, and with it the only key
+        /// that opens messages sealed to it
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print the public key belonging to a secret key file
+    Export {
+        /// Secret key file written by `generate`
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Write the public key here instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Write the 32 raw key bytes instead of the text form
+        #[arg(long, requires = "output")]
+        raw: bool,
+    },
+
+    /// Check a key received from someone else, as text or 32 raw bytes, and
+    /// store it in text form
+    Import {
+        /// Key file, or the key text itself
+        #[arg(short, long)]
+        input: String,
+
+        /// File to write the key to
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Treat 32 raw bytes as a secret key rather than a public key
+        #[arg(long)]
+        secret: bool,
+
+        /// Treat 32 raw bytes as an Ed25519 signing key
+        #[arg(long)]
+        signing: bool,
+
+        /// Overwrite an existing secret key file
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Generate {
+            output,
+            public_output,
+            signing,
+            force,
+        } => {
+            let secret = if signing {
+                Secret::Signing(SignerSecret::generate())
+            } else {
+                Secret::Recipient(RecipientSecret::generate())
+            };
+            store_secret(&output, &secret, force)?;
+            if let Some(path) = public_output {
+                std::fs::write(&path, format!("{}\n", secret.public_text()))?;
+                eprintln!("Public key written to {:?}", path);
+            }
+        }
+        Command::Export { input, output, raw } => {
+            let secret = Secret::parse(&std::fs::read_to_string(&input)?)?;
+            match output {
+                Some(path) => {
+                    if raw {
+                        std::fs::write(&path, secret.public_bytes())?;
+                    } else {
+                        std::fs::write(&path, format!("{}\n", secret.public_text()))?;
+                    }
+                    eprintln!("Public key written to {:?}", path);
+                }
+                None => println!("{}", secret.public_text()),
+            }
+        }
+        Command::Import {
+            input,
+            output,
+            secret,
+            signing,
+            force,
+        } => {
+            let is_text = [
+                PUBLIC_KEY_PREFIX,
+                SECRET_KEY_PREFIX,
+                SIGNER_KEY_PREFIX,
+                SIGNER_SECRET_PREFIX,
+            ]
+            .iter()
+            .any(|prefix| input.starts_with(prefix));
+            let bytes = if is_text {
+                input.into_bytes()
+            } else {
+                std::fs::read(&input)?
+            };
+            if let Ok(key) = <[u8; KEY_LEN]>::try_from(bytes.as_slice()) {
+                match (secret, signing) {
+                    (true, false) => store_secret(
+                        &output,
+                        &Secret::Recipient(RecipientSecret::from_bytes(key)),
+                        force,
+                    )?,
+                    (true, true) => store_secret(
+                        &output,
+                        &Secret::Signing(SignerSecret::from_bytes(key)),
+                        force,
+                    )?,
+                    (false, false) => {
+                        store_public(&output, &RecipientKey::from_bytes(key).to_string())?
+                    }
+                    (false, true) => {
+                        store_public(&output, &SignerKey::from_bytes(key)?.to_string())?
+                    }
+                }
+            } else {
+                let text = String::from_utf8(bytes)
+                    .map_err(|_| format!("expected a key in text form or {} raw bytes", KEY_LEN))?;
+                let text = text.trim();
+                if text.starts_with(SECRET_KEY_PREFIX) || text.starts_with(SIGNER_SECRET_PREFIX) {
+                    store_secret(&output, &Secret::parse(text)?, force)?;
+                } else if text.starts_with(SIGNER_KEY_PREFIX) {
+                    store_public(&output, &text.parse::<SignerKey>()?.to_string())?;
+                } else {
+                    store_public(&output, &text.parse::<RecipientKey>()?.to_string())?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A secret key of either kind.
+enum Secret {
+    Recipient(RecipientSecret),
+    Signing(SignerSecret),
+}
+
+impl Secret {
+    /// Read a secret key in text form, telling the kinds apart by prefix.
+    fn parse(text: &str) -> Result<Self, steg_core::StegError> {
+        if text.trim_start().starts_with(SIGNER_SECRET_PREFIX) {
+            text.parse().map(Secret::Signing)
+        } else {
+            text.parse().map(Secret::Recipient)
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Secret::Recipient(secret) => secret.to_text(),
+            Secret::Signing(secret) => secret.to_text(),
+        }
+    }
+
+    fn public_text(&self) -> String {
+        match self {
+            Secret::Recipient(secret) => secret.public_key().to_string(),
+            Secret::Signing(secret) => secret.public_key().to_string(),
+        }
+    }
+
+    fn public_bytes(&self) -> [u8; KEY_LEN] {
+        match self {
+            Secret::Recipient(secret) => secret.public_key().to_bytes(),
+            Secret::Signing(secret) => secret.public_key().to_bytes(),
+        }
+    }
+}
+
+/// Write `secret` to `path`, replacing an existing file only if `force`, and
+/// print its public key.
+fn store_secret(path: &Path, secret: &Secret, force: bool) -> std::io::Result<()> {
+    write_private(path, &secret.to_text(), force)?;
+    eprintln!("Secret key written to {:?}", path);
+    println!("{}", secret.public_text());
+    Ok(())
+}
+
+/// Write the public key text `public` to `path` and print it.
+fn store_public(path: &Path, public: &str) -> std::io::Result<()> {
+    std::fs::write(path, format!("{}\n", public))?;
+    eprintln!("Public key written to {:?}", path);
+    println!("{}", public);
+    Ok(())
+}
+
+/// Write a secret key so that only the current user can read it. An existing
+/// file is refused unless `force`, and loses any wider permissions it had.
+fn write_private(path: &Path, text: &str, force: bool) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind, Write};
+
+    let mut options = std::fs::OpenOptions::new();
+    if force {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.write(true).create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{:?} already exists; pass --force to overwrite it", path),
+        ),
+        _ => e,
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    writeln!(file, "{}", text)
+}